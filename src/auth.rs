@@ -0,0 +1,115 @@
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, io};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::Connection;
+
+const ACCEPTED: u8 = 1;
+const REJECTED: u8 = 0;
+
+/// Shared-secret handshake performed when a connection is established.
+///
+/// The client sends the token as soon as it connects and the server replies with a single status
+/// byte. Connections that fail to present a matching token within the timeout are closed without
+/// being handed to the application.
+///
+/// Set the token on the server with [`Endpoint::set_token_auth`](crate::Endpoint::set_token_auth)
+/// and on the client with [`ConnectOptions::token_auth`](crate::ConnectOptions::token_auth).
+#[derive(Clone)]
+pub struct TokenAuth {
+    token: Arc<[u8]>,
+    timeout: Duration,
+}
+
+impl fmt::Debug for TokenAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenAuth")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TokenAuth {
+    /// Creates a new handshake using the given token.
+    pub fn new(token: impl AsRef<[u8]>) -> Self {
+        Self {
+            token: token.as_ref().into(),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Maximum amount of time to wait for the handshake to complete. Defaults to 5 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub(crate) async fn authenticate(&self, conn: Connection) -> io::Result<Connection> {
+        self.with_timeout(self.send_token(conn)).await
+    }
+
+    pub(crate) async fn verify(&self, conn: Connection) -> io::Result<Connection> {
+        self.with_timeout(self.check_token(conn)).await
+    }
+
+    async fn with_timeout(
+        &self,
+        handshake: impl std::future::Future<Output = io::Result<Connection>>,
+    ) -> io::Result<Connection> {
+        tokio::time::timeout(self.timeout, handshake)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "authentication timed out"))?
+    }
+
+    async fn send_token(&self, mut conn: Connection) -> io::Result<Connection> {
+        let len = u32::try_from(self.token.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "token is too long"))?;
+        conn.write_u32(len).await?;
+        conn.write_all(&self.token).await?;
+        conn.flush().await?;
+        match conn.read_u8().await? {
+            ACCEPTED => Ok(conn),
+            _ => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "authentication rejected",
+            )),
+        }
+    }
+
+    async fn check_token(&self, mut conn: Connection) -> io::Result<Connection> {
+        let len = conn.read_u32().await? as usize;
+        // Reject before reading the token so we never allocate based on an untrusted length
+        if len != self.token.len() {
+            trace!("rejecting connection with invalid token length");
+            conn.write_u8(REJECTED).await?;
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "invalid token",
+            ));
+        }
+        let mut received = vec![0; len];
+        conn.read_exact(&mut received).await?;
+        if constant_time_eq(&received, &self.token) {
+            conn.write_u8(ACCEPTED).await?;
+            conn.flush().await?;
+            Ok(conn)
+        } else {
+            trace!("rejecting connection with invalid token");
+            conn.write_u8(REJECTED).await?;
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "invalid token",
+            ))
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
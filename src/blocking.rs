@@ -0,0 +1,109 @@
+//! Synchronous IPC for programs that don't run an async runtime, such as CLI tools and build
+//! scripts.
+//!
+//! Servers are created with [`Endpoint::incoming_blocking`](crate::Endpoint::incoming_blocking)
+//! and clients with [`Endpoint::connect_blocking`](crate::Endpoint::connect_blocking). Both ends
+//! use the same wire format as the async API, so a blocking client can talk to an async server
+//! and vice versa.
+
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+
+use crate::pid_file::PidFile;
+use crate::platform;
+
+/// Blocking IPC connection.
+pub struct Connection {
+    inner: platform::BlockingConnection,
+}
+
+impl Connection {
+    pub(crate) fn new(inner: platform::BlockingConnection) -> Self {
+        Self { inner }
+    }
+
+    /// Creates a new handle to the same connection, which can be used to read and write from
+    /// different threads.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self::new(self.inner.try_clone()?))
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsFd for Connection {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for Connection {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsHandle for Connection {
+    fn as_handle(&self) -> std::os::windows::io::BorrowedHandle<'_> {
+        self.inner.as_handle()
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawHandle for Connection {
+    fn as_raw_handle(&self) -> std::os::windows::io::RawHandle {
+        self.inner.as_raw_handle()
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        self.inner.read_vectored(bufs)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.inner.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Blocking iterator of incoming connections, created by
+/// [`Endpoint::incoming_blocking`](crate::Endpoint::incoming_blocking).
+///
+/// Each call to [`Iterator::next`] blocks until a client connects. The iterator never ends on its
+/// own.
+pub struct Incoming {
+    inner: platform::BlockingIncoming,
+    // Declared after the listener so the pid file is removed once it's closed
+    _pid_file: Option<PidFile>,
+}
+
+impl Incoming {
+    pub(crate) fn new(inner: platform::BlockingIncoming, pid_file: Option<PidFile>) -> Self {
+        Self {
+            inner,
+            _pid_file: pid_file,
+        }
+    }
+}
+
+impl Iterator for Incoming {
+    type Item = io::Result<Connection>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|res| res.map(Connection::new))
+    }
+}
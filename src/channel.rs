@@ -0,0 +1,88 @@
+use std::io;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::{SinkExt, Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+
+use crate::{Connection, OwnedReadHalf, OwnedWriteHalf};
+
+/// Splits a [`Connection`] into a typed sender and receiver.
+///
+/// Values are serialized with [`bincode`] and sent as length-delimited messages. Both ends of the
+/// connection must agree on the types being sent, so the sender type on one end should match the
+/// receiver type on the other.
+pub fn channel<S, R>(connection: Connection) -> (IpcSender<S>, IpcReceiver<R>)
+where
+    S: Serialize,
+    R: DeserializeOwned,
+{
+    let (reader, writer) = connection.into_split();
+    (
+        IpcSender {
+            inner: FramedWrite::new(writer, LengthDelimitedCodec::new()),
+            _marker: PhantomData,
+        },
+        IpcReceiver {
+            inner: FramedRead::new(reader, LengthDelimitedCodec::new()),
+            _marker: PhantomData,
+        },
+    )
+}
+
+/// Sending half of a typed IPC channel, created by [`channel`].
+pub struct IpcSender<T> {
+    inner: FramedWrite<OwnedWriteHalf, LengthDelimitedCodec>,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T> IpcSender<T>
+where
+    T: Serialize,
+{
+    /// Serializes the value and sends it to the other end of the connection.
+    pub async fn send(&mut self, value: &T) -> io::Result<()> {
+        let bytes =
+            bincode::serialize(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.inner.send(Bytes::from(bytes)).await
+    }
+}
+
+/// Receiving half of a typed IPC channel, created by [`channel`].
+pub struct IpcReceiver<T> {
+    inner: FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> IpcReceiver<T>
+where
+    T: DeserializeOwned,
+{
+    /// Receives the next value from the other end of the connection.
+    ///
+    /// Returns `None` once the connection has been closed.
+    pub async fn recv(&mut self) -> Option<io::Result<T>> {
+        self.next().await
+    }
+}
+
+impl<T> Stream for IpcReceiver<T>
+where
+    T: DeserializeOwned,
+{
+    type Item = io::Result<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner).poll_next(cx).map(|message| {
+            message.map(|message| {
+                bincode::deserialize(&message?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            })
+        })
+    }
+}
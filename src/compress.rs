@@ -0,0 +1,244 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+
+use crate::framed::DEFAULT_MAX_FRAME_LENGTH;
+use crate::{Connection, MessageConnection};
+
+// Messages smaller than this are rarely worth the overhead of compressing
+const MIN_COMPRESS_LEN: usize = 64;
+
+const FLAG_RAW: u8 = 0;
+const FLAG_COMPRESSED: u8 = 1;
+
+/// Compression algorithm used by a [`CompressedConnection`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Messages are sent as is
+    None,
+    /// LZ4 block compression, which is very fast but compresses less
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Zstandard compression at the default level
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+    // Preferred algorithms come first
+    const ALL: &'static [Self] = &[
+        #[cfg(feature = "zstd")]
+        Self::Zstd,
+        #[cfg(feature = "lz4")]
+        Self::Lz4,
+        Self::None,
+    ];
+
+    fn id(self) -> u8 {
+        match self {
+            Self::None => 0,
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => 1,
+            #[cfg(feature = "zstd")]
+            Self::Zstd => 2,
+        }
+    }
+
+    fn compress(self, message: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Self::None => Ok(None),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => Ok(Some(lz4_flex::compress_prepend_size(message))),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd::bulk::compress(message, 0).map(Some),
+        }
+    }
+
+    fn decompress(self, message: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+        match self {
+            Self::None => Err(invalid_data("received a compressed message")),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => {
+                if message.len() < 4 {
+                    return Err(invalid_data("compressed message is truncated"));
+                }
+                let (size, message) = message.split_at(4);
+                // Check the size before allocating so a peer can't force a huge allocation
+                let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+                if size > max_size {
+                    return Err(too_large());
+                }
+                lz4_flex::decompress(message, size).map_err(invalid_data)
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => zstd_decompress(message, max_size),
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decompress(message: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    // Frames written by the sender include their size, so the output buffer can be allocated
+    // exactly. The bulk decoder fails if the frame turns out to be larger than it claims.
+    match zstd::zstd_safe::get_frame_content_size(message) {
+        Ok(Some(size)) if size > max_size as u64 => Err(too_large()),
+        Ok(Some(size)) => zstd::bulk::decompress(message, size as usize),
+        Ok(None) => {
+            // The size is unknown, so grow the buffer as needed and stop once it's over the limit
+            let mut decompressed = Vec::new();
+            zstd::stream::read::Decoder::with_buffer(message)?
+                .take(max_size as u64 + 1)
+                .read_to_end(&mut decompressed)?;
+            if decompressed.len() > max_size {
+                return Err(too_large());
+            }
+            Ok(decompressed)
+        }
+        Err(_) => Err(invalid_data("invalid zstd frame header")),
+    }
+}
+
+/// A [`MessageConnection`] that compresses each message.
+///
+/// Both ends announce the algorithms they support when the connection is created and use the
+/// best one they have in common, preferring Zstandard over LZ4. If they have none in common,
+/// messages are sent uncompressed, so peers built with different features can still talk to each
+/// other. Small messages, and messages that don't shrink when compressed, are always sent as is.
+/// Decompressed messages larger than the frame length limit (8 MiB by default) are rejected.
+pub struct CompressedConnection {
+    inner: MessageConnection,
+    compression: Compression,
+    max_message_size: usize,
+}
+
+impl CompressedConnection {
+    /// Wraps the connection and negotiates compression with the peer using the given
+    /// algorithms. [`Compression::None`] is always allowed, even if it isn't listed.
+    ///
+    /// Both ends must call this before sending any other data, since the negotiation is sent
+    /// as the first message in each direction.
+    pub async fn negotiate(connection: Connection, algorithms: &[Compression]) -> io::Result<Self> {
+        Self::negotiate_with_max_frame_length(connection, algorithms, DEFAULT_MAX_FRAME_LENGTH)
+            .await
+    }
+
+    /// Same as [`CompressedConnection::negotiate`], but rejects messages larger than
+    /// `max_frame_length` bytes, both before and after decompression. See
+    /// [`MessageConnection::with_max_frame_length`].
+    pub async fn negotiate_with_max_frame_length(
+        connection: Connection,
+        algorithms: &[Compression],
+        max_frame_length: usize,
+    ) -> io::Result<Self> {
+        let mut inner = MessageConnection::with_max_frame_length(connection, max_frame_length);
+        let offer: Vec<u8> = algorithms.iter().map(|a| a.id()).collect();
+        inner.send(Bytes::from(offer)).await?;
+        let peer = inner.next().await.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed during compression negotiation",
+            )
+        })??;
+        let compression = Compression::ALL
+            .iter()
+            .copied()
+            .find(|a| algorithms.contains(a) && peer.contains(&a.id()))
+            .unwrap_or(Compression::None);
+        trace!(?compression, "negotiated compression");
+        Ok(Self {
+            inner,
+            compression,
+            max_message_size: max_frame_length,
+        })
+    }
+
+    /// Algorithm that was agreed on with the peer.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Returns the underlying connection.
+    ///
+    /// Any buffered data that has not been read or written yet is lost.
+    pub fn into_inner(self) -> Connection {
+        self.inner.into_inner()
+    }
+
+    fn decode(&self, message: Bytes) -> io::Result<Bytes> {
+        match message.split_first() {
+            Some((&FLAG_RAW, _)) => Ok(message.slice(1..)),
+            Some((&FLAG_COMPRESSED, rest)) => self
+                .compression
+                .decompress(rest, self.max_message_size)
+                .map(Bytes::from),
+            _ => Err(invalid_data("invalid compressed message header")),
+        }
+    }
+
+    fn encode(&self, message: &[u8]) -> io::Result<Bytes> {
+        let compressed = if message.len() < MIN_COMPRESS_LEN {
+            None
+        } else {
+            self.compression
+                .compress(message)?
+                .filter(|compressed| compressed.len() < message.len())
+        };
+        let (flag, payload) = match &compressed {
+            Some(compressed) => (FLAG_COMPRESSED, compressed.as_slice()),
+            None => (FLAG_RAW, message),
+        };
+        let mut frame = BytesMut::with_capacity(payload.len() + 1);
+        frame.put_u8(flag);
+        frame.put_slice(payload);
+        Ok(frame.freeze())
+    }
+}
+
+impl Stream for CompressedConnection {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = Pin::into_inner(self);
+        this.inner
+            .poll_next_unpin(cx)
+            .map(|message| message.map(|message| this.decode(message?)))
+    }
+}
+
+impl Sink<Bytes> for CompressedConnection {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::into_inner(self).inner.poll_ready_unpin(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let this = Pin::into_inner(self);
+        let frame = this.encode(&item)?;
+        this.inner.start_send_unpin(frame)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::into_inner(self).inner.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::into_inner(self).inner.poll_close_unpin(cx)
+    }
+}
+
+fn too_large() -> io::Error {
+    invalid_data("decompressed message is too large")
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
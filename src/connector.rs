@@ -0,0 +1,51 @@
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tower_service::Service;
+
+use crate::{ConnectOptions, Connection, Endpoint, IntoIpcPath};
+
+/// A [`Service`] that opens a new [`Connection`] to a fixed endpoint each time it's called.
+///
+/// The request is ignored, so this can be used anywhere a connector for URIs or other targets is
+/// expected, such as tower's `MakeConnection`.
+#[derive(Clone, Debug)]
+pub struct IpcConnector {
+    path: PathBuf,
+    options: ConnectOptions,
+}
+
+impl IpcConnector {
+    /// Creates a connector for the given path using the default [`ConnectOptions`].
+    pub fn new(path: impl IntoIpcPath) -> io::Result<Self> {
+        Ok(Self {
+            path: path.into_ipc_path()?,
+            options: ConnectOptions::default(),
+        })
+    }
+
+    /// Sets the options used for each connection.
+    pub fn with_options(mut self, options: ConnectOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl<T> Service<T> for IpcConnector {
+    type Response = Connection;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Connection>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: T) -> Self::Future {
+        let path = self.path.clone();
+        let options = self.options.clone();
+        Box::pin(async move { Ok(Endpoint::connect_with_options(path, options).await?) })
+    }
+}
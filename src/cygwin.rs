@@ -0,0 +1,102 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::{Endpoint, IntoIpcPath};
+
+const SOCKET_COOKIE: &str = "!<socket >";
+
+impl Endpoint {
+    /// Connects to a Unix socket created by a Cygwin or MSYS2 program, such as `ssh-agent`
+    /// running under Git Bash.
+    ///
+    /// Cygwin emulates Unix sockets on Windows with a regular file that contains a loopback TCP
+    /// port and a secret key. This reads the file, connects to the port, and performs Cygwin's
+    /// handshake, which exchanges the key and the credentials of each side. `uid` and `gid` are
+    /// sent as the client's credentials and should match the output of `id -u` and `id -g` in
+    /// the Cygwin environment, since servers like `ssh-agent` reject clients owned by other users.
+    ///
+    /// The returned stream is a plain TCP stream once the handshake is done. Only stream sockets
+    /// are supported.
+    pub async fn connect_cygwin(
+        path: impl IntoIpcPath,
+        uid: u32,
+        gid: u32,
+    ) -> io::Result<TcpStream> {
+        let path = path.into_ipc_path()?;
+        let (port, key) = read_socket_file(&path)?;
+        let mut stream = TcpStream::connect(SocketAddr::from((Ipv4Addr::LOCALHOST, port))).await?;
+
+        stream.write_all(&key).await?;
+        let mut server_key = [0; 16];
+        stream.read_exact(&mut server_key).await?;
+        if server_key != key {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Cygwin socket server sent the wrong key",
+            ));
+        }
+
+        let mut credentials = [0; 12];
+        credentials[..4].copy_from_slice(&std::process::id().to_le_bytes());
+        credentials[4..8].copy_from_slice(&uid.to_le_bytes());
+        credentials[8..].copy_from_slice(&gid.to_le_bytes());
+        stream.write_all(&credentials).await?;
+        // The server's credentials aren't needed, but they have to be consumed
+        stream.read_exact(&mut credentials).await?;
+        debug!(?path, port, "connected to Cygwin socket");
+
+        Ok(stream)
+    }
+}
+
+// The file contains "!<socket >{port} s {key}" where the key is four 32-bit hex numbers separated
+// by dashes. "s" means SOCK_STREAM; datagram sockets use "d".
+fn read_socket_file(path: &Path) -> io::Result<(u16, [u8; 16])> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{path:?} is not a Cygwin socket file"),
+        )
+    };
+    let contents = std::fs::read(path)?;
+    let contents = std::str::from_utf8(&contents).map_err(|_| invalid())?;
+    let contents = contents
+        .trim_end_matches('\0')
+        .strip_prefix(SOCKET_COOKIE)
+        .ok_or_else(invalid)?;
+
+    let mut parts = contents.split_whitespace();
+    let port = parts
+        .next()
+        .and_then(|port| port.parse().ok())
+        .ok_or_else(invalid)?;
+    match parts.next() {
+        Some("s") => {}
+        Some("d") => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Cygwin datagram sockets aren't supported",
+            ))
+        }
+        _ => return Err(invalid()),
+    }
+
+    let mut key = [0; 16];
+    let mut words = parts.next().ok_or_else(invalid)?.split('-');
+    for chunk in key.chunks_exact_mut(4) {
+        let word = words
+            .next()
+            .and_then(|word| u32::from_str_radix(word, 16).ok())
+            .ok_or_else(invalid)?;
+        // Cygwin sends the key as it's laid out in memory, which is little-endian on Windows
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    if words.next().is_some() || parts.next().is_some() {
+        return Err(invalid());
+    }
+    Ok((port, key))
+}
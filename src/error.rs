@@ -0,0 +1,114 @@
+use std::path::{Path, PathBuf};
+use std::{fmt, io};
+
+/// Error from creating, accepting connections on, or connecting to an IPC endpoint.
+///
+/// Each variant carries the path of the endpoint along with the underlying I/O error, so the
+/// message says which endpoint failed rather than just "Permission denied". This converts into
+/// an [`io::Error`] with the same [`kind`](Error::kind), and the original error can be recovered
+/// from the converted error with [`Error::from_io`].
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to bind the endpoint and start listening
+    Bind {
+        /// Path of the endpoint
+        path: PathBuf,
+        /// Underlying error
+        source: io::Error,
+    },
+    /// Failed to accept a connection on a listening endpoint
+    Accept {
+        /// Path of the endpoint
+        path: PathBuf,
+        /// Underlying error
+        source: io::Error,
+    },
+    /// Failed to connect to an endpoint
+    Connect {
+        /// Path of the endpoint
+        path: PathBuf,
+        /// Underlying error
+        source: io::Error,
+    },
+}
+
+impl Error {
+    pub(crate) fn bind(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Self::Bind { path, source }
+    }
+
+    pub(crate) fn accept(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Self::Accept { path, source }
+    }
+
+    pub(crate) fn connect(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| Self::Connect { path, source }
+    }
+
+    /// Path of the endpoint that the operation failed on.
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Bind { path, .. } | Self::Accept { path, .. } | Self::Connect { path, .. } => {
+                path
+            }
+        }
+    }
+
+    /// The underlying I/O error.
+    pub fn io_error(&self) -> &io::Error {
+        match self {
+            Self::Bind { source, .. }
+            | Self::Accept { source, .. }
+            | Self::Connect { source, .. } => source,
+        }
+    }
+
+    /// Kind of the underlying I/O error.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.io_error().kind()
+    }
+
+    /// OS error code of the underlying I/O error, if there is one.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        self.io_error().raw_os_error()
+    }
+
+    /// Returns the crate error wrapped by an [`io::Error`], if there is one.
+    ///
+    /// Errors yielded by [`IpcStream`](crate::IpcStream) and errors converted with `?` in
+    /// functions that return [`io::Result`] can be inspected this way.
+    pub fn from_io(error: &io::Error) -> Option<&Self> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operation = match self {
+            Self::Bind { .. } => "bind",
+            Self::Accept { .. } => "accept a connection on",
+            Self::Connect { .. } => "connect to",
+        };
+        write!(
+            f,
+            "failed to {operation} {:?}: {}",
+            self.path(),
+            self.io_error()
+        )
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(self.io_error())
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        Self::new(error.kind(), error)
+    }
+}
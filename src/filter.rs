@@ -0,0 +1,121 @@
+#[cfg(windows)]
+use std::io;
+
+use crate::{platform, Connection};
+
+/// Restricts which peers are allowed to connect to an [`Endpoint`](crate::Endpoint).
+///
+/// A connection is accepted if its peer matches any of the configured rules. Connections from
+/// other peers, or from peers whose identity can't be determined, are closed before they are
+/// yielded from [`Endpoint::incoming`](crate::Endpoint::incoming). A filter without any rules
+/// rejects every connection.
+#[derive(Clone, Debug, Default)]
+pub struct PeerFilter {
+    #[cfg(unix)]
+    uids: Vec<u32>,
+    #[cfg(unix)]
+    gids: Vec<u32>,
+    #[cfg(windows)]
+    sids: Vec<String>,
+    #[cfg(windows)]
+    process_names: Vec<String>,
+    #[cfg(windows)]
+    sessions: Vec<u32>,
+}
+
+impl PeerFilter {
+    /// Creates a new filter that doesn't allow any peers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow peers running as the given user id.
+    #[cfg(unix)]
+    pub fn allow_uid(mut self, uid: u32) -> Self {
+        self.uids.push(uid);
+        self
+    }
+
+    /// Allow peers running with the given group id.
+    #[cfg(unix)]
+    pub fn allow_gid(mut self, gid: u32) -> Self {
+        self.gids.push(gid);
+        self
+    }
+
+    /// Allow peers running as the user with the given SID, e.g. `S-1-5-18`.
+    #[cfg(windows)]
+    pub fn allow_sid(mut self, sid: impl Into<String>) -> Self {
+        self.sids.push(sid.into());
+        self
+    }
+
+    /// Allow peers whose executable has the given file name, e.g. `app.exe`. The comparison is
+    /// case-insensitive.
+    #[cfg(windows)]
+    pub fn allow_process_name(mut self, process_name: impl Into<String>) -> Self {
+        self.process_names.push(process_name.into());
+        self
+    }
+
+    /// Allow peers running in the Terminal Services session with the given id.
+    #[cfg(windows)]
+    pub fn allow_session(mut self, session_id: u32) -> Self {
+        self.sessions.push(session_id);
+        self
+    }
+
+    /// Allow peers running in the same Terminal Services session as the current process. This
+    /// lets a per-user agent reject clients started from other users' sessions.
+    #[cfg(windows)]
+    pub fn allow_current_session(self) -> io::Result<Self> {
+        Ok(self.allow_session(platform::current_session_id()?))
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn allows(&self, conn: &Connection) -> bool {
+        match platform::peer_credentials(&conn.inner) {
+            Ok(creds) => {
+                creds.uid.is_some_and(|uid| self.uids.contains(&uid))
+                    || creds.gid.is_some_and(|gid| self.gids.contains(&gid))
+            }
+            Err(e) => {
+                trace!("unable to get peer credentials: {e:?}");
+                false
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    pub(crate) fn allows(&self, conn: &Connection) -> bool {
+        if !self.sids.is_empty() {
+            match platform::peer_sid(&conn.inner) {
+                Ok(sid) if self.sids.iter().any(|s| s.eq_ignore_ascii_case(&sid)) => return true,
+                Ok(_) => {}
+                Err(e) => trace!("unable to get peer SID: {e:?}"),
+            }
+        }
+        if !self.process_names.is_empty() {
+            match platform::peer_process_name(&conn.inner) {
+                Ok(name)
+                    if self
+                        .process_names
+                        .iter()
+                        .any(|n| n.eq_ignore_ascii_case(&name)) =>
+                {
+                    return true;
+                }
+                Ok(_) => {}
+                Err(e) => trace!("unable to get peer process name: {e:?}"),
+            }
+        }
+        if !self.sessions.is_empty() {
+            match platform::peer_session_id(&conn.inner) {
+                Ok(session_id) if self.sessions.contains(&session_id) => return true,
+                Ok(_) => {}
+                Err(e) => trace!("unable to get peer session id: {e:?}"),
+            }
+        }
+        false
+    }
+}
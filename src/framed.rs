@@ -0,0 +1,153 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::{fmt, io};
+
+use bytes::Bytes;
+use futures::{Sink, Stream};
+use tokio_util::codec::{Framed, LengthDelimitedCodec, LengthDelimitedCodecError};
+
+use crate::Connection;
+
+impl Connection {
+    /// Wraps the connection with the given codec, which turns the stream of bytes into a
+    /// `Stream` of decoded frames and a `Sink` of frames to encode.
+    ///
+    /// Common codecs, like [`LinesCodec`](crate::codec::LinesCodec), are available from
+    /// [`tipsy::codec`](crate::codec). Use [`MessageConnection`] for plain length-delimited
+    /// messages.
+    pub fn framed<C>(self, codec: C) -> Framed<Self, C> {
+        Framed::new(self, codec)
+    }
+}
+
+/// A [`Connection`] that sends and receives discrete messages instead of a stream of bytes.
+///
+/// Each message is prefixed with its length as a 4-byte big-endian integer. Messages larger than 8
+/// MiB are rejected by default, which can be changed with
+/// [`MessageConnection::with_max_frame_length`].
+pub struct MessageConnection {
+    inner: Framed<Connection, LengthDelimitedCodec>,
+    max_frame_length: usize,
+}
+
+impl MessageConnection {
+    /// Wraps the connection with length-delimited framing.
+    pub fn new(connection: Connection) -> Self {
+        Self::with_max_frame_length(connection, DEFAULT_MAX_FRAME_LENGTH)
+    }
+
+    /// Wraps the connection with length-delimited framing that rejects messages larger than
+    /// `max_frame_length` bytes.
+    ///
+    /// The limit is checked against the length prefix before any space is allocated for the
+    /// message, so a peer can't force large allocations by sending a forged length. Receiving or
+    /// sending a message over the limit fails with an error that wraps [`FrameTooLarge`]. The
+    /// stream ends after an oversized message is received, since the rest of the data can no
+    /// longer be parsed.
+    pub fn with_max_frame_length(connection: Connection, max_frame_length: usize) -> Self {
+        let codec = LengthDelimitedCodec::builder()
+            .max_frame_length(max_frame_length)
+            .new_codec();
+        Self {
+            inner: Framed::new(connection, codec),
+            max_frame_length,
+        }
+    }
+
+    fn map_err(&self, e: io::Error) -> io::Error {
+        if e.get_ref()
+            .is_some_and(|e| e.is::<LengthDelimitedCodecError>())
+        {
+            io::Error::new(
+                e.kind(),
+                FrameTooLarge {
+                    max_frame_length: self.max_frame_length,
+                },
+            )
+        } else {
+            e
+        }
+    }
+
+    /// Returns the underlying connection.
+    ///
+    /// Any buffered data that has not been read or written yet is lost.
+    pub fn into_inner(self) -> Connection {
+        self.inner.into_inner()
+    }
+}
+
+impl From<Connection> for MessageConnection {
+    fn from(connection: Connection) -> Self {
+        Self::new(connection)
+    }
+}
+
+impl Stream for MessageConnection {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner)
+            .poll_next(cx)
+            .map_ok(|message| message.freeze())
+            .map_err(|e| this.map_err(e))
+    }
+}
+
+impl Sink<Bytes> for MessageConnection {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        Sink::<Bytes>::poll_ready(Pin::new(&mut this.inner), cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner)
+            .start_send(item)
+            .map_err(|e| this.map_err(e))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        Sink::<Bytes>::poll_flush(Pin::new(&mut this.inner), cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        Sink::<Bytes>::poll_close(Pin::new(&mut this.inner), cx)
+    }
+}
+
+// Also the limit for the other message-based wrappers, so they all accept the same messages
+pub(crate) const DEFAULT_MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+/// Error returned when a message is larger than the limit set with
+/// [`MessageConnection::with_max_frame_length`].
+///
+/// This is wrapped in an [`io::Error`] and can be retrieved with [`io::Error::get_ref`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameTooLarge {
+    max_frame_length: usize,
+}
+
+impl FrameTooLarge {
+    /// The limit that the message exceeded.
+    pub fn max_frame_length(&self) -> usize {
+        self.max_frame_length
+    }
+}
+
+impl fmt::Display for FrameTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "message exceeds the maximum frame length of {} bytes",
+            self.max_frame_length
+        )
+    }
+}
+
+impl std::error::Error for FrameTooLarge {}
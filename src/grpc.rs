@@ -0,0 +1,53 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use hyper_util::rt::TokioIo;
+use tonic::transport::server::Connected;
+use tonic::transport::{Channel, Endpoint as TonicEndpoint, Uri};
+use tower_service::Service;
+
+use crate::{Connection, IpcConnector, PeerCredentials};
+
+// tonic doesn't use the URI when a custom connector is supplied, but it still requires a valid one
+const PLACEHOLDER_URI: &str = "http://localhost";
+
+impl Connected for Connection {
+    type ConnectInfo = Option<PeerCredentials>;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.peer_credentials().ok()
+    }
+}
+
+impl IpcConnector {
+    /// Connects to a gRPC server listening on the connector's endpoint.
+    ///
+    /// On the server side, [`IpcStream`](crate::IpcStream) can be passed directly to
+    /// [`Server::serve_with_incoming`](tonic::transport::Server::serve_with_incoming).
+    pub async fn connect_tonic(self) -> io::Result<Channel> {
+        TonicEndpoint::from_static(PLACEHOLDER_URI)
+            .connect_with_connector(TonicConnector(self))
+            .await
+            .map_err(io::Error::other)
+    }
+}
+
+#[derive(Clone)]
+struct TonicConnector(IpcConnector);
+
+impl Service<Uri> for TonicConnector {
+    type Response = TokioIo<Connection>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Service::<Uri>::poll_ready(&mut self.0, cx)
+    }
+
+    fn call(&mut self, req: Uri) -> Self::Future {
+        let connect = self.0.call(req);
+        Box::pin(async move { Ok(TokioIo::new(connect.await?)) })
+    }
+}
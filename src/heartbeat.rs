@@ -0,0 +1,190 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{Sink, Stream};
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use crate::Connection;
+
+const DATA: u8 = 0;
+const PING: u8 = 1;
+const PONG: u8 = 2;
+// Tokio's intervals can't have a zero period
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Heartbeat settings for a [`HeartbeatConnection`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    interval: Duration,
+    max_missed: u32,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            max_missed: 3,
+        }
+    }
+}
+
+impl Heartbeat {
+    /// New default heartbeat settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How often to send a ping to the peer. Defaults to 5 seconds. Intervals shorter than 1
+    /// millisecond are rounded up to 1 millisecond.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval.max(MIN_INTERVAL);
+        self
+    }
+
+    /// Number of consecutive pings that can go unanswered before the peer is considered
+    /// unresponsive. Defaults to 3.
+    pub fn max_missed(mut self, max_missed: u32) -> Self {
+        self.max_missed = max_missed;
+        self
+    }
+}
+
+/// A message-based [`Connection`] that periodically pings the peer to detect when it stops
+/// responding.
+///
+/// Messages are framed like [`MessageConnection`](crate::MessageConnection), with an extra
+/// leading byte used to distinguish application data from pings and pongs, so both ends of the
+/// connection must use a `HeartbeatConnection`.
+///
+/// Pings are sent and answered while the stream is being polled. Any frame received from the peer
+/// counts as a sign of life. If [`Heartbeat::max_missed`] pings in a row go unanswered, the stream
+/// yields an error of kind [`io::ErrorKind::TimedOut`].
+pub struct HeartbeatConnection {
+    inner: Framed<Connection, LengthDelimitedCodec>,
+    ticker: Interval,
+    max_missed: u32,
+    missed: u32,
+    send_ping: bool,
+    send_pong: bool,
+}
+
+impl HeartbeatConnection {
+    /// Wraps the connection with heartbeat framing.
+    pub fn new(connection: Connection, heartbeat: Heartbeat) -> Self {
+        let mut ticker =
+            tokio::time::interval_at(Instant::now() + heartbeat.interval, heartbeat.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            inner: Framed::new(connection, LengthDelimitedCodec::new()),
+            ticker,
+            max_missed: heartbeat.max_missed,
+            missed: 0,
+            send_ping: false,
+            send_pong: false,
+        }
+    }
+
+    /// Returns the underlying connection.
+    ///
+    /// Any buffered data that has not been read or written yet is lost.
+    pub fn into_inner(self) -> Connection {
+        self.inner.into_inner()
+    }
+
+    fn check_heartbeat(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        while self.ticker.poll_tick(cx).is_ready() {
+            if self.missed >= self.max_missed {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "peer is unresponsive",
+                ));
+            }
+            self.missed += 1;
+            self.send_ping = true;
+        }
+        Ok(())
+    }
+
+    fn poll_control_frames(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.send_pong {
+            ready!(Sink::<Bytes>::poll_ready(Pin::new(&mut self.inner), cx))?;
+            Pin::new(&mut self.inner).start_send(Bytes::from_static(&[PONG]))?;
+            self.send_pong = false;
+        }
+        if self.send_ping {
+            ready!(Sink::<Bytes>::poll_ready(Pin::new(&mut self.inner), cx))?;
+            Pin::new(&mut self.inner).start_send(Bytes::from_static(&[PING]))?;
+            self.send_ping = false;
+        }
+        Sink::<Bytes>::poll_flush(Pin::new(&mut self.inner), cx)
+    }
+}
+
+impl Stream for HeartbeatConnection {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = Pin::into_inner(self);
+        loop {
+            this.check_heartbeat(cx)?;
+            // Control frames are sent on a best-effort basis, a pending write shouldn't block
+            // reading
+            if let Poll::Ready(Err(e)) = this.poll_control_frames(cx) {
+                return Poll::Ready(Some(Err(e)));
+            }
+            let Some(frame) = ready!(Pin::new(&mut this.inner).poll_next(cx)?) else {
+                return Poll::Ready(None);
+            };
+            this.missed = 0;
+            let mut frame = frame.freeze();
+            if frame.is_empty() {
+                return Poll::Ready(Some(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "received empty heartbeat frame",
+                ))));
+            }
+            match frame.split_to(1)[0] {
+                DATA => return Poll::Ready(Some(Ok(frame))),
+                PING => this.send_pong = true,
+                PONG => {}
+                tag => {
+                    return Poll::Ready(Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("received unknown heartbeat frame type {tag}"),
+                    ))));
+                }
+            }
+        }
+    }
+}
+
+impl Sink<Bytes> for HeartbeatConnection {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        Sink::<Bytes>::poll_ready(Pin::new(&mut this.inner), cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let this = Pin::into_inner(self);
+        let mut frame = BytesMut::with_capacity(item.len() + 1);
+        frame.put_u8(DATA);
+        frame.put(item);
+        Pin::new(&mut this.inner).start_send(frame.freeze())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        Sink::<Bytes>::poll_flush(Pin::new(&mut this.inner), cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        Sink::<Bytes>::poll_close(Pin::new(&mut this.inner), cx)
+    }
+}
@@ -0,0 +1,142 @@
+#[cfg(feature = "axum")]
+use std::future::{Future, IntoFuture};
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[cfg(feature = "axum")]
+use futures::future::{self, BoxFuture, Either};
+use futures::{Stream, StreamExt};
+#[cfg(feature = "axum")]
+use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::TokioIo;
+#[cfg(feature = "axum")]
+use hyper_util::server::conn::auto;
+#[cfg(feature = "axum")]
+use hyper_util::server::graceful::GracefulShutdown;
+#[cfg(feature = "axum")]
+use hyper_util::service::TowerToHyperService;
+
+#[cfg(feature = "axum")]
+use crate::serve::wait_after_accept_error;
+use crate::{Connection, Endpoint, IpcStream, ShutdownHandle};
+
+impl Endpoint {
+    /// Stream of incoming connections that can be served by hyper, for serving HTTP over a Unix
+    /// socket or named pipe.
+    ///
+    /// hyper 1.0 no longer has a server that drives an accept loop, so each connection should be
+    /// passed to a connection builder such as
+    /// [`hyper::server::conn::http1::Builder::serve_connection`](https://docs.rs/hyper/1/hyper/server/conn/http1/struct.Builder.html#method.serve_connection),
+    /// usually from a spawned task.
+    pub fn incoming_hyper(self) -> io::Result<HyperIncoming> {
+        Ok(HyperIncoming(self.incoming()?))
+    }
+}
+
+/// Stream of incoming connections wrapped in [`TokioIo`] so they implement hyper's I/O traits,
+/// created by [`Endpoint::incoming_hyper`].
+pub struct HyperIncoming(IpcStream);
+
+impl HyperIncoming {
+    /// Returns a handle that can be used to stop accepting connections from another task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.0.shutdown_handle()
+    }
+}
+
+impl Stream for HyperIncoming {
+    type Item = io::Result<TokioIo<Connection>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .0
+            .poll_next_unpin(cx)
+            .map(|conn| conn.map(|conn| conn.map(TokioIo::new)))
+    }
+}
+
+/// Serves an [`axum::Router`] on the endpoint, handling each connection in its own task.
+///
+/// The returned value must be awaited to start the server. It runs until the endpoint stops
+/// accepting connections or the signal passed to [`ServeAxum::with_graceful_shutdown`]
+/// completes, and then waits for in-flight connections to finish. Errors accepting individual
+/// connections are logged and skipped rather than stopping the server, after a short delay so
+/// persistent errors don't keep the CPU busy.
+#[cfg(feature = "axum")]
+pub fn serve_axum(endpoint: Endpoint, router: axum::Router) -> ServeAxum {
+    ServeAxum {
+        endpoint,
+        router,
+        signal: None,
+    }
+}
+
+/// Future that serves an [`axum::Router`], created by [`serve_axum`].
+#[cfg(feature = "axum")]
+pub struct ServeAxum {
+    endpoint: Endpoint,
+    router: axum::Router,
+    signal: Option<BoxFuture<'static, ()>>,
+}
+
+#[cfg(feature = "axum")]
+impl ServeAxum {
+    /// Stop accepting connections once the given future completes.
+    ///
+    /// Connections that are already open are asked to finish their current request and close,
+    /// and the server resolves once all of them are closed.
+    pub fn with_graceful_shutdown<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.signal = Some(Box::pin(signal));
+        self
+    }
+
+    async fn run(self) -> io::Result<()> {
+        let mut incoming = self.endpoint.incoming_hyper()?;
+        let mut signal = self.signal.unwrap_or_else(|| Box::pin(future::pending()));
+        let builder = auto::Builder::new(TokioExecutor::new());
+        let graceful = GracefulShutdown::new();
+        loop {
+            let io = match future::select(incoming.next(), &mut signal).await {
+                Either::Left((Some(Ok(io)), _)) => io,
+                Either::Left((Some(Err(e)), _)) => {
+                    debug!("error accepting connection: {e:?}");
+                    if !wait_after_accept_error(&mut signal).await {
+                        debug!("shutdown signal received");
+                        incoming.shutdown_handle().shutdown();
+                        break;
+                    }
+                    continue;
+                }
+                Either::Left((None, _)) => break,
+                Either::Right(_) => {
+                    debug!("shutdown signal received");
+                    incoming.shutdown_handle().shutdown();
+                    break;
+                }
+            };
+            let service = TowerToHyperService::new(self.router.clone());
+            let conn = graceful.watch(builder.serve_connection(io, service).into_owned());
+            tokio::spawn(async move {
+                if let Err(e) = conn.await {
+                    debug!("error serving connection: {e:?}");
+                }
+            });
+        }
+        graceful.shutdown().await;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "axum")]
+impl IntoFuture for ServeAxum {
+    type Output = io::Result<()>;
+    type IntoFuture = BoxFuture<'static, io::Result<()>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.run())
+    }
+}
@@ -0,0 +1,86 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::time::{Instant, Sleep};
+
+/// Tracks how long a connection has gone without any reads or writes. The last activity time is
+/// shared between the halves of a split connection so that activity on either half keeps both
+/// alive.
+pub(crate) struct IdleTimer {
+    timeout: Duration,
+    last_activity: Arc<Mutex<Instant>>,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl IdleTimer {
+    pub(crate) fn new(timeout: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            timeout,
+            last_activity: Arc::new(Mutex::new(now)),
+            sleep: Box::pin(tokio::time::sleep_until(now + timeout)),
+        }
+    }
+
+    pub(crate) fn split(&self) -> Self {
+        Self {
+            timeout: self.timeout,
+            last_activity: self.last_activity.clone(),
+            sleep: Box::pin(tokio::time::sleep_until(self.deadline())),
+        }
+    }
+
+    fn deadline(&self) -> Instant {
+        *self
+            .last_activity
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            + self.timeout
+    }
+
+    pub(crate) fn record_activity(&self) {
+        *self
+            .last_activity
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Instant::now();
+    }
+
+    /// Checks the result of an I/O operation, returning an error in place of `Poll::Pending` once
+    /// the connection has been idle for too long.
+    pub(crate) fn check<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        res: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        match res {
+            Poll::Pending => {
+                ready!(self.poll_elapsed(cx));
+                Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "connection was idle for too long",
+                )))
+            }
+            Poll::Ready(Ok(val)) => {
+                self.record_activity();
+                Poll::Ready(Ok(val))
+            }
+            res => res,
+        }
+    }
+
+    fn poll_elapsed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            ready!(self.sleep.as_mut().poll(cx));
+            // Activity since the timer was last set pushes the deadline back
+            let deadline = self.deadline();
+            if deadline <= Instant::now() {
+                return Poll::Ready(());
+            }
+            self.sleep.as_mut().reset(deadline);
+        }
+    }
+}
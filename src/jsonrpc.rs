@@ -0,0 +1,167 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, BufMut, BytesMut};
+use futures::{Sink, Stream};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+use crate::framed::DEFAULT_MAX_FRAME_LENGTH;
+use crate::Connection;
+
+/// A [`Connection`] that sends and receives JSON-RPC messages.
+///
+/// Incoming messages are split on the boundaries of top-level JSON objects and arrays, so peers
+/// don't need to agree on a delimiter. This is the framing used by most JSON-RPC IPC
+/// implementations, including Ethereum clients. Outgoing messages are followed by a newline so
+/// that peers which split on newlines can read them too.
+///
+/// Messages are passed through as strings so they can be handed to any JSON-RPC implementation.
+/// Incoming messages are checked for matching brackets and valid UTF-8, but aren't otherwise
+/// validated. Messages larger than 8 MiB are rejected.
+pub struct JsonRpcConnection {
+    inner: Framed<Connection, JsonCodec>,
+}
+
+impl JsonRpcConnection {
+    /// Wraps the connection with JSON-RPC framing.
+    pub fn new(connection: Connection) -> Self {
+        Self {
+            inner: Framed::new(connection, JsonCodec::default()),
+        }
+    }
+
+    /// Returns the underlying connection.
+    ///
+    /// Any buffered data that has not been read or written yet is lost.
+    pub fn into_inner(self) -> Connection {
+        self.inner.into_inner()
+    }
+}
+
+impl From<Connection> for JsonRpcConnection {
+    fn from(connection: Connection) -> Self {
+        Self::new(connection)
+    }
+}
+
+impl Stream for JsonRpcConnection {
+    type Item = io::Result<String>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner).poll_next(cx)
+    }
+}
+
+impl Sink<String> for JsonRpcConnection {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        Sink::<String>::poll_ready(Pin::new(&mut this.inner), cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: String) -> Result<(), Self::Error> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner).start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        Sink::<String>::poll_flush(Pin::new(&mut this.inner), cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = Pin::into_inner(self);
+        Sink::<String>::poll_close(Pin::new(&mut this.inner), cx)
+    }
+}
+
+/// Splits a byte stream into top-level JSON values by tracking open brackets, ignoring brackets
+/// inside strings.
+#[derive(Default)]
+struct JsonCodec {
+    // Number of bytes of the current message that have already been scanned
+    scanned: usize,
+    // Closing bracket expected for each bracket that's still open, innermost last
+    closing: Vec<u8>,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Decoder for JsonCodec {
+    type Item = String;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<String>> {
+        if self.closing.is_empty() {
+            let whitespace = src.iter().take_while(|b| b.is_ascii_whitespace()).count();
+            src.advance(whitespace);
+        }
+        for i in self.scanned..src.len() {
+            let byte = src[i];
+            if self.closing.is_empty() {
+                match byte {
+                    b'{' => self.closing.push(b'}'),
+                    b'[' => self.closing.push(b']'),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "expected a JSON object or array",
+                        ));
+                    }
+                }
+            } else if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                }
+            } else {
+                match byte {
+                    b'"' => self.in_string = true,
+                    b'{' => self.closing.push(b'}'),
+                    b'[' => self.closing.push(b']'),
+                    b'}' | b']' => {
+                        if self.closing.pop() != Some(byte) {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "mismatched brackets in JSON message",
+                            ));
+                        }
+                        if self.closing.is_empty() {
+                            self.scanned = 0;
+                            let message = src.split_to(i + 1);
+                            return String::from_utf8(message.to_vec())
+                                .map(Some)
+                                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.scanned = src.len();
+        if self.scanned > DEFAULT_MAX_FRAME_LENGTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "JSON-RPC message is too large",
+            ));
+        }
+        Ok(None)
+    }
+}
+
+impl Encoder<String> for JsonCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: String, dst: &mut BytesMut) -> io::Result<()> {
+        dst.reserve(item.len() + 1);
+        dst.put_slice(item.as_bytes());
+        dst.put_u8(b'\n');
+        Ok(())
+    }
+}
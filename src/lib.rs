@@ -8,32 +8,144 @@
 #![warn(clippy::doc_markdown)]
 #![warn(clippy::default_trait_access)]
 #![warn(clippy::ignored_unit_patterns)]
-#![warn(clippy::semicolon_if_nothing_returned)]
 #![warn(clippy::missing_fields_in_debug)]
 #![warn(clippy::use_self)]
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
 #![doc = include_str!("../README.md")]
 
+#[macro_use]
+mod macros;
+
+mod auth;
+pub mod blocking;
+#[cfg(feature = "channel")]
+mod channel;
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+mod compress;
+#[cfg(feature = "tower")]
+mod connector;
+mod cygwin;
+mod error;
+mod filter;
+#[cfg(feature = "tokio-util")]
+mod framed;
+#[cfg(feature = "tonic")]
+mod grpc;
+#[cfg(feature = "tokio-util")]
+mod heartbeat;
+#[cfg(feature = "hyper")]
+mod http;
+mod idle;
+#[cfg(feature = "jsonrpc")]
+mod jsonrpc;
+mod listener;
+mod metrics;
+pub mod mock;
+#[cfg(feature = "mux")]
+mod mux;
+pub mod os;
+mod pid_file;
+mod pool;
+#[cfg(feature = "pubsub")]
+mod pubsub;
+mod rate_limit;
+mod reconnect;
+#[cfg(feature = "rpc")]
+mod rpc;
+mod serve;
+mod stats;
+mod tcp;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tokio-serde")]
+mod typed;
 #[cfg(not(windows))]
 mod unix;
 #[cfg(windows)]
 mod win;
 
-use std::io;
+use std::ffi::OsString;
+use std::io::{self, IoSlice};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
 
-use futures::Stream;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::task::AtomicWaker;
+use futures::{Stream, StreamExt};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+#[cfg(feature = "tls")]
+pub use tokio_rustls::rustls;
+#[cfg(feature = "tokio-serde")]
+pub use tokio_serde;
+#[cfg(feature = "tokio-util")]
+pub use tokio_util::codec;
+
+pub use crate::auth::TokenAuth;
+#[cfg(feature = "channel")]
+pub use crate::channel::{channel, IpcReceiver, IpcSender};
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+pub use crate::compress::{CompressedConnection, Compression};
+#[cfg(feature = "tower")]
+pub use crate::connector::IpcConnector;
+pub use crate::error::Error;
+pub use crate::filter::PeerFilter;
+#[cfg(feature = "tokio-util")]
+pub use crate::framed::{FrameTooLarge, MessageConnection};
+#[cfg(feature = "tokio-util")]
+pub use crate::heartbeat::{Heartbeat, HeartbeatConnection};
+#[cfg(feature = "hyper")]
+pub use crate::http::HyperIncoming;
+#[cfg(feature = "axum")]
+pub use crate::http::{serve_axum, ServeAxum};
+use crate::idle::IdleTimer;
+#[cfg(feature = "jsonrpc")]
+pub use crate::jsonrpc::JsonRpcConnection;
+pub use crate::listener::IpcListener;
+pub use crate::metrics::IpcMetrics;
+use crate::metrics::{ConnectionMetrics, MetricsHandle};
+#[cfg(feature = "mux")]
+pub use crate::mux::{Multiplexer, MuxControl, MuxMode, MuxStream};
+use crate::pid_file::PidFile;
+pub use crate::pool::{IpcPool, PooledConnection};
+#[cfg(feature = "pubsub")]
+pub use crate::pubsub::{LagPolicy, PubSubEvent, Publisher, Subscriber};
+pub use crate::rate_limit::RateLimit;
+use crate::rate_limit::TokenBucket;
+pub use crate::reconnect::{ConnectionState, ReconnectPolicy, ReconnectingConnection};
+#[cfg(feature = "rpc")]
+pub use crate::rpc::{serve_rpc, RpcClient, RpcHandler};
+pub use crate::serve::Serve;
+pub use crate::stats::ConnectionStats;
+use crate::stats::StatsTracker;
+pub use crate::tcp::{DualConnection, DualIncoming};
+#[cfg(feature = "tls")]
+pub use crate::tls::SecureConnection;
+#[cfg(feature = "tokio-serde")]
+pub use crate::typed::SerdeConnection;
 
 mod platform {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) use crate::unix::{autobind, connect_abstract, peer_security_label, SplicePipe};
     #[cfg(unix)]
     pub(crate) use crate::unix::{
-        from_std_stream, Connection, Endpoint, IpcStream, SecurityAttributes,
+        current_user_id, default_path, dir_path, from_std_stream, into_split, peek, peer_addr,
+        peer_credentials, peer_process, probe, recv_handle, send_handle, validate_path,
+        BlockingConnection, BlockingIncoming, Cleanup, Connection, Endpoint, IpcStream,
+        OwnedReadHalf, OwnedWriteHalf, SecurityAttributes,
     };
     #[cfg(windows)]
-    pub(crate) use crate::win::{Connection, Endpoint, IpcStream, SecurityAttributes};
+    pub(crate) use crate::win::{
+        current_session_id, current_user_id, default_path, dir_path, impersonate, into_split, peek,
+        peer_addr, peer_credentials, peer_process, peer_process_name, peer_session_id, peer_sid,
+        pipe_info, probe, recv_handle, revert_to_self, send_handle, validate_path,
+        BlockingConnection, BlockingIncoming, Cleanup, Connection, Endpoint, IpcStream,
+        OwnedReadHalf, OwnedWriteHalf, SecurityAttributes,
+    };
 }
 
 /// Path used for an IPC client or server.
@@ -48,6 +160,24 @@ impl IntoIpcPath for PathBuf {
     }
 }
 
+impl IntoIpcPath for &Path {
+    fn into_ipc_path(self) -> io::Result<PathBuf> {
+        Ok(self.to_path_buf())
+    }
+}
+
+impl IntoIpcPath for &str {
+    fn into_ipc_path(self) -> io::Result<PathBuf> {
+        Ok(PathBuf::from(self))
+    }
+}
+
+impl IntoIpcPath for OsString {
+    fn into_ipc_path(self) -> io::Result<PathBuf> {
+        Ok(PathBuf::from(self))
+    }
+}
+
 /// How to proceed when the socket path already exists
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum OnConflict {
@@ -57,6 +187,47 @@ pub enum OnConflict {
     Error,
     /// Overwrite the existing socket
     Overwrite,
+    /// Overwrite the existing socket only if no server is listening on it, otherwise throw an
+    /// error when attempting to bind to the path.
+    ///
+    /// This is useful for cleaning up socket files left behind by processes that exited
+    /// unexpectedly. Named pipes are removed automatically when the server exits, so this behaves
+    /// the same as [`OnConflict::Ignore`] on Windows.
+    OverwriteIfStale,
+    /// Atomically replace the existing socket with a new one.
+    ///
+    /// The socket is bound to a temporary path and renamed over the existing one, so clients
+    /// always find either the old server or the new one instead of a missing or dead socket.
+    /// This is useful for restarting a server without downtime. The old server doesn't remove the
+    /// new socket when it shuts down. Named pipes don't need to be replaced since several servers
+    /// can listen on the same name at once, so this behaves the same as [`OnConflict::Ignore`] on
+    /// Windows.
+    Replace,
+}
+
+/// Whether a server is listening on an endpoint, returned from [`Endpoint::probe`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EndpointStatus {
+    /// Nothing exists at the path
+    NotFound,
+    /// A socket file exists at the path, but no server is accepting connections on it. This
+    /// usually means the server exited without cleaning up. Named pipes are removed as soon as
+    /// the server exits, so this is never returned on Windows.
+    Stale,
+    /// A server is accepting connections
+    Listening,
+}
+
+/// Which way data can flow over connections to an endpoint, from the server's point of view.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Direction {
+    /// Both sides can read and write
+    #[default]
+    Duplex,
+    /// Only the client can write and only the server can read
+    Inbound,
+    /// Only the server can write and only the client can read
+    Outbound,
 }
 
 /// Cross-platform representation of an IPC connection path
@@ -70,6 +241,8 @@ pub enum OnConflict {
 /// not exist)
 ///
 /// Linux: `$XDG_RUNTIME_DIR/{serverId}`
+///
+/// On Unix, setting the [`RUNTIME_DIR_ENV`] environment variable overrides the directory.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ServerId<T>(pub T)
 where
@@ -80,11 +253,263 @@ where
     T: Into<String> + Send,
 {
     fn into_ipc_path(self) -> io::Result<PathBuf> {
-        self.into_ipc_path()
+        DefaultResolver.resolve(&self.0.into())
+    }
+}
+
+impl<T> ServerId<T>
+where
+    T: Into<String> + Send,
+{
+    /// Uses a custom strategy to generate the path instead of the platform default.
+    pub fn resolve_with<R: PathResolver>(self, resolver: R) -> ResolvedPath<R> {
+        ResolvedPath::new(self.0.into(), resolver)
+    }
+}
+
+/// Structured IPC connection path made up of an application name, a purpose, and an optional
+/// instance
+///
+/// Like [`ServerId`], this generates a platform-specific path when calling
+/// [`IntoIpcPath::into_ipc_path`]. The components are joined with `.` and any character other
+/// than ASCII letters, digits, `-` and `_` is percent-encoded, so different combinations of
+/// components never render to the same path. Namespacing endpoints by application prevents two
+/// programs that use the same generic purpose, such as `"control"`, from colliding.
+///
+/// Pipe names are case-insensitive on Windows, so components that only differ by case will
+/// refer to the same pipe there.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ServiceId {
+    app: String,
+    purpose: String,
+    instance: Option<String>,
+}
+
+impl ServiceId {
+    /// New id for the given application and purpose.
+    pub fn new(app: impl Into<String>, purpose: impl Into<String>) -> Self {
+        Self {
+            app: app.into(),
+            purpose: purpose.into(),
+            instance: None,
+        }
+    }
+
+    /// Distinguishes between multiple endpoints with the same application and purpose, such as
+    /// one per user session or per process.
+    pub fn instance(mut self, instance: impl Into<String>) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    /// Scopes the endpoint to the given user by using `user-{user_id}` as the instance.
+    ///
+    /// This lets a service find the endpoint of a per-user agent, given the id that the agent
+    /// uses with [`ServiceId::per_user`]. Ids without an instance aren't scoped to anything, so
+    /// they work as global names for endpoints shared by all users, such as one owned by a system
+    /// service.
+    pub fn for_user(self, user_id: impl std::fmt::Display) -> Self {
+        self.instance(format!("user-{user_id}"))
+    }
+
+    /// Scopes the endpoint to the user running the current process, so agents run by different
+    /// users on the same machine don't collide. The user is identified by their SID on Windows
+    /// and their effective uid on Unix. See [`ServiceId::for_user`].
+    pub fn per_user(self) -> io::Result<Self> {
+        Ok(self.for_user(platform::current_user_id()?))
+    }
+
+    /// Scopes the endpoint to the given Terminal Services session by using `session-{session_id}`
+    /// as the instance.
+    ///
+    /// The session id of a client can be retrieved with [`Connection::peer_session_id`].
+    #[cfg(windows)]
+    pub fn for_session(self, session_id: u32) -> Self {
+        self.instance(format!("session-{session_id}"))
+    }
+
+    /// Scopes the endpoint to the Terminal Services session of the current process. Each
+    /// interactive logon gets its own session, so this keeps agents in different sessions from
+    /// colliding, even when they run as the same user. See [`ServiceId::for_session`].
+    #[cfg(windows)]
+    pub fn per_session(self) -> io::Result<Self> {
+        Ok(self.for_session(platform::current_session_id()?))
+    }
+
+    /// Name that's used in place of the server id when generating the path.
+    pub fn name(&self) -> String {
+        let mut name = format!(
+            "{}.{}",
+            encode_component(&self.app),
+            encode_component(&self.purpose)
+        );
+        if let Some(instance) = &self.instance {
+            name.push('.');
+            name.push_str(&encode_component(instance));
+        }
+        name
+    }
+}
+
+impl IntoIpcPath for ServiceId {
+    fn into_ipc_path(self) -> io::Result<PathBuf> {
+        DefaultResolver.resolve(&self.name())
+    }
+}
+
+impl ServiceId {
+    /// Uses a custom strategy to generate the path instead of the platform default.
+    pub fn resolve_with<R: PathResolver>(self, resolver: R) -> ResolvedPath<R> {
+        ResolvedPath::new(self.name(), resolver)
+    }
+}
+
+/// Strategy for turning an endpoint name into a platform-specific IPC path.
+///
+/// [`ServerId`] and [`ServiceId`] use [`DefaultResolver`] unless another one is supplied with
+/// `resolve_with`. This is implemented for closures that take the name and return the path, so
+/// applications can put endpoints in per-service directories or test sandboxes without
+/// reimplementing the platform-specific parts.
+pub trait PathResolver: Send {
+    /// Returns the path for the endpoint with the given name.
+    fn resolve(&self, name: &str) -> io::Result<PathBuf>;
+}
+
+impl<F> PathResolver for F
+where
+    F: Fn(&str) -> io::Result<PathBuf> + Send,
+{
+    fn resolve(&self, name: &str) -> io::Result<PathBuf> {
+        self(name)
+    }
+}
+
+/// Environment variable that overrides the directory used by [`DefaultResolver`] on Unix.
+pub const RUNTIME_DIR_ENV: &str = "TIPSY_RUNTIME_DIR";
+
+/// Resolves names to the platform's default location.
+///
+/// Windows: `\\.\pipe\{name}`
+///
+/// Mac: `$HOME/Library/Caches/TemporaryItems/{name}.sock` (defaults to tmp if this directory does
+/// not exist)
+///
+/// Linux: `$XDG_RUNTIME_DIR/{name}.sock` (defaults to tmp if the variable isn't set)
+///
+/// On Unix, if the [`RUNTIME_DIR_ENV`] environment variable is set to a non-empty value, sockets
+/// are placed in that directory instead, which is created if it doesn't exist. This lets
+/// deployments and tests redirect where sockets are created without changing any code. Clients
+/// and servers must see the same value to agree on the path.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultResolver;
+
+impl PathResolver for DefaultResolver {
+    fn resolve(&self, name: &str) -> io::Result<PathBuf> {
+        platform::default_path(name)
+    }
+}
+
+/// Resolves names to sockets in the given directory, which is created if it doesn't exist.
+///
+/// Named pipes don't live in the file system, so the directory is ignored on Windows and
+/// names resolve to the same paths as [`DefaultResolver`].
+#[derive(Clone, Debug)]
+pub struct DirResolver(PathBuf);
+
+impl DirResolver {
+    /// New resolver that places sockets in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self(dir.into())
+    }
+}
+
+impl PathResolver for DirResolver {
+    fn resolve(&self, name: &str) -> io::Result<PathBuf> {
+        platform::dir_path(&self.0, name)
+    }
+}
+
+/// Wraps another [`PathResolver`] and replaces the file name with a hash of the endpoint name
+/// when the resolved path is too long to be used as an endpoint.
+///
+/// Unix socket paths are limited to 104 bytes on macOS and the BSDs and 108 bytes on Linux,
+/// which deeply nested runtime directories or long names can easily exceed. Paths that fit are
+/// returned unchanged, and shortened paths keep the directory and extension of the original.
+/// The hash is stable across processes and builds, so servers and clients that use the same
+/// resolver agree on the path.
+#[derive(Clone, Debug)]
+pub struct ShortenResolver<R>(R);
+
+impl<R: PathResolver> ShortenResolver<R> {
+    /// New resolver that shortens the paths produced by `resolver`.
+    pub fn new(resolver: R) -> Self {
+        Self(resolver)
+    }
+}
+
+impl<R: PathResolver> PathResolver for ShortenResolver<R> {
+    fn resolve(&self, name: &str) -> io::Result<PathBuf> {
+        let path = self.0.resolve(name)?;
+        if platform::validate_path(&path).is_ok() {
+            return Ok(path);
+        }
+        let mut short = path.with_file_name(format!("{:016x}", hash_name(name)));
+        if let Some(extension) = path.extension() {
+            short.set_extension(extension);
+        }
+        trace!(?path, ?short, "shortened endpoint path");
+        Ok(short)
+    }
+}
+
+// 64-bit FNV-1a, which unlike the standard library's hasher is guaranteed not to change between
+// Rust versions
+fn hash_name(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Endpoint name paired with the [`PathResolver`] used to turn it into a path, created by
+/// [`ServerId::resolve_with`] or [`ServiceId::resolve_with`].
+#[derive(Clone, Debug)]
+pub struct ResolvedPath<R> {
+    name: String,
+    resolver: R,
+}
+
+impl<R: PathResolver> ResolvedPath<R> {
+    fn new(name: String, resolver: R) -> Self {
+        Self { name, resolver }
+    }
+}
+
+impl<R: PathResolver> IntoIpcPath for ResolvedPath<R> {
+    fn into_ipc_path(self) -> io::Result<PathBuf> {
+        self.resolver.resolve(&self.name)
+    }
+}
+
+fn encode_component(component: &str) -> String {
+    let mut encoded = String::with_capacity(component.len());
+    for byte in component.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
     }
+    encoded
 }
 
 /// Permissions and ownership for the IPC connection
+///
+/// On Unix, the default attributes give the socket mode `0o600`. To apply the permissions before
+/// anyone can connect, the socket is bound in a private temporary directory next to it and then
+/// moved into place. If that isn't possible, because the temporary path would be too long for a
+/// socket or the filesystem doesn't support hard links, the socket is bound with a restrictive
+/// umask instead. The umask is shared by the whole process, so files created by other threads
+/// while the socket is being bound get the restricted permissions as well.
 pub struct SecurityAttributes(platform::SecurityAttributes);
 
 impl SecurityAttributes {
@@ -107,94 +532,2080 @@ impl SecurityAttributes {
     pub fn allow_everyone_create() -> io::Result<Self> {
         Ok(Self(platform::SecurityAttributes::allow_everyone_create()?))
     }
-}
 
-/// IPC endpoint.
-pub struct Endpoint(platform::Endpoint);
+    /// Change the owner of the socket to the given user id after it's created.
+    ///
+    /// Changing the owner generally requires elevated privileges.
+    #[cfg(unix)]
+    pub fn set_owner(self, uid: u32) -> io::Result<Self> {
+        Ok(Self(self.0.set_owner(uid)?))
+    }
 
-impl Endpoint {
-    /// Stream of incoming connections
-    pub fn incoming(self) -> io::Result<IpcStream> {
-        Ok(IpcStream(self.0.incoming()?))
+    /// Change the group of the socket to the given group id after it's created.
+    #[cfg(unix)]
+    pub fn set_group(self, gid: u32) -> io::Result<Self> {
+        Ok(Self(self.0.set_group(gid)?))
     }
-    /// Set security attributes for the connection
-    pub fn set_security_attributes(&mut self, security_attributes: SecurityAttributes) {
-        self.0.set_security_attributes(security_attributes.0);
+
+    /// Change the group of the socket to the group with the given name after it's created.
+    ///
+    /// Returns an error if the group doesn't exist.
+    #[cfg(unix)]
+    pub fn set_group_name(self, name: &str) -> io::Result<Self> {
+        Ok(Self(self.0.set_group_name(name)?))
     }
-    /// Returns the path of the endpoint.
-    pub fn path(&self) -> &Path {
-        self.0.path()
+
+    /// New security attributes that only allow the current user to connect.
+    ///
+    /// On Windows, the DACL only grants access to the SID of the user running the current
+    /// process. On Unix, the socket is created with mode `0600` and binding fails if the socket
+    /// isn't owned by the current user.
+    pub fn current_user_only() -> io::Result<Self> {
+        Ok(Self(platform::SecurityAttributes::current_user_only()?))
     }
-    /// Make new connection using the provided path and running event pool.
-    pub async fn connect(path: impl IntoIpcPath) -> io::Result<Connection> {
-        Ok(Connection(platform::Endpoint::connect(path).await?))
+
+    /// Security attributes described by a Security Descriptor Definition Language string,
+    /// e.g. `D:(A;;GA;;;WD)`.
+    #[cfg(windows)]
+    pub fn from_sddl(sddl: &str) -> io::Result<Self> {
+        Ok(Self(platform::SecurityAttributes::from_sddl(sddl)?))
     }
 
-    /// New IPC endpoint at the given path
-    pub fn new(path: impl IntoIpcPath, on_conflict: OnConflict) -> io::Result<Self> {
-        Ok(Self(platform::Endpoint::new(path, on_conflict)?))
+    /// Security attributes that use a copy of an existing security descriptor, e.g. one built
+    /// with another Windows crate.
+    ///
+    /// Both absolute and self-relative descriptors are accepted. The descriptor is copied, so the
+    /// caller keeps ownership of it and may free it as soon as this returns.
+    ///
+    /// # Safety
+    ///
+    /// `descriptor` must point to a valid `SECURITY_DESCRIPTOR`.
+    #[cfg(windows)]
+    pub unsafe fn from_security_descriptor(descriptor: *mut std::ffi::c_void) -> io::Result<Self> {
+        Ok(Self(
+            platform::SecurityAttributes::from_security_descriptor(descriptor)?,
+        ))
     }
-}
 
-/// IPC connection.
-pub struct Connection(platform::Connection);
+    /// Grant read and write access to the given account.
+    ///
+    /// Accounts that aren't granted access by any entry are denied access.
+    #[cfg(windows)]
+    pub fn allow(self, trustee: Trustee) -> io::Result<Self> {
+        Ok(Self(self.0.allow(trustee)?))
+    }
 
-impl Connection {
-    /// Create a stream from an existing [`UnixStream`](std::os::unix::net::UnixStream).
+    /// Deny all access to the given account. Deny entries take precedence over allow entries.
+    #[cfg(windows)]
+    pub fn deny(self, trustee: Trustee) -> io::Result<Self> {
+        Ok(Self(self.0.deny(trustee)?))
+    }
+
+    /// Grant access to a single user or group, given either as a name like `DOMAIN\account` or
+    /// as a SID string like `S-1-5-32-544`.
+    ///
+    /// Accounts that aren't granted access by any entry are denied access.
+    #[cfg(windows)]
+    pub fn allow_user(self, account: &str, access: Access) -> io::Result<Self> {
+        Ok(Self(self.0.allow_user(account, access)?))
+    }
+
+    /// Make the given user id the owner of the socket and grant it `access` through the owner
+    /// permission bits.
+    ///
+    /// A socket only has a single owner, so calling this again replaces the previous user.
+    /// Changing the owner generally requires elevated privileges. Connecting to a socket requires
+    /// write permission, so [`Access::Read`] on its own doesn't allow the user to connect.
     #[cfg(unix)]
-    pub async fn from_std_stream(stream: std::os::unix::net::UnixStream) -> io::Result<Self> {
-        Ok(Self(platform::from_std_stream(stream).await?))
+    pub fn allow_user(self, uid: u32, access: Access) -> io::Result<Self> {
+        Ok(Self(self.0.allow_user(uid, access)?))
+    }
+
+    /// Make the given group id the group of the socket and grant it `access` through the group
+    /// permission bits.
+    ///
+    /// A socket only has a single group, so calling this again replaces the previous group.
+    #[cfg(unix)]
+    pub fn allow_group(self, gid: u32, access: Access) -> io::Result<Self> {
+        Ok(Self(self.0.allow_group(gid, access)?))
     }
 }
 
-impl AsyncRead for Connection {
-    fn poll_read(
-        self: Pin<&mut Self>,
-        ctx: &mut Context<'_>,
-        buf: &mut ReadBuf<'_>,
-    ) -> Poll<io::Result<()>> {
-        let this = Pin::into_inner(self);
-        Pin::new(&mut this.0).poll_read(ctx, buf)
+/// Access granted to a user or group with [`SecurityAttributes::allow_user`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Access {
+    /// The account can read from the endpoint
+    Read,
+    /// The account can write to the endpoint
+    Write,
+    /// The account can read from and write to the endpoint
+    #[default]
+    ReadWrite,
+}
+
+/// Account or group that an access control entry applies to.
+#[cfg(windows)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Trustee {
+    /// The well-known Everyone group
+    Everyone,
+    /// The user running the current process
+    CurrentUser,
+    /// Security identifier in string form, e.g. `S-1-5-32-544` for the built-in Administrators
+    /// group.
+    Sid(String),
+    /// Account or group name, e.g. `DOMAIN\account`.
+    Name(String),
+}
+
+/// Either end of a named pipe, returned by [`Connection::into_inner`].
+#[cfg(windows)]
+#[derive(Debug)]
+pub enum NamedPipe {
+    /// Connection accepted by a server
+    Server(tokio::net::windows::named_pipe::NamedPipeServer),
+    /// Connection established by a client
+    Client(tokio::net::windows::named_pipe::NamedPipeClient),
+}
+
+/// Creates named pipe instances with an endpoint's options, returned by
+/// [`Endpoint::into_listener`].
+///
+/// Each instance accepts a single client. Wait for one with
+/// [`NamedPipeServer::connect`](tokio::net::windows::named_pipe::NamedPipeServer::connect), and
+/// create the next instance before handing the connected one off, so clients don't find the pipe
+/// busy in between.
+#[cfg(windows)]
+pub struct PipeListener {
+    inner: platform::Endpoint,
+    // Created up front so the pipe name is claimed as soon as the listener exists
+    first: Option<tokio::net::windows::named_pipe::NamedPipeServer>,
+}
+
+#[cfg(windows)]
+impl PipeListener {
+    /// Returns a new pipe instance that's ready for a client to connect.
+    ///
+    /// Fails with `ERROR_PIPE_BUSY` if the maximum number of instances set with
+    /// [`Endpoint::set_max_instances`] already exist.
+    pub fn create_instance(
+        &mut self,
+    ) -> io::Result<tokio::net::windows::named_pipe::NamedPipeServer> {
+        match self.first.take() {
+            Some(first) => Ok(first),
+            None => self.inner.create_listener(),
+        }
+    }
+
+    /// Path of the pipe.
+    pub fn path(&self) -> &Path {
+        self.inner.path()
     }
 }
 
-impl AsyncWrite for Connection {
-    fn poll_write(
-        self: Pin<&mut Self>,
-        ctx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<Result<usize, io::Error>> {
-        let this = Pin::into_inner(self);
-        Pin::new(&mut this.0).poll_write(ctx, buf)
+/// Mode used for reading from and writing to a named pipe.
+#[cfg(windows)]
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum PipeMode {
+    /// Data is read and written as a stream of bytes
+    #[default]
+    Byte,
+    /// Data is read and written as discrete messages. Each write is treated as a single message
+    /// and reads return whole messages.
+    ///
+    /// Reading a message into a buffer that's too small to hold it returns an error with the
+    /// code `ERROR_MORE_DATA`.
+    Message,
+}
+
+/// Configuration and state of a named pipe, returned from [`Connection::pipe_info`].
+#[cfg(windows)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PipeInfo {
+    is_server: bool,
+    pipe_mode: PipeMode,
+    read_mode: PipeMode,
+    out_buffer_size: u32,
+    in_buffer_size: u32,
+    max_instances: Option<u32>,
+    current_instances: u32,
+}
+
+#[cfg(windows)]
+impl PipeInfo {
+    /// Whether the handle is the server end of the pipe.
+    pub fn is_server(&self) -> bool {
+        self.is_server
     }
 
-    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        let this = Pin::into_inner(self);
-        Pin::new(&mut this.0).poll_flush(ctx)
+    /// Mode the pipe was created with, which determines how data is written to it.
+    pub fn pipe_mode(&self) -> PipeMode {
+        self.pipe_mode
     }
 
-    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
-        let this = Pin::into_inner(self);
-        Pin::new(&mut this.0).poll_shutdown(ctx)
+    /// Mode this end of the pipe reads data in. A pipe created in message mode can still be
+    /// read as a stream of bytes.
+    pub fn read_mode(&self) -> PipeMode {
+        self.read_mode
+    }
+
+    /// Size of the buffer for outgoing data in bytes. A size of 0 means the buffer is allocated
+    /// as needed.
+    pub fn out_buffer_size(&self) -> u32 {
+        self.out_buffer_size
+    }
+
+    /// Size of the buffer for incoming data in bytes. A size of 0 means the buffer is allocated
+    /// as needed.
+    pub fn in_buffer_size(&self) -> u32 {
+        self.in_buffer_size
+    }
+
+    /// Maximum number of instances of the pipe, or `None` if it's unlimited.
+    pub fn max_instances(&self) -> Option<u32> {
+        self.max_instances
+    }
+
+    /// Number of instances of the pipe that currently exist.
+    pub fn current_instances(&self) -> u32 {
+        self.current_instances
     }
 }
 
-/// Stream of incoming connections.
-pub struct IpcStream(platform::IpcStream);
+/// Strategy used to space out connection attempts.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Backoff {
+    /// Wait for the same interval between each attempt
+    Fixed,
+    /// Double the interval after each attempt, up to the given maximum
+    Exponential {
+        /// Upper bound for the interval between attempts
+        max: Duration,
+    },
+}
 
-impl IpcStream {
-    /// Create a listener from an existing [`UnixListener`](std::os::unix::net::UnixListener).
-    #[cfg(unix)]
-    pub fn from_std_listener(listener: std::os::unix::net::UnixListener) -> io::Result<Self> {
-        Ok(Self(platform::IpcStream::from_std_listener(listener)?))
+impl Backoff {
+    pub(crate) fn next_interval(&self, interval: Duration) -> Duration {
+        match self {
+            Self::Fixed => interval,
+            Self::Exponential { max } => interval.saturating_mul(2).min(*max),
+        }
     }
 }
 
-impl Stream for IpcStream {
-    type Item = io::Result<Connection>;
+/// Options for connecting to an IPC endpoint.
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    pub(crate) busy_timeout: Duration,
+    pub(crate) poll_interval: Duration,
+    pub(crate) backoff: Backoff,
+    pub(crate) retry_attempts: u32,
+    #[cfg(windows)]
+    pub(crate) pipe_mode: PipeMode,
+    pub(crate) write_through: bool,
+    pub(crate) direction: Direction,
+    pub(crate) token_auth: Option<TokenAuth>,
+    pub(crate) metrics: Option<MetricsHandle>,
+    pub(crate) in_buffer_size: Option<u32>,
+    pub(crate) out_buffer_size: Option<u32>,
+}
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let this = Pin::into_inner(self);
-        Pin::new(&mut this.0).poll_next(cx).map_ok(Connection)
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            busy_timeout: Duration::from_secs(5),
+            poll_interval: Duration::from_millis(50),
+            backoff: Backoff::Fixed,
+            retry_attempts: 0,
+            #[cfg(windows)]
+            pipe_mode: PipeMode::Byte,
+            write_through: false,
+            direction: Direction::Duplex,
+            token_auth: None,
+            metrics: None,
+            in_buffer_size: None,
+            out_buffer_size: None,
+        }
+    }
+}
+
+impl ConnectOptions {
+    /// New default connection options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long to wait for a pipe instance to become available while all of them are busy
+    /// before giving up. Defaults to 5 seconds.
+    ///
+    /// This is only used on Windows, where the connection is retried as soon as an instance is
+    /// freed.
+    pub fn busy_timeout(mut self, busy_timeout: Duration) -> Self {
+        self.busy_timeout = busy_timeout;
+        self
+    }
+
+    /// Initial interval to wait between connection attempts. Defaults to 50 milliseconds.
+    ///
+    /// This applies to [retries](ConnectOptions::retry_attempts).
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Strategy used to adjust the interval between connection attempts. Defaults to
+    /// [`Backoff::Fixed`].
+    ///
+    /// This applies to [retries](ConnectOptions::retry_attempts).
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Number of times to retry connecting when no server is listening yet, which covers the
+    /// window where a server is still starting up. Defaults to 0.
+    ///
+    /// A retry happens when the endpoint doesn't exist or the connection is refused. Attempts
+    /// are spaced out using the [`poll_interval`](ConnectOptions::poll_interval) and
+    /// [`backoff`](ConnectOptions::backoff) settings. The error from the last attempt is returned
+    /// once all retries are used up.
+    pub fn retry_attempts(mut self, retry_attempts: u32) -> Self {
+        self.retry_attempts = retry_attempts;
+        self
+    }
+
+    /// Size of the socket's receive buffer (`SO_RCVBUF`). Defaults to the system setting.
+    ///
+    /// This is only used on Unix. Named pipe buffers are sized by the server.
+    pub fn in_buffer_size(mut self, size: u32) -> Self {
+        self.in_buffer_size = Some(size);
+        self
+    }
+
+    /// Size of the socket's send buffer (`SO_SNDBUF`). Defaults to the system setting.
+    ///
+    /// This is only used on Unix. Named pipe buffers are sized by the server.
+    pub fn out_buffer_size(mut self, size: u32) -> Self {
+        self.out_buffer_size = Some(size);
+        self
+    }
+
+    /// Read mode of the client end of the pipe. This should match the mode the server was
+    /// created with. Defaults to [`PipeMode::Byte`].
+    #[cfg(windows)]
+    pub fn pipe_mode(mut self, pipe_mode: PipeMode) -> Self {
+        self.pipe_mode = pipe_mode;
+        self
+    }
+
+    /// Open the client end of the pipe with `FILE_FLAG_WRITE_THROUGH`, so writes don't return
+    /// until the data has been transmitted instead of being buffered by the OS. Defaults to
+    /// `false`. See [`Endpoint::set_write_through`] for the caveats.
+    ///
+    /// This is only used on Windows.
+    pub fn write_through(mut self, write_through: bool) -> Self {
+        self.write_through = write_through;
+        self
+    }
+
+    /// Direction of the endpoint being connected to. This must match the direction the server
+    /// was created with. Defaults to [`Direction::Duplex`].
+    pub fn direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Token to present to the server after connecting. The server must be configured with the
+    /// same token using [`Endpoint::set_token_auth`].
+    pub fn token_auth(mut self, token_auth: TokenAuth) -> Self {
+        self.token_auth = Some(token_auth);
+        self
+    }
+
+    /// Report metrics for connections created with these options.
+    pub fn metrics(mut self, metrics: Arc<dyn IpcMetrics>) -> Self {
+        self.metrics = Some(MetricsHandle::new(metrics));
+        self
+    }
+}
+
+/// IPC endpoint.
+///
+/// Options can be set with the `set_*` methods, or all at once with an [`EndpointBuilder`].
+pub struct Endpoint {
+    inner: platform::Endpoint,
+    token_auth: Option<TokenAuth>,
+    peer_filter: Option<PeerFilter>,
+    metrics: Option<MetricsHandle>,
+    idle_timeout: Option<Duration>,
+    rate_limit: Option<RateLimit>,
+    pid_file: Option<PathBuf>,
+    on_ready: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Endpoint {
+    /// Stream of incoming connections
+    pub fn incoming(self) -> Result<IpcStream, Error> {
+        let path = self.path().to_owned();
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("ipc_endpoint", path = ?path);
+        let inner = self.inner.incoming().map_err(Error::bind(&path))?;
+        let mut stream = IpcStream::new(inner);
+        if let Some(pid_file) = self.pid_file {
+            let pid_file = PidFile::create(pid_file).map_err(Error::bind(&path))?;
+            stream.shutdown.set_pid_file(pid_file);
+        }
+        if let Some(on_ready) = self.on_ready {
+            on_ready();
+        }
+        stream.path = Some(path);
+        #[cfg(feature = "tracing")]
+        {
+            stream.span = span;
+        }
+        stream.token_auth = self.token_auth;
+        stream.peer_filter = self.peer_filter;
+        stream.metrics = self.metrics;
+        stream.idle_timeout = self.idle_timeout;
+        stream.rate_limit = self.rate_limit.map(TokenBucket::new);
+        Ok(stream)
+    }
+    /// Stream of incoming connections that ends once the given token is cancelled.
+    ///
+    /// Cancelling the token has the same effect as calling [`ShutdownHandle::shutdown`], including
+    /// waking a task that's waiting for a connection.
+    #[cfg(feature = "tokio-util")]
+    pub fn incoming_with_cancellation(
+        self,
+        token: tokio_util::sync::CancellationToken,
+    ) -> Result<IpcStream, Error> {
+        let mut stream = self.incoming()?;
+        stream.cancellation = Some(Box::pin(token.cancelled_owned()));
+        Ok(stream)
+    }
+    /// Stream of incoming connections along with information about each peer.
+    pub fn incoming_with_info(self) -> Result<IpcStreamWithInfo, Error> {
+        Ok(IpcStreamWithInfo(self.incoming()?))
+    }
+    /// Blocking iterator of incoming connections, for use without an async runtime.
+    ///
+    /// Token authentication, peer filters, metrics, idle timeouts, and rate limits require an
+    /// async runtime, so an error is returned if any of them are set.
+    pub fn incoming_blocking(self) -> Result<blocking::Incoming, Error> {
+        let path = self.path().to_owned();
+        if self.token_auth.is_some()
+            || self.peer_filter.is_some()
+            || self.metrics.is_some()
+            || self.idle_timeout.is_some()
+            || self.rate_limit.is_some()
+        {
+            return Err(Error::bind(path)(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "endpoint options are not supported by blocking connections",
+            )));
+        }
+        let inner = self.inner.incoming_blocking().map_err(Error::bind(&path))?;
+        let pid_file = self
+            .pid_file
+            .map(PidFile::create)
+            .transpose()
+            .map_err(Error::bind(path))?;
+        if let Some(on_ready) = self.on_ready {
+            on_ready();
+        }
+        Ok(blocking::Incoming::new(inner, pid_file))
+    }
+    /// Binds the endpoint and returns the underlying listener, for driving accepts directly.
+    ///
+    /// The socket is created with the endpoint's path, security attributes, conflict handling and
+    /// backlog, but accepted connections are plain [`UnixStream`](tokio::net::UnixStream)s that
+    /// none of the per-connection options apply to. The socket file isn't removed when the
+    /// listener is dropped. Token authentication, peer filters, metrics, idle timeouts, rate
+    /// limits, pid files and lock files depend on the crate accepting connections, so an error is
+    /// returned if any of them are set.
+    #[cfg(unix)]
+    pub fn into_listener(self) -> Result<tokio::net::UnixListener, Error> {
+        let path = self.path().to_owned();
+        self.check_listener_options().map_err(Error::bind(&path))?;
+        let listener = self.inner.into_listener().map_err(Error::bind(path))?;
+        if let Some(on_ready) = self.on_ready {
+            on_ready();
+        }
+        Ok(listener)
+    }
+    /// Creates the first pipe instance and returns a [`PipeListener`] that creates more
+    /// instances with the endpoint's options, for driving accepts directly.
+    ///
+    /// Token authentication, peer filters, metrics, idle timeouts, rate limits and pid files
+    /// depend on the crate accepting connections, so an error is returned if any of them are set.
+    #[cfg(windows)]
+    pub fn into_listener(mut self) -> Result<PipeListener, Error> {
+        let path = self.path().to_owned();
+        self.check_listener_options().map_err(Error::bind(&path))?;
+        let first = self.inner.create_listener().map_err(Error::bind(path))?;
+        if let Some(on_ready) = self.on_ready {
+            on_ready();
+        }
+        Ok(PipeListener {
+            inner: self.inner,
+            first: Some(first),
+        })
+    }
+    fn check_listener_options(&self) -> io::Result<()> {
+        if self.token_auth.is_some()
+            || self.peer_filter.is_some()
+            || self.metrics.is_some()
+            || self.idle_timeout.is_some()
+            || self.rate_limit.is_some()
+            || self.pid_file.is_some()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "endpoint options are not supported by raw listeners",
+            ));
+        }
+        Ok(())
+    }
+    /// Set security attributes for the connection. See [`SecurityAttributes`] for how they're
+    /// applied on Unix.
+    pub fn set_security_attributes(&mut self, security_attributes: SecurityAttributes) {
+        self.inner.set_security_attributes(security_attributes.0);
+    }
+    /// Restrict connections to sending data in one direction. Defaults to [`Direction::Duplex`].
+    ///
+    /// On Windows, the pipe is created with only inbound or outbound access, so clients have to
+    /// connect with the same direction using [`ConnectOptions::direction`]. On Unix, the unused
+    /// half of each accepted socket is shut down, so writes in the wrong direction fail and
+    /// reads in the wrong direction return EOF.
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.inner.set_direction(direction);
+    }
+    /// Remove the socket file when the stream of incoming connections is dropped or shut down.
+    /// Defaults to `true`.
+    ///
+    /// Disable this when the listening socket is handed off to another process during a restart,
+    /// since removing the file would make the socket unreachable for the new process. The file can
+    /// still be removed deliberately with [`IpcStream::cleanup`].
+    #[cfg(unix)]
+    pub fn set_remove_on_drop(&mut self, remove_on_drop: bool) {
+        self.inner.set_remove_on_drop(remove_on_drop);
+    }
+    /// Take an exclusive advisory lock on a companion file named `{path}.lock` before binding,
+    /// which ensures only one server runs on the path at a time. Defaults to `false`.
+    ///
+    /// If another server already holds the lock, binding fails with
+    /// [`io::ErrorKind::AddrInUse`] and the error message includes the pid of that server. The
+    /// lock is released when the stream of incoming connections is dropped or the process exits,
+    /// so a server that crashed never blocks a new one. Since the lock is taken first, the socket
+    /// file left behind by a crashed server can safely be replaced with
+    /// [`OnConflict::Overwrite`]. The lock file itself is left in place.
+    #[cfg(unix)]
+    pub fn set_lock_file(&mut self, lock_file: bool) {
+        self.inner.set_lock_file(lock_file);
+    }
+    /// Set the mode of the pipe instances created by the server. Defaults to [`PipeMode::Byte`].
+    #[cfg(windows)]
+    pub fn set_pipe_mode(&mut self, pipe_mode: PipeMode) {
+        self.inner.set_pipe_mode(pipe_mode);
+    }
+    /// Limit the number of pipe instances that can exist at once, which bounds the number of
+    /// simultaneously connected clients. Clamped to the range `1..=254`. Defaults to unlimited.
+    ///
+    /// Once the limit is reached, the server stops creating new instances until an existing
+    /// connection is closed. Clients that connect in the meantime see all instances as busy, so
+    /// [`Endpoint::connect_with_options`] waits for an instance until the
+    /// [`busy_timeout`](ConnectOptions::busy_timeout) elapses and then fails with
+    /// `ERROR_PIPE_BUSY` (raw OS error 231).
+    #[cfg(windows)]
+    pub fn set_max_instances(&mut self, max_instances: u8) {
+        self.inner.set_max_instances(max_instances);
+    }
+    /// Allow the server to create additional instances of a pipe that's already owned by another
+    /// server, rather than failing. Defaults to `false`.
+    ///
+    /// Clients are spread across the instances of both servers, which allows a new version of a
+    /// server to start accepting connections before the old one shuts down. The existing pipe must
+    /// have been created with the same [`PipeMode`] and instance limit, and its security
+    /// attributes must allow the current user to create instances.
+    ///
+    /// When this is disabled and the pipe is already in use, [`Endpoint::incoming`] fails with
+    /// [`io::ErrorKind::AddrInUse`] and an error message containing the process id of the
+    /// existing server, if it could be determined.
+    #[cfg(windows)]
+    pub fn set_join_existing(&mut self, join_existing: bool) {
+        self.inner.set_join_existing(join_existing);
+    }
+    /// Create pipe instances with `FILE_FLAG_WRITE_THROUGH`, so writes don't return until the data
+    /// has been transmitted instead of being buffered by the OS. This trades throughput for more
+    /// predictable latency. Defaults to `false`.
+    ///
+    /// Windows only honors this for byte-mode pipes, and only when the client is on a different
+    /// machine than the server, which requires [`Endpoint::set_reject_remote_clients`] to be
+    /// disabled.
+    #[cfg(windows)]
+    pub fn set_write_through(&mut self, write_through: bool) {
+        self.inner.set_write_through(write_through);
+    }
+    /// Reject clients connecting from other machines over SMB. Defaults to `true`.
+    ///
+    /// Only disable this when remote access is actually needed, such as for cross-machine admin
+    /// tools. Remote clients must also be granted access by the security attributes, and
+    /// anonymous network logons are denied access to pipes by default.
+    #[cfg(windows)]
+    pub fn set_reject_remote_clients(&mut self, reject_remote_clients: bool) {
+        self.inner.set_reject_remote_clients(reject_remote_clients);
+    }
+    /// Reuse the pipe instance of a closed connection for the next client instead of closing it
+    /// and creating a new one. This cuts down on handle churn for servers that accept many
+    /// short-lived connections. Defaults to `false`.
+    ///
+    /// When an accepted connection is dropped, its instance is disconnected with
+    /// `DisconnectNamedPipe`, which discards any data the client hasn't read yet. Only enable
+    /// this if clients are done reading by the time the server drops the connection, for
+    /// example because the client always closes the connection first. Connections converted with
+    /// [`Connection::into_inner`] are never reused. This only applies to [`Endpoint::incoming`].
+    #[cfg(windows)]
+    pub fn set_reuse_instances(&mut self, reuse_instances: bool) {
+        self.inner.set_reuse_instances(reuse_instances);
+    }
+    /// Number of pipe instances that wait for clients at the same time. Defaults to 1.
+    ///
+    /// With a single instance, clients that connect while the server is replacing the instance
+    /// that was just used see all instances as busy and have to wait. Keeping more instances ready
+    /// lets bursts of clients connect right away. Waiting instances count toward the limit set
+    /// with [`Endpoint::set_max_instances`].
+    #[cfg(windows)]
+    pub fn set_standby_instances(&mut self, standby_instances: usize) {
+        self.inner.set_standby_instances(standby_instances);
+    }
+    /// Set the size of the buffer used for data received by the server.
+    ///
+    /// On Windows, this is the input buffer of each pipe instance and defaults to 64 KiB. On Unix,
+    /// this sets `SO_RCVBUF` on the listener and each accepted socket and defaults to the system
+    /// setting.
+    ///
+    /// The system treats this as a hint and may round the size or grow the buffer as needed.
+    pub fn set_in_buffer_size(&mut self, size: u32) {
+        self.inner.set_in_buffer_size(size);
+    }
+    /// Set the size of the buffer used for data sent by the server.
+    ///
+    /// On Windows, this is the output buffer of each pipe instance and defaults to 64 KiB. On Unix,
+    /// this sets `SO_SNDBUF` on the listener and each accepted socket and defaults to the system
+    /// setting.
+    ///
+    /// The system treats this as a hint and may round the size or grow the buffer as needed.
+    pub fn set_out_buffer_size(&mut self, size: u32) {
+        self.inner.set_out_buffer_size(size);
+    }
+    /// Require clients to present the given token before their connections are yielded from
+    /// [`Endpoint::incoming`].
+    pub fn set_token_auth(&mut self, token_auth: TokenAuth) {
+        self.token_auth = Some(token_auth);
+    }
+    /// Only accept connections from peers allowed by the given filter.
+    pub fn set_peer_filter(&mut self, peer_filter: PeerFilter) {
+        self.peer_filter = Some(peer_filter);
+    }
+    /// Report metrics for the endpoint and its accepted connections.
+    pub fn set_metrics(&mut self, metrics: Arc<dyn IpcMetrics>) {
+        self.metrics = Some(MetricsHandle::new(metrics));
+    }
+    /// Close accepted connections that go longer than the given duration without any reads or
+    /// writes.
+    ///
+    /// Once the timeout elapses, pending reads and writes on the connection fail with
+    /// [`io::ErrorKind::TimedOut`] so the task handling the connection can drop it.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = Some(timeout);
+    }
+    /// Limit how quickly connections are accepted.
+    pub fn set_accept_rate_limit(&mut self, rate_limit: RateLimit) {
+        self.rate_limit = Some(rate_limit);
+    }
+    /// Write the pid of the current process to the given file once the endpoint is bound, so
+    /// process managers and command line tools can find the server that owns the endpoint.
+    ///
+    /// The file is removed when the stream of incoming connections is dropped or shut down. It's
+    /// left behind if the process exits without dropping the stream, so readers should check
+    /// that the process is still running. Pair this with [`Endpoint::set_lock_file`] on Unix to
+    /// make sure only one server writes to the file.
+    pub fn set_pid_file(&mut self, path: impl Into<PathBuf>) {
+        self.pid_file = Some(path.into());
+    }
+    /// Call the given function once the endpoint is bound and its permissions are applied, just
+    /// before [`Endpoint::incoming`] returns.
+    ///
+    /// This lets supervisors and tests wait for the server to be ready without polling, for
+    /// example by sending on a `oneshot` channel or calling `Notify::notify_one`. The function
+    /// isn't called if binding fails.
+    pub fn set_on_ready(&mut self, on_ready: impl FnOnce() + Send + 'static) {
+        self.on_ready = Some(Box::new(on_ready));
+    }
+    /// Returns the path of the endpoint.
+    pub fn path(&self) -> &Path {
+        self.inner.path()
+    }
+    /// Make new connection using the provided path and running event pool.
+    pub async fn connect(path: impl IntoIpcPath) -> Result<Connection, Error> {
+        Self::connect_with_options(path, ConnectOptions::default()).await
+    }
+
+    /// Make a single connection attempt without sleeping or retrying, so callers can schedule
+    /// their own retries.
+    ///
+    /// If the server isn't ready to accept the connection, this fails immediately. On Windows,
+    /// that means an `ERROR_PIPE_BUSY` error if all pipe instances are in use. On Unix, that
+    /// means an [`io::ErrorKind::WouldBlock`] error if the server's backlog is full.
+    pub async fn try_connect(path: impl IntoIpcPath) -> Result<Connection, Error> {
+        // A zero busy timeout disables waiting for a pipe instance
+        let options = ConnectOptions::default().busy_timeout(Duration::ZERO);
+        Self::connect_with_options(path, options).await
+    }
+
+    /// Make new connection using the provided path and connection options.
+    pub async fn connect_with_options(
+        path: impl IntoIpcPath,
+        options: ConnectOptions,
+    ) -> Result<Connection, Error> {
+        // The path isn't known if it can't be resolved
+        let path = path
+            .into_ipc_path()
+            .map_err(Error::connect(PathBuf::new()))?;
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("ipc_connect", path = ?path);
+        let on_error = Error::connect(path.clone());
+        let connect = async move {
+            let conn = Connection::new(connect_with_retry(path, &options).await?);
+            trace!(id = conn.id(), "connected");
+            let mut conn = match &options.token_auth {
+                Some(token_auth) => token_auth.authenticate(conn).await?,
+                None => conn,
+            };
+            if let Some(metrics) = &options.metrics {
+                conn.metrics = Some(metrics.established(conn.id));
+            }
+            Ok(conn)
+        };
+        #[cfg(feature = "tracing")]
+        let connect = tracing::Instrument::instrument(connect, span);
+        connect.await.map_err(on_error)
+    }
+
+    /// Connect to a socket in the abstract namespace, such as one created with
+    /// [`IpcStream::autobind`]. `name` shouldn't include the leading null byte.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub async fn connect_abstract(name: impl AsRef<[u8]>) -> io::Result<Connection> {
+        let conn = Connection::new(platform::connect_abstract(name.as_ref().to_vec()).await?);
+        trace!(id = conn.id(), "connected");
+        Ok(conn)
+    }
+
+    /// Make a new blocking connection using the provided path, for use without an async runtime.
+    pub fn connect_blocking(path: impl IntoIpcPath) -> Result<blocking::Connection, Error> {
+        Self::connect_blocking_with_options(path, ConnectOptions::default())
+    }
+
+    /// Make a new blocking connection using the provided path and connection options.
+    ///
+    /// Token authentication and metrics require an async runtime, so an error is returned if
+    /// either of them is set.
+    pub fn connect_blocking_with_options(
+        path: impl IntoIpcPath,
+        options: ConnectOptions,
+    ) -> Result<blocking::Connection, Error> {
+        let path = path
+            .into_ipc_path()
+            .map_err(Error::connect(PathBuf::new()))?;
+        if options.token_auth.is_some() || options.metrics.is_some() {
+            return Err(Error::connect(path)(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "connect options are not supported by blocking connections",
+            )));
+        }
+        let mut attempts = 0;
+        let mut interval = options.poll_interval;
+        loop {
+            match platform::Endpoint::connect_blocking(path.clone(), &options) {
+                Err(e) if attempts < options.retry_attempts && is_not_listening(&e) => {
+                    attempts += 1;
+                    debug!(?interval, attempts, "no server is listening, retrying");
+                    std::thread::sleep(interval);
+                    interval = options.backoff.next_interval(interval);
+                }
+                res => {
+                    return res
+                        .map(blocking::Connection::new)
+                        .map_err(Error::connect(path))
+                }
+            }
+        }
+    }
+
+    /// Checks whether a server is listening at the given path without opening a connection that
+    /// the caller has to deal with. This is useful for deciding whether a daemon needs to be
+    /// spawned.
+    ///
+    /// On Windows, this uses `WaitNamedPipeW` and doesn't consume a pipe instance. On Unix, there
+    /// is no way to check for a listener without connecting, so the server will see a
+    /// connection that closes immediately.
+    pub fn probe(path: impl IntoIpcPath) -> io::Result<EndpointStatus> {
+        platform::probe(&path.into_ipc_path()?)
+    }
+
+    /// Returns `true` if a server is accepting connections at the given path.
+    ///
+    /// This is shorthand for checking if [`Endpoint::probe`] returns
+    /// [`EndpointStatus::Listening`].
+    pub fn is_listening(path: impl IntoIpcPath) -> bool {
+        matches!(Self::probe(path), Ok(EndpointStatus::Listening))
+    }
+
+    /// Waits until a server is accepting connections at the given path. This is useful for
+    /// clients that are started alongside the server and may run before it finishes starting up.
+    ///
+    /// The endpoint is checked with [`Endpoint::probe`] every 50 milliseconds, so on Unix the
+    /// server will see a connection that closes immediately once it's ready. Fails with
+    /// [`io::ErrorKind::TimedOut`] if no server is listening once the timeout elapses.
+    pub async fn wait_ready(path: impl IntoIpcPath, timeout: Duration) -> io::Result<()> {
+        let path = path.into_ipc_path()?;
+        let wait = async {
+            loop {
+                if platform::probe(&path)? == EndpointStatus::Listening {
+                    return Ok(());
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("no server started listening on {path:?} in time"),
+            )
+        })?
+    }
+
+    /// New IPC endpoint at the given path
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the path can't be used for an endpoint on
+    /// this platform, such as a socket path that's too long or a pipe path that doesn't start
+    /// with `\\.\pipe\`. Resolve the path with a [`ShortenResolver`] to avoid failing when
+    /// the path is too long. Errors are reported as [`Error::Bind`].
+    pub fn new(path: impl IntoIpcPath, on_conflict: OnConflict) -> Result<Self, Error> {
+        // The path isn't known if it can't be resolved
+        let path = path.into_ipc_path().map_err(Error::bind(PathBuf::new()))?;
+        let inner =
+            platform::Endpoint::new(path.clone(), on_conflict).map_err(Error::bind(path))?;
+        Ok(Self::from_inner(inner))
+    }
+
+    fn from_inner(inner: platform::Endpoint) -> Self {
+        Self {
+            inner,
+            token_auth: None,
+            peer_filter: None,
+            metrics: None,
+            idle_timeout: None,
+            rate_limit: None,
+            pid_file: None,
+            on_ready: None,
+        }
+    }
+
+    /// Start building an IPC endpoint at the given path.
+    pub fn builder(path: impl IntoIpcPath, on_conflict: OnConflict) -> EndpointBuilder {
+        EndpointBuilder::new(path, on_conflict)
+    }
+
+    /// Endpoint for a listening socket that was passed to this process with
+    /// [`IpcStream::hand_off`].
+    ///
+    /// Returns `None` if the process wasn't started that way, in which case the server should
+    /// bind a new endpoint instead. The environment variables used for the handoff are removed so
+    /// they aren't passed on to child processes. Modifying the environment isn't thread-safe, so
+    /// this must be called before the process starts any threads, including the ones of a
+    /// multi-threaded tokio runtime.
+    ///
+    /// [`incoming`](Endpoint::incoming) uses the inherited socket instead of binding, so options
+    /// that apply when binding, like security attributes and buffer sizes, have no effect. If the
+    /// parent held a lock file, the lock is taken over as well. Options for accepted connections
+    /// still apply.
+    #[cfg(unix)]
+    pub fn from_inherited() -> io::Result<Option<Self>> {
+        Ok(platform::Endpoint::from_inherited()?.map(Self::from_inner))
+    }
+}
+
+/// Builder for an [`Endpoint`].
+///
+/// Options that only exist on one platform are set through the `EndpointBuilderExt` traits in
+/// the [`os`] module.
+pub struct EndpointBuilder {
+    // Errors from creating the endpoint are deferred until `build` so the options can be chained
+    endpoint: Result<Endpoint, Error>,
+}
+
+impl EndpointBuilder {
+    /// Start building an IPC endpoint at the given path.
+    pub fn new(path: impl IntoIpcPath, on_conflict: OnConflict) -> Self {
+        Self {
+            endpoint: Endpoint::new(path, on_conflict),
+        }
+    }
+
+    pub(crate) fn with(mut self, f: impl FnOnce(&mut Endpoint)) -> Self {
+        if let Ok(endpoint) = &mut self.endpoint {
+            f(endpoint);
+        }
+        self
+    }
+
+    /// Security attributes for the endpoint. See [`Endpoint::set_security_attributes`].
+    pub fn security_attributes(self, security_attributes: SecurityAttributes) -> Self {
+        self.with(|e| e.set_security_attributes(security_attributes))
+    }
+
+    /// Direction data can flow over connections. See [`Endpoint::set_direction`].
+    pub fn direction(self, direction: Direction) -> Self {
+        self.with(|e| e.set_direction(direction))
+    }
+
+    /// Size of the buffer used for data received by the server. See
+    /// [`Endpoint::set_in_buffer_size`].
+    pub fn in_buffer_size(self, size: u32) -> Self {
+        self.with(|e| e.set_in_buffer_size(size))
+    }
+
+    /// Size of the buffer used for data sent by the server. See
+    /// [`Endpoint::set_out_buffer_size`].
+    pub fn out_buffer_size(self, size: u32) -> Self {
+        self.with(|e| e.set_out_buffer_size(size))
+    }
+
+    /// Token clients must present. See [`Endpoint::set_token_auth`].
+    pub fn token_auth(self, token_auth: TokenAuth) -> Self {
+        self.with(|e| e.set_token_auth(token_auth))
+    }
+
+    /// Filter for allowed peers. See [`Endpoint::set_peer_filter`].
+    pub fn peer_filter(self, peer_filter: PeerFilter) -> Self {
+        self.with(|e| e.set_peer_filter(peer_filter))
+    }
+
+    /// Metrics for the endpoint and its connections. See [`Endpoint::set_metrics`].
+    pub fn metrics(self, metrics: Arc<dyn IpcMetrics>) -> Self {
+        self.with(|e| e.set_metrics(metrics))
+    }
+
+    /// Timeout for idle connections. See [`Endpoint::set_idle_timeout`].
+    pub fn idle_timeout(self, timeout: Duration) -> Self {
+        self.with(|e| e.set_idle_timeout(timeout))
+    }
+
+    /// Limit on how quickly connections are accepted. See [`Endpoint::set_accept_rate_limit`].
+    pub fn accept_rate_limit(self, rate_limit: RateLimit) -> Self {
+        self.with(|e| e.set_accept_rate_limit(rate_limit))
+    }
+
+    /// File to write the pid of the server to. See [`Endpoint::set_pid_file`].
+    pub fn pid_file(self, path: impl Into<PathBuf>) -> Self {
+        self.with(|e| e.set_pid_file(path))
+    }
+
+    /// Function to call once the endpoint is ready. See [`Endpoint::set_on_ready`].
+    pub fn on_ready(self, on_ready: impl FnOnce() + Send + 'static) -> Self {
+        self.with(|e| e.set_on_ready(on_ready))
+    }
+
+    /// Create the endpoint.
+    ///
+    /// Fails if the path is invalid or can't be prepared according to the [`OnConflict`] setting.
+    pub fn build(self) -> Result<Endpoint, Error> {
+        self.endpoint
+    }
+
+    /// Create the endpoint and start accepting connections. Shorthand for calling
+    /// [`Endpoint::incoming`] on the result of [`EndpointBuilder::build`].
+    pub fn incoming(self) -> Result<IpcStream, Error> {
+        self.build()?.incoming()
+    }
+}
+
+async fn connect_with_retry(
+    path: PathBuf,
+    options: &ConnectOptions,
+) -> io::Result<platform::Connection> {
+    let mut attempts = 0;
+    let mut interval = options.poll_interval;
+    loop {
+        match platform::Endpoint::connect(path.clone(), options).await {
+            Err(e) if attempts < options.retry_attempts && is_not_listening(&e) => {
+                attempts += 1;
+                debug!(?interval, attempts, "no server is listening, retrying");
+                tokio::time::sleep(interval).await;
+                interval = options.backoff.next_interval(interval);
+            }
+            res => return res,
+        }
+    }
+}
+
+fn is_not_listening(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+    )
+}
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(0);
+
+/// IPC connection.
+pub struct Connection {
+    inner: platform::Connection,
+    id: u64,
+    metrics: Option<Arc<ConnectionMetrics>>,
+    stats: Arc<StatsTracker>,
+    idle: Option<IdleTimer>,
+    active: Option<Arc<ActiveGuard>>,
+}
+
+impl Connection {
+    fn new(inner: platform::Connection) -> Self {
+        Self {
+            inner,
+            id: NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed),
+            metrics: None,
+            stats: Arc::new(StatsTracker::new()),
+            idle: None,
+            active: None,
+        }
+    }
+
+    /// Identifier of the connection, unique within the current process.
+    ///
+    /// Ids are assigned in increasing order as connections are accepted or established, which
+    /// makes them useful for correlating log messages and metrics.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the number of bytes transferred over the connection and when it was last used.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns the underlying [`UnixStream`](tokio::net::UnixStream) so platform-specific APIs
+    /// can be used.
+    ///
+    /// Metrics and idle timeouts configured for the connection no longer apply to the returned
+    /// stream.
+    #[cfg(unix)]
+    pub fn into_inner(self) -> tokio::net::UnixStream {
+        self.inner
+    }
+
+    /// Returns the underlying named pipe so platform-specific APIs can be used.
+    ///
+    /// Metrics and idle timeouts configured for the connection no longer apply to the returned
+    /// pipe. Any data buffered by [`Connection::peek`] that hasn't been read yet is discarded,
+    /// since it has already been taken out of the pipe.
+    #[cfg(windows)]
+    pub fn into_inner(self) -> NamedPipe {
+        self.inner.into_inner()
+    }
+
+    /// Create a stream from an existing [`UnixStream`](std::os::unix::net::UnixStream).
+    #[cfg(unix)]
+    pub async fn from_std_stream(stream: std::os::unix::net::UnixStream) -> io::Result<Self> {
+        Ok(Self::new(platform::from_std_stream(stream).await?))
+    }
+
+    /// Retrieves the credentials of the process on the other end of the connection.
+    ///
+    /// On Linux, this uses `SO_PEERCRED` and returns the peer's uid, gid, and pid.
+    ///
+    /// On macOS and the BSDs, this uses `getpeereid` to get the peer's uid and gid. The pid is
+    /// also available on macOS, where it's retrieved with `LOCAL_PEERPID`.
+    ///
+    /// On Windows, only the process id is available. Server-side connections return the client's
+    /// process id and client-side connections return the server's process id.
+    pub fn peer_credentials(&self) -> io::Result<PeerCredentials> {
+        platform::peer_credentials(&self.inner)
+    }
+
+    /// Retrieves the process id and executable path of the process on the other end of the
+    /// connection, e.g. for audit logging.
+    ///
+    /// The executable path is best-effort: it's read from `/proc` on Linux and from
+    /// `QueryFullProcessImageNameW` on Windows, and may be `None` if the current process lacks
+    /// permission to inspect the peer. An error is only returned if the peer's process id can't be
+    /// determined.
+    pub fn peer_process(&self) -> io::Result<PeerProcess> {
+        platform::peer_process(&self.inner)
+    }
+
+    /// Returns the label that the active Linux security module assigned to the peer, such as its
+    /// security context or confinement profile, retrieved with `SO_PEERSEC`.
+    ///
+    /// Fails with [`io::ErrorKind::Unsupported`] if no security module that labels sockets is
+    /// active.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn peer_security_label(&self) -> io::Result<String> {
+        platform::peer_security_label(&self.inner)
+    }
+
+    /// Wraps the connection in a [`MessageConnection`] that sends and receives length-delimited
+    /// messages.
+    #[cfg(feature = "tokio-util")]
+    pub fn into_messages(self) -> MessageConnection {
+        MessageConnection::new(self)
+    }
+
+    /// Wraps the connection in a [`JsonRpcConnection`] that sends and receives JSON-RPC
+    /// messages.
+    #[cfg(feature = "jsonrpc")]
+    pub fn into_json_rpc(self) -> JsonRpcConnection {
+        JsonRpcConnection::new(self)
+    }
+
+    /// Wraps the connection in a [`HeartbeatConnection`] that pings the peer to detect when it
+    /// stops responding.
+    #[cfg(feature = "tokio-util")]
+    pub fn into_heartbeat(self, heartbeat: Heartbeat) -> HeartbeatConnection {
+        HeartbeatConnection::new(self, heartbeat)
+    }
+
+    /// Wraps the connection with read and write buffers of the given sizes in bytes.
+    ///
+    /// This cuts down on system calls when reading or writing many small pieces of data, which is
+    /// especially slow with named pipes. Written data stays in the buffer until it's full or the
+    /// stream is flushed, so call `flush` once a message is complete.
+    pub fn buffered(
+        self,
+        read_capacity: usize,
+        write_capacity: usize,
+    ) -> tokio::io::BufStream<Self> {
+        tokio::io::BufStream::with_capacity(read_capacity, write_capacity, self)
+    }
+
+    /// Splits the connection into owned read and write halves so they can be moved into separate
+    /// tasks.
+    pub fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+        let (read, write) = platform::into_split(self.inner);
+        (
+            OwnedReadHalf {
+                inner: read,
+                metrics: self.metrics.clone(),
+                stats: self.stats.clone(),
+                idle: self.idle.as_ref().map(IdleTimer::split),
+                _active: self.active.clone(),
+            },
+            OwnedWriteHalf {
+                inner: write,
+                metrics: self.metrics,
+                stats: self.stats,
+                idle: self.idle,
+                _active: self.active,
+            },
+        )
+    }
+
+    /// Sends a file descriptor to the peer, which must receive it with
+    /// [`Connection::recv_handle`].
+    ///
+    /// The descriptor is passed using `SCM_RIGHTS` along with a single byte of data, so the
+    /// call must line up with a corresponding `recv_handle` call on the other end of the stream.
+    #[cfg(unix)]
+    pub async fn send_handle(&mut self, fd: std::os::fd::BorrowedFd<'_>) -> io::Result<()> {
+        platform::send_handle(&mut self.inner, fd).await
+    }
+
+    /// Sends a handle to the peer, which must receive it with [`Connection::recv_handle`].
+    ///
+    /// The current process id and the handle's value are written to the stream, and the peer
+    /// duplicates the handle out of this process. This waits for the peer to confirm that it has
+    /// done so, so the call must line up with a corresponding `recv_handle` call on the other end.
+    /// The handle can be closed once this returns.
+    #[cfg(windows)]
+    pub async fn send_handle(
+        &mut self,
+        handle: std::os::windows::io::BorrowedHandle<'_>,
+    ) -> io::Result<()> {
+        platform::send_handle(&mut self.inner, handle).await
+    }
+
+    /// Receives a file descriptor sent with [`Connection::send_handle`].
+    #[cfg(unix)]
+    pub async fn recv_handle(&mut self) -> io::Result<std::os::fd::OwnedFd> {
+        platform::recv_handle(&mut self.inner).await
+    }
+
+    /// Receives a handle sent with [`Connection::send_handle`].
+    ///
+    /// The handle is duplicated out of the process on the other end of the pipe, which requires
+    /// `PROCESS_DUP_HANDLE` access to it. Fails if the sent value isn't a valid handle in that
+    /// process.
+    #[cfg(windows)]
+    pub async fn recv_handle(&mut self) -> io::Result<std::os::windows::io::OwnedHandle> {
+        platform::recv_handle(&mut self.inner).await
+    }
+
+    /// Reads data from the connection directly into the spare capacity of `buf`, returning the
+    /// number of bytes read.
+    ///
+    /// A return value of 0 means either that the peer closed the connection or that `buf` has no
+    /// remaining capacity.
+    #[cfg(feature = "bytes")]
+    pub async fn read_buf<B: bytes::BufMut>(&mut self, buf: &mut B) -> io::Result<usize> {
+        std::future::poll_fn(|cx| self.poll_read_buf(cx, buf)).await
+    }
+
+    /// Polling variant of [`Connection::read_buf`], for use in manual
+    /// [`AsyncRead`] or [`Stream`] implementations.
+    #[cfg(feature = "bytes")]
+    pub fn poll_read_buf<B: bytes::BufMut>(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut B,
+    ) -> Poll<io::Result<usize>> {
+        if !buf.has_remaining_mut() {
+            return Poll::Ready(Ok(0));
+        }
+        let read = {
+            let dst = buf.chunk_mut();
+            // Safety: the slice is only handed to `ReadBuf`, which never de-initializes memory
+            let mut read_buf = ReadBuf::uninit(unsafe { dst.as_uninit_slice_mut() });
+            let ptr = read_buf.filled().as_ptr();
+            std::task::ready!(Pin::new(&mut *self).poll_read(cx, &mut read_buf))?;
+            // Make sure the reader didn't swap out the buffer
+            assert_eq!(ptr, read_buf.filled().as_ptr());
+            read_buf.filled().len()
+        };
+        // Safety: `read` bytes were just initialized by the read above
+        unsafe { buf.advance_mut(read) };
+        Poll::Ready(Ok(read))
+    }
+
+    /// Returns the Terminal Services session id of the process on the other end of the pipe.
+    ///
+    /// Server-side connections return the client's session and client-side connections return
+    /// the server's session. Each interactive logon gets its own session, so this can be used to
+    /// tell apart clients run by different users on the same machine.
+    #[cfg(windows)]
+    pub fn peer_session_id(&self) -> io::Result<u32> {
+        platform::peer_session_id(&self.inner)
+    }
+
+    /// Returns the configuration and current state of the named pipe, as reported by
+    /// `GetNamedPipeInfo` and `GetNamedPipeHandleStateW`.
+    #[cfg(windows)]
+    pub fn pipe_info(&self) -> io::Result<PipeInfo> {
+        platform::pipe_info(&self.inner)
+    }
+
+    /// Impersonates the client on the current thread until the returned guard is dropped, so
+    /// the server can access resources using the client's identity.
+    ///
+    /// Only server-side connections can impersonate, and the server must read from the pipe
+    /// before impersonating. Impersonation applies to the calling thread, so the guard can't be
+    /// held across an `.await` in a multithreaded runtime. The level of access available depends
+    /// on the impersonation level the client allowed when connecting.
+    #[cfg(windows)]
+    pub fn impersonate(&self) -> io::Result<ImpersonationGuard<'_>> {
+        platform::impersonate(&self.inner)?;
+        Ok(ImpersonationGuard {
+            _conn: std::marker::PhantomData,
+        })
+    }
+
+    /// Waits for any of the requested kinds of readiness, returning the ones that are ready.
+    ///
+    /// This is meant for readiness-driven loops that use [`Connection::try_read`] and
+    /// [`Connection::try_write`] rather than awaiting each operation. Readiness can be a false
+    /// positive, in which case the next operation fails with [`io::ErrorKind::WouldBlock`].
+    pub async fn ready(&self, interest: tokio::io::Interest) -> io::Result<tokio::io::Ready> {
+        self.inner.ready(interest).await
+    }
+
+    /// Waits until the connection may be readable. See [`Connection::ready`].
+    pub async fn readable(&self) -> io::Result<()> {
+        self.inner.readable().await
+    }
+
+    /// Waits until the connection may be writable. See [`Connection::ready`].
+    pub async fn writable(&self) -> io::Result<()> {
+        self.inner.writable().await
+    }
+
+    /// Reads data into `buf` without waiting, returning the number of bytes read.
+    ///
+    /// Fails with [`io::ErrorKind::WouldBlock`] if no data is available yet. A return value of 0
+    /// means the peer closed the connection. Usually paired with [`Connection::readable`].
+    pub fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.try_read(buf)?;
+        self.add_bytes_read(len);
+        Ok(len)
+    }
+
+    /// Writes data from `buf` without waiting, returning the number of bytes written.
+    ///
+    /// Fails with [`io::ErrorKind::WouldBlock`] if the connection isn't ready for writing.
+    /// Usually paired with [`Connection::writable`].
+    pub fn try_write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.try_write(buf)?;
+        self.add_bytes_written(len);
+        Ok(len)
+    }
+
+    // Records I/O that bypassed `poll_read`
+    fn add_bytes_read(&self, bytes: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.add_bytes_read(bytes);
+        }
+        self.stats.add_bytes_read(bytes);
+        if let Some(idle) = &self.idle {
+            idle.record_activity();
+        }
+    }
+
+    // Records I/O that bypassed `poll_write`
+    fn add_bytes_written(&self, bytes: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.add_bytes_written(bytes);
+        }
+        self.stats.add_bytes_written(bytes);
+        if let Some(idle) = &self.idle {
+            idle.record_activity();
+        }
+    }
+
+    /// Reads data from the connection without removing it, so the same data is returned by the
+    /// next read. This lets a server look at the start of a connection to decide how to handle
+    /// it, such as telling HTTP requests apart from a custom protocol.
+    ///
+    /// Waits until data is available and returns the number of bytes copied into `buf`, which is
+    /// 0 if the peer closed the connection. On Unix, this uses `MSG_PEEK`, so each call returns
+    /// all of the data that has arrived, up to the size of `buf`. On Windows, the data is kept in
+    /// a buffer inside the connection until it's read, and later calls only return the data
+    /// from the first one. Peeked data that hasn't been read is lost when the connection is
+    /// converted with [`Connection::into_inner`].
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        platform::peek(&mut self.inner, buf).await
+    }
+
+    /// Forwards up to `len` bytes read from this connection to `other`, returning the number of
+    /// bytes forwarded. Fewer than `len` bytes are forwarded if this connection reaches EOF.
+    ///
+    /// On Linux, data is moved between the sockets with `splice(2)` so it's never copied into
+    /// userspace. Other platforms fall back to a buffered copy. Either way, the bytes count
+    /// towards both connections' metrics and stats and keep their idle timers from expiring.
+    ///
+    /// Data that has been read from this connection but not yet written to `other` is lost if
+    /// the future is dropped before it completes.
+    pub async fn splice_to(&mut self, other: &mut Self, len: usize) -> io::Result<usize> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            let pipe = platform::SplicePipe::new()?;
+            let mut total = 0;
+            while total < len {
+                let filled = pipe.fill(&mut self.inner, len - total).await?;
+                if filled == 0 {
+                    break;
+                }
+                self.add_bytes_read(filled);
+                pipe.drain(&mut other.inner, filled).await?;
+                other.add_bytes_written(filled);
+                total += filled;
+            }
+            Ok(total)
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            use tokio::io::AsyncReadExt;
+
+            let copied = tokio::io::copy(&mut (&mut *self).take(len as u64), other).await?;
+            Ok(copied as usize)
+        }
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsFd for Connection {
+    fn as_fd(&self) -> std::os::fd::BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for Connection {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsHandle for Connection {
+    fn as_handle(&self) -> std::os::windows::io::BorrowedHandle<'_> {
+        self.inner.as_handle()
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawHandle for Connection {
+    fn as_raw_handle(&self) -> std::os::windows::io::RawHandle {
+        self.inner.as_raw_handle()
+    }
+}
+
+/// Reverts the current thread to its own identity when dropped, created by
+/// [`Connection::impersonate`].
+///
+/// If reverting fails, the process is aborted rather than continuing to run with the client's
+/// identity.
+#[cfg(windows)]
+pub struct ImpersonationGuard<'a> {
+    // Impersonation is tied to the current thread, so the guard must not move between threads
+    _conn: std::marker::PhantomData<(&'a Connection, *const ())>,
+}
+
+#[cfg(windows)]
+impl Drop for ImpersonationGuard<'_> {
+    fn drop(&mut self) {
+        platform::revert_to_self();
+    }
+}
+
+/// Credentials of the process on the other end of a [`Connection`].
+///
+/// Fields that can't be retrieved on the current platform are set to `None`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerCredentials {
+    uid: Option<u32>,
+    gid: Option<u32>,
+    pid: Option<u32>,
+}
+
+impl PeerCredentials {
+    /// User id of the peer process.
+    pub fn uid(&self) -> Option<u32> {
+        self.uid
+    }
+
+    /// Group id of the peer process.
+    pub fn gid(&self) -> Option<u32> {
+        self.gid
+    }
+
+    /// Process id of the peer process.
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+}
+
+/// Process on the other end of a [`Connection`], returned from [`Connection::peer_process`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerProcess {
+    pid: u32,
+    exe: Option<PathBuf>,
+}
+
+impl PeerProcess {
+    fn new(pid: u32, exe: Option<PathBuf>) -> Self {
+        Self { pid, exe }
+    }
+
+    /// Process id of the peer process.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Full path to the executable of the peer process, if it could be retrieved.
+    pub fn exe(&self) -> Option<&Path> {
+        self.exe.as_deref()
+    }
+
+    /// File name of the executable of the peer process, if it could be retrieved.
+    pub fn name(&self) -> Option<String> {
+        self.exe()?
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+}
+
+/// Information about an accepted [`Connection`], yielded from [`IpcStreamWithInfo`].
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    peer_addr: Option<PathBuf>,
+    pid: Option<u32>,
+    accepted_at: SystemTime,
+}
+
+impl ConnectionInfo {
+    fn new(conn: &Connection) -> Self {
+        Self {
+            peer_addr: platform::peer_addr(&conn.inner),
+            pid: conn.peer_credentials().ok().and_then(|creds| creds.pid()),
+            accepted_at: SystemTime::now(),
+        }
+    }
+
+    /// Path the peer socket is bound to.
+    ///
+    /// Client sockets are usually unnamed, so this is typically `None`. This is always `None` on
+    /// Windows.
+    pub fn peer_addr(&self) -> Option<&Path> {
+        self.peer_addr.as_deref()
+    }
+
+    /// Process id of the peer process, if it's available on the current platform.
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    /// Time the connection was accepted.
+    pub fn accepted_at(&self) -> SystemTime {
+        self.accepted_at
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        let filled = buf.filled().len();
+        let mut res = Pin::new(&mut this.inner).poll_read(ctx, buf);
+        if let Some(idle) = &mut this.idle {
+            res = idle.check(ctx, res);
+        }
+        if let Some(metrics) = &this.metrics {
+            metrics.record_read(&res, buf.filled().len() - filled);
+        }
+        this.stats.record_read(&res, buf.filled().len() - filled);
+        // A successful read that doesn't fill any bytes means the peer closed the connection
+        #[cfg(feature = "tracing")]
+        if matches!(res, Poll::Ready(Ok(()))) && buf.filled().len() == filled && buf.remaining() > 0
+        {
+            trace!(id = this.id, "connection closed by peer");
+        }
+        res
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        let mut res = Pin::new(&mut this.inner).poll_write(ctx, buf);
+        if let Some(idle) = &mut this.idle {
+            res = idle.check(ctx, res);
+        }
+        if let Some(metrics) = &this.metrics {
+            metrics.record_write(&res);
+        }
+        this.stats.record_write(&res);
+        res
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        let mut res = Pin::new(&mut this.inner).poll_write_vectored(ctx, bufs);
+        if let Some(idle) = &mut this.idle {
+            res = idle.check(ctx, res);
+        }
+        if let Some(metrics) = &this.metrics {
+            metrics.record_write(&res);
+        }
+        this.stats.record_write(&res);
+        res
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner).poll_flush(ctx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        let res = Pin::new(&mut this.inner).poll_shutdown(ctx);
+        if let Poll::Ready(Ok(())) = res {
+            trace!(id = this.id, "connection shut down");
+        }
+        res
+    }
+}
+
+/// Owned read half of a [`Connection`], created by [`Connection::into_split`].
+pub struct OwnedReadHalf {
+    inner: platform::OwnedReadHalf,
+    metrics: Option<Arc<ConnectionMetrics>>,
+    stats: Arc<StatsTracker>,
+    idle: Option<IdleTimer>,
+    _active: Option<Arc<ActiveGuard>>,
+}
+
+impl OwnedReadHalf {
+    /// Returns the statistics of the connection, which are shared with the other half.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.snapshot()
+    }
+}
+
+impl AsyncRead for OwnedReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        let filled = buf.filled().len();
+        let mut res = Pin::new(&mut this.inner).poll_read(ctx, buf);
+        if let Some(idle) = &mut this.idle {
+            res = idle.check(ctx, res);
+        }
+        if let Some(metrics) = &this.metrics {
+            metrics.record_read(&res, buf.filled().len() - filled);
+        }
+        this.stats.record_read(&res, buf.filled().len() - filled);
+        res
+    }
+}
+
+/// Owned write half of a [`Connection`], created by [`Connection::into_split`].
+pub struct OwnedWriteHalf {
+    inner: platform::OwnedWriteHalf,
+    metrics: Option<Arc<ConnectionMetrics>>,
+    stats: Arc<StatsTracker>,
+    idle: Option<IdleTimer>,
+    _active: Option<Arc<ActiveGuard>>,
+}
+
+impl OwnedWriteHalf {
+    /// Returns the statistics of the connection, which are shared with the other half.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.snapshot()
+    }
+}
+
+impl AsyncWrite for OwnedWriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        let mut res = Pin::new(&mut this.inner).poll_write(ctx, buf);
+        if let Some(idle) = &mut this.idle {
+            res = idle.check(ctx, res);
+        }
+        if let Some(metrics) = &this.metrics {
+            metrics.record_write(&res);
+        }
+        this.stats.record_write(&res);
+        res
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        let mut res = Pin::new(&mut this.inner).poll_write_vectored(ctx, bufs);
+        if let Some(idle) = &mut this.idle {
+            res = idle.check(ctx, res);
+        }
+        if let Some(metrics) = &this.metrics {
+            metrics.record_write(&res);
+        }
+        this.stats.record_write(&res);
+        res
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner).poll_flush(ctx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner).poll_shutdown(ctx)
+    }
+}
+
+/// Stream of incoming connections.
+pub struct IpcStream {
+    inner: Option<platform::IpcStream>,
+    // Not known for listeners created from an existing socket
+    path: Option<PathBuf>,
+    shutdown: Arc<Shutdown>,
+    token_auth: Option<TokenAuth>,
+    peer_filter: Option<PeerFilter>,
+    metrics: Option<MetricsHandle>,
+    idle_timeout: Option<Duration>,
+    rate_limit: Option<TokenBucket>,
+    handshakes: FuturesUnordered<BoxFuture<'static, io::Result<Connection>>>,
+    #[cfg(feature = "tokio-util")]
+    cancellation: Option<Pin<Box<tokio_util::sync::WaitForCancellationFutureOwned>>>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl IpcStream {
+    fn new(inner: platform::IpcStream) -> Self {
+        let shutdown = Arc::new(Shutdown {
+            requested: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+            cleanup: inner.cleanup(),
+            pid_file: Mutex::new(None),
+            active: AtomicUsize::new(0),
+            idle: tokio::sync::Notify::new(),
+        });
+        Self {
+            inner: Some(inner),
+            path: None,
+            shutdown,
+            token_auth: None,
+            peer_filter: None,
+            metrics: None,
+            idle_timeout: None,
+            rate_limit: None,
+            handshakes: FuturesUnordered::new(),
+            #[cfg(feature = "tokio-util")]
+            cancellation: None,
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::none(),
+        }
+    }
+
+    /// Create a listener from an existing [`UnixListener`](std::os::unix::net::UnixListener).
+    #[cfg(unix)]
+    pub fn from_std_listener(listener: std::os::unix::net::UnixListener) -> io::Result<Self> {
+        Ok(Self::new(platform::IpcStream::from_std_listener(listener)?))
+    }
+
+    /// Create a listener from an existing tokio [`UnixListener`](tokio::net::UnixListener),
+    /// e.g. one created by another library.
+    ///
+    /// If `path` is given, the socket file at that path is removed when the listener is dropped
+    /// or shut down, the same as for listeners created by [`Endpoint::incoming`], and errors
+    /// include the path.
+    #[cfg(unix)]
+    pub fn from_tokio_listener(listener: tokio::net::UnixListener, path: Option<PathBuf>) -> Self {
+        let mut stream = Self::new(platform::IpcStream::from_tokio_listener(
+            listener,
+            path.clone(),
+        ));
+        stream.path = path;
+        stream
+    }
+
+    /// Create a listener bound to a unique name in the abstract socket namespace, which the
+    /// kernel picks when binding.
+    ///
+    /// This is useful for short-lived endpoints, such as a channel for replies, since nothing is
+    /// created in the file system and the caller doesn't have to come up with a unique name.
+    /// Use [`IpcStream::abstract_name`] to get the name to hand to clients, which connect with
+    /// [`Endpoint::connect_abstract`]. Abstract sockets don't have file permissions, so any
+    /// process in the same network namespace can connect.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn autobind() -> io::Result<Self> {
+        let stream = Self::from_std_listener(platform::autobind()?)?;
+        debug!(name = ?stream.abstract_name(), "bound abstract endpoint");
+        Ok(stream)
+    }
+
+    /// Name of the abstract socket the listener is bound to, without the leading null byte.
+    ///
+    /// Returns `None` if the listener is bound to a path or it was shut down.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn abstract_name(&self) -> Option<Vec<u8>> {
+        self.inner.as_ref()?.abstract_name().ok().flatten()
+    }
+
+    /// Create a listener from a socket activated by launchd.
+    ///
+    /// `name` is the key of the socket in the `Sockets` dictionary of the job's property list.
+    /// If launchd provides multiple sockets for the key, only the first one is used.
+    #[cfg(target_os = "macos")]
+    pub fn from_launchd(name: &str) -> io::Result<Self> {
+        Ok(Self::new(platform::IpcStream::from_launchd(name)?))
+    }
+
+    /// Spawns a child process with `command` and passes the listening socket to it, which the
+    /// child picks up with [`Endpoint::from_inherited`]. This allows a server to be upgraded
+    /// without closing the socket.
+    ///
+    /// Both processes share the socket until this listener is dropped, and clients that haven't
+    /// been accepted yet stay queued until one of them accepts, so none are lost. Once the child
+    /// is ready, stop accepting here with [`ShutdownHandle::shutdown`]. After the child has been
+    /// spawned, it owns the socket file, so the file is no longer removed when this listener is
+    /// dropped. The lock file set up with `Endpoint::set_lock_file` is passed to the child too,
+    /// so the lock stays held after this process exits.
+    ///
+    /// Fails if the listener wasn't created by [`Endpoint::incoming`], was shut down, or the
+    /// child couldn't be spawned.
+    #[cfg(unix)]
+    pub fn hand_off(&self, command: &mut std::process::Command) -> io::Result<std::process::Child> {
+        let (Some(inner), Some(path)) = (&self.inner, &self.path) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "listener has no socket path or was shut down",
+            ));
+        };
+        inner.hand_off(path, command)
+    }
+
+    /// Returns a handle that can be used to stop accepting connections from another task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(self.shutdown.clone())
+    }
+
+    /// Removes the socket file, even if the endpoint was configured to keep it with
+    /// `Endpoint::set_remove_on_drop`. Does nothing if the file was already removed or on Windows,
+    /// where named pipes are removed automatically once all handles are closed.
+    ///
+    /// Connections that were already accepted aren't affected, but new clients won't be able to
+    /// find the socket.
+    pub fn cleanup(&self) {
+        self.shutdown.cleanup.remove();
+    }
+
+    fn accepted(&self, mut conn: Connection) -> Connection {
+        trace!(id = conn.id(), "accepted connection");
+        if let Some(metrics) = &self.metrics {
+            conn.metrics = Some(metrics.accepted(conn.id));
+        }
+        conn.idle = self.idle_timeout.map(IdleTimer::new);
+        self.shutdown.active.fetch_add(1, Ordering::SeqCst);
+        conn.active = Some(Arc::new(ActiveGuard(self.shutdown.clone())));
+        conn
+    }
+}
+
+impl Stream for IpcStream {
+    type Item = io::Result<Connection>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = Pin::into_inner(self);
+        #[cfg(feature = "tracing")]
+        let _span = this.span.clone().entered();
+        this.shutdown.waker.register(cx.waker());
+        #[cfg(feature = "tokio-util")]
+        if let Some(cancellation) = &mut this.cancellation {
+            if std::future::Future::poll(cancellation.as_mut(), cx).is_ready() {
+                this.cancellation = None;
+                this.shutdown.request();
+            }
+        }
+        if this.shutdown.requested.load(Ordering::SeqCst) {
+            // Dropping the platform stream closes the listener
+            if this.inner.take().is_some() {
+                debug!("stopped accepting connections");
+            }
+            this.handshakes.clear();
+        }
+        while let Some(inner) = &mut this.inner {
+            if let Some(rate_limit) = &mut this.rate_limit {
+                if rate_limit.poll_ready(cx).is_pending() {
+                    break;
+                }
+            }
+            let conn = match Pin::new(inner).poll_next(cx) {
+                Poll::Ready(Some(Ok(conn))) => {
+                    if let Some(rate_limit) = &mut this.rate_limit {
+                        rate_limit.consume();
+                    }
+                    Connection::new(conn)
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    debug!(error = ?e, "failed to accept connection");
+                    if let Some(metrics) = &this.metrics {
+                        metrics.accept_error(&e);
+                    }
+                    let e = match &this.path {
+                        Some(path) => Error::accept(path)(e).into(),
+                        None => e,
+                    };
+                    return Poll::Ready(Some(Err(e)));
+                }
+                Poll::Ready(None) => {
+                    this.inner = None;
+                    break;
+                }
+                Poll::Pending => break,
+            };
+            if let Some(peer_filter) = &this.peer_filter {
+                if !peer_filter.allows(&conn) {
+                    trace!("rejecting connection from disallowed peer");
+                    continue;
+                }
+            }
+            match &this.token_auth {
+                Some(token_auth) => {
+                    let token_auth = token_auth.clone();
+                    this.handshakes
+                        .push(Box::pin(async move { token_auth.verify(conn).await }));
+                }
+                None => return Poll::Ready(Some(Ok(this.accepted(conn)))),
+            }
+        }
+        loop {
+            match this.handshakes.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(conn))) => return Poll::Ready(Some(Ok(this.accepted(conn)))),
+                Poll::Ready(Some(Err(e))) => {
+                    trace!("dropping connection that failed authentication: {e:?}");
+                }
+                Poll::Ready(None) if this.inner.is_none() => return Poll::Ready(None),
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+impl Drop for IpcStream {
+    fn drop(&mut self) {
+        self.shutdown.remove_pid_file();
+    }
+}
+
+/// Stream of incoming connections along with information about each peer, created by
+/// [`Endpoint::incoming_with_info`].
+pub struct IpcStreamWithInfo(IpcStream);
+
+impl IpcStreamWithInfo {
+    /// Returns a handle that can be used to stop accepting connections from another task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.0.shutdown_handle()
+    }
+}
+
+impl Stream for IpcStreamWithInfo {
+    type Item = io::Result<(Connection, ConnectionInfo)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.get_mut().0)
+            .poll_next(cx)
+            .map_ok(|conn| {
+                let info = ConnectionInfo::new(&conn);
+                (conn, info)
+            })
+    }
+}
+
+struct Shutdown {
+    requested: AtomicBool,
+    waker: AtomicWaker,
+    cleanup: platform::Cleanup,
+    pid_file: Mutex<Option<PidFile>>,
+    // Number of accepted connections that are still open
+    active: AtomicUsize,
+    idle: tokio::sync::Notify,
+}
+
+impl Shutdown {
+    fn request(&self) {
+        self.stop();
+        self.cleanup.run();
+        self.remove_pid_file();
+    }
+
+    fn set_pid_file(&self, pid_file: PidFile) {
+        *self.pid_file.lock().unwrap_or_else(PoisonError::into_inner) = Some(pid_file);
+    }
+
+    fn remove_pid_file(&self) {
+        // Dropping the pid file removes it
+        self.pid_file
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+    }
+
+    fn stop(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+        self.waker.wake();
+    }
+
+    async fn wait_idle(&self) {
+        loop {
+            // Notified futures receive notifications as soon as they're created, so there's no
+            // race between checking the count and waiting
+            let idle = self.idle.notified();
+            if self.active.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+// Held by each accepted connection, and shared between its halves once it's split
+struct ActiveGuard(Arc<Shutdown>);
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        if self.0.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// Handle for stopping an [`IpcStream`], created by [`IpcStream::shutdown_handle`].
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<Shutdown>);
+
+impl ShutdownHandle {
+    /// Stops accepting new connections.
+    ///
+    /// Any task waiting on the stream is woken up and the stream will end. On Unix, the socket
+    /// file is removed before this method returns unless this was disabled with
+    /// `Endpoint::set_remove_on_drop`. Connections that were already accepted are not affected.
+    pub fn shutdown(&self) {
+        self.0.request();
+    }
+
+    /// Stops accepting new connections and waits up to `timeout` for the connections that were
+    /// accepted from the stream to close. On Unix, the socket file is removed afterwards rather
+    /// than right away.
+    ///
+    /// Fails with [`io::ErrorKind::TimedOut`] if connections are still open once the timeout
+    /// elapses, but the socket file is removed either way. Connections are tracked until they're
+    /// dropped, including both halves of a split connection. Connections that were converted with
+    /// `Connection::into_inner` are no longer tracked.
+    pub async fn drain(&self, timeout: Duration) -> io::Result<()> {
+        // Keep the socket file around while draining
+        let cleanup = self.0.cleanup.take();
+        self.0.stop();
+        debug!(
+            active = self.0.active.load(Ordering::SeqCst),
+            "draining connections"
+        );
+        let res = tokio::time::timeout(timeout, self.0.wait_idle()).await;
+        cleanup.run();
+        self.0.remove_pid_file();
+        res.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out waiting for connections to close",
+            )
+        })
     }
 }
@@ -0,0 +1,62 @@
+use std::io;
+
+use futures::StreamExt;
+use tokio::sync::Mutex;
+
+use crate::{Connection, Endpoint, Error, IpcStream, ShutdownHandle};
+
+impl Endpoint {
+    /// Listener that can be shared between several tasks.
+    ///
+    /// This is like [`Endpoint::incoming`], but [`IpcListener::accept`] takes `&self`, so the
+    /// listener can be shared between workers with an [`Arc`](std::sync::Arc) instead of
+    /// forwarding connections from a dedicated accept task.
+    pub fn listen(self) -> Result<IpcListener, Error> {
+        Ok(IpcListener::new(self.incoming()?))
+    }
+}
+
+/// Listener that can be shared between tasks, created by [`Endpoint::listen`].
+///
+/// Each connection is handed to exactly one of the tasks that are waiting in
+/// [`accept`](IpcListener::accept). Token authentication, peer filters, metrics and the other
+/// endpoint options apply the same way they do for [`IpcStream`].
+///
+/// Connections still come from a single [`IpcStream`], so waiting tasks take turns rather than
+/// accepting in parallel. Token handshakes run concurrently inside the stream, so a slow peer
+/// doesn't hold up the others, but this doesn't add accept throughput over a single task
+/// reading from [`Endpoint::incoming`].
+pub struct IpcListener {
+    stream: Mutex<IpcStream>,
+    shutdown: ShutdownHandle,
+}
+
+impl IpcListener {
+    /// Wraps an existing stream of incoming connections.
+    pub fn new(stream: IpcStream) -> Self {
+        Self {
+            shutdown: stream.shutdown_handle(),
+            stream: Mutex::new(stream),
+        }
+    }
+
+    /// Waits for the next connection.
+    ///
+    /// This can be called from any number of tasks at once. Only one of them waits on the
+    /// underlying stream at a time and the rest queue up behind it in the order they called
+    /// this. Returns `None` once the listener has been shut down.
+    pub async fn accept(&self) -> Option<io::Result<Connection>> {
+        self.stream.lock().await.next().await
+    }
+
+    /// Returns a handle that can be used to stop accepting connections from another task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+}
+
+impl From<IpcStream> for IpcListener {
+    fn from(stream: IpcStream) -> Self {
+        Self::new(stream)
+    }
+}
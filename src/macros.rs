@@ -0,0 +1,68 @@
+// Logging macros that compile to nothing unless the `tracing` feature is enabled. Without the
+// feature, the arguments are still checked in dead code, so values that are only used for
+// logging don't trigger unused variable warnings.
+
+#[cfg(feature = "tracing")]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        tracing::trace!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        if false {
+            log_args!($($arg)+);
+        }
+    };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        tracing::debug!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        if false {
+            log_args!($($arg)+);
+        }
+    };
+}
+
+// Uses each field and the message of a tracing event without recording anything
+#[cfg(not(feature = "tracing"))]
+macro_rules! log_args {
+    ($name:ident = ? $value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $(log_args!($($rest)*);)?
+    };
+    ($name:ident = % $value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $(log_args!($($rest)*);)?
+    };
+    ($name:ident = $value:expr $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $(log_args!($($rest)*);)?
+    };
+    (? $value:ident $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $(log_args!($($rest)*);)?
+    };
+    (% $value:ident $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $(log_args!($($rest)*);)?
+    };
+    ($value:ident $(, $($rest:tt)*)?) => {
+        let _ = &$value;
+        $(log_args!($($rest)*);)?
+    };
+    ($($message:tt)+) => {
+        let _ = format_args!($($message)+);
+    };
+    () => {};
+}
@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::{fmt, io};
+
+/// Receives metrics events from endpoints and their connections.
+///
+/// All methods have empty default implementations, so implementors only need to override the
+/// events they're interested in. Methods are called inline from I/O paths, so they should return
+/// quickly.
+///
+/// Install an implementation on the server with
+/// [`Endpoint::set_metrics`](crate::Endpoint::set_metrics) and on the client with
+/// [`ConnectOptions::metrics`](crate::ConnectOptions::metrics).
+pub trait IpcMetrics: Send + Sync + 'static {
+    /// A connection was accepted by the server.
+    fn connection_accepted(&self, _id: u64) {}
+
+    /// The server failed to accept a connection.
+    fn accept_error(&self, _error: &io::Error) {}
+
+    /// The client connected to a server.
+    fn connection_established(&self, _id: u64) {}
+
+    /// Bytes were read from a connection.
+    fn bytes_read(&self, _id: u64, _bytes: usize) {}
+
+    /// Bytes were written to a connection.
+    fn bytes_written(&self, _id: u64, _bytes: usize) {}
+
+    /// The number of open connections changed.
+    fn active_connections(&self, _count: usize) {}
+}
+
+#[derive(Clone)]
+pub(crate) struct MetricsHandle {
+    metrics: Arc<dyn IpcMetrics>,
+    active: Arc<AtomicUsize>,
+}
+
+impl fmt::Debug for MetricsHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetricsHandle")
+            .field("active", &self.active)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MetricsHandle {
+    pub(crate) fn new(metrics: Arc<dyn IpcMetrics>) -> Self {
+        Self {
+            metrics,
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub(crate) fn accepted(&self, id: u64) -> Arc<ConnectionMetrics> {
+        self.metrics.connection_accepted(id);
+        self.track(id)
+    }
+
+    pub(crate) fn established(&self, id: u64) -> Arc<ConnectionMetrics> {
+        self.metrics.connection_established(id);
+        self.track(id)
+    }
+
+    pub(crate) fn accept_error(&self, error: &io::Error) {
+        self.metrics.accept_error(error);
+    }
+
+    fn track(&self, id: u64) -> Arc<ConnectionMetrics> {
+        let count = self.active.fetch_add(1, Ordering::Relaxed) + 1;
+        self.metrics.active_connections(count);
+        Arc::new(ConnectionMetrics {
+            handle: self.clone(),
+            id,
+        })
+    }
+}
+
+/// Per-connection metrics state, shared between the halves of a split connection. The connection
+/// is considered closed once every copy is dropped.
+pub(crate) struct ConnectionMetrics {
+    handle: MetricsHandle,
+    id: u64,
+}
+
+impl ConnectionMetrics {
+    pub(crate) fn record_read(&self, res: &Poll<io::Result<()>>, bytes: usize) {
+        if matches!(res, Poll::Ready(Ok(()))) {
+            self.add_bytes_read(bytes);
+        }
+    }
+
+    pub(crate) fn record_write(&self, res: &Poll<io::Result<usize>>) {
+        if let Poll::Ready(Ok(bytes)) = res {
+            self.add_bytes_written(*bytes);
+        }
+    }
+
+    pub(crate) fn add_bytes_read(&self, bytes: usize) {
+        if bytes > 0 {
+            self.handle.metrics.bytes_read(self.id, bytes);
+        }
+    }
+
+    pub(crate) fn add_bytes_written(&self, bytes: usize) {
+        if bytes > 0 {
+            self.handle.metrics.bytes_written(self.id, bytes);
+        }
+    }
+}
+
+impl Drop for ConnectionMetrics {
+    fn drop(&mut self) {
+        let count = self.handle.active.fetch_sub(1, Ordering::Relaxed) - 1;
+        self.handle.metrics.active_connections(count);
+    }
+}
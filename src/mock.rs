@@ -0,0 +1,197 @@
+//! In-memory transport with the same shape as the real [`Endpoint`](crate::Endpoint) API, for
+//! unit tests that shouldn't touch the file system or the pipe namespace.
+//!
+//! Endpoints are registered in a process-wide table keyed by path, and each connection is a
+//! [`tokio::io::duplex`] pair. Connecting never blocks: the server half is queued on the
+//! endpoint immediately, so connections are always accepted in the order that
+//! [`Endpoint::connect`] was called.
+
+use std::collections::HashMap;
+use std::io::{self, IoSlice};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::task::{Context, Poll};
+
+use futures::Stream;
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio::sync::mpsc;
+
+use crate::{IntoIpcPath, OnConflict};
+
+const DEFAULT_BUFFER_SIZE: usize = 65536;
+
+struct Listener {
+    id: u64,
+    buffer_size: usize,
+    tx: mpsc::UnboundedSender<Connection>,
+}
+
+static NEXT_LISTENER_ID: AtomicU64 = AtomicU64::new(0);
+
+fn listeners() -> std::sync::MutexGuard<'static, HashMap<PathBuf, Listener>> {
+    static LISTENERS: OnceLock<Mutex<HashMap<PathBuf, Listener>>> = OnceLock::new();
+    LISTENERS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// In-memory stand-in for [`crate::Endpoint`].
+pub struct Endpoint {
+    path: PathBuf,
+    on_conflict: OnConflict,
+    buffer_size: usize,
+}
+
+impl Endpoint {
+    /// New in-memory endpoint at the given path. Nothing is created on disk.
+    pub fn new(path: impl IntoIpcPath, on_conflict: OnConflict) -> io::Result<Self> {
+        Ok(Self {
+            path: path.into_ipc_path()?,
+            on_conflict,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        })
+    }
+
+    /// Returns the path of the endpoint.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Set the number of bytes that can be written to each side of a connection before writes
+    /// wait for the peer to read. Defaults to 64 KiB.
+    pub fn set_buffer_size(&mut self, size: usize) {
+        self.buffer_size = size.max(1);
+    }
+
+    /// Stream of incoming connections.
+    ///
+    /// Fails with [`io::ErrorKind::AddrInUse`] if another mock endpoint is already listening on
+    /// the path, unless the endpoint was created with [`OnConflict::Overwrite`] or
+    /// [`OnConflict::Replace`], in which case the existing endpoint stops receiving new
+    /// connections.
+    pub fn incoming(self) -> io::Result<IpcStream> {
+        let mut listeners = listeners();
+        if listeners.contains_key(&self.path)
+            && !matches!(
+                self.on_conflict,
+                OnConflict::Overwrite | OnConflict::Replace
+            )
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!(
+                    "Unable to bind to {:?} because it is already in use",
+                    self.path
+                ),
+            ));
+        }
+        let id = NEXT_LISTENER_ID.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::unbounded_channel();
+        listeners.insert(
+            self.path.clone(),
+            Listener {
+                id,
+                buffer_size: self.buffer_size,
+                tx,
+            },
+        );
+        Ok(IpcStream {
+            path: self.path,
+            id,
+            rx,
+        })
+    }
+
+    /// Connects to the mock endpoint at the given path.
+    ///
+    /// Fails with [`io::ErrorKind::NotFound`] if no mock endpoint is listening on the path.
+    pub async fn connect(path: impl IntoIpcPath) -> io::Result<Connection> {
+        let path = path.into_ipc_path()?;
+        let listeners = listeners();
+        let listener = listeners.get(&path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No mock endpoint is listening on {path:?}"),
+            )
+        })?;
+        let (client, server) = tokio::io::duplex(listener.buffer_size);
+        listener
+            .tx
+            .send(Connection(server))
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+        Ok(Connection(client))
+    }
+}
+
+/// Stream of incoming in-memory connections, created by [`Endpoint::incoming`].
+///
+/// The endpoint stops accepting connections when this is dropped.
+pub struct IpcStream {
+    path: PathBuf,
+    id: u64,
+    rx: mpsc::UnboundedReceiver<Connection>,
+}
+
+impl Stream for IpcStream {
+    type Item = io::Result<Connection>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().rx.poll_recv(cx).map(|conn| conn.map(Ok))
+    }
+}
+
+impl Drop for IpcStream {
+    fn drop(&mut self) {
+        let mut listeners = listeners();
+        // The path may have been taken over by another endpoint using `OnConflict::Overwrite`
+        if listeners.get(&self.path).is_some_and(|l| l.id == self.id) {
+            listeners.remove(&self.path);
+        }
+    }
+}
+
+/// In-memory stand-in for [`crate::Connection`].
+pub struct Connection(DuplexStream);
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_read(ctx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write(ctx, buf)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().0).poll_write_vectored(ctx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.0.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(ctx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(ctx)
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::channel::{mpsc, oneshot};
+use futures::{Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::compat::{Compat, FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
+
+use crate::Connection;
+
+type OpenRequest = oneshot::Sender<io::Result<MuxStream>>;
+
+/// Which side of the connection the multiplexer is running on.
+///
+/// Each end of a connection must use a different mode.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MuxMode {
+    /// The side that initiated the connection
+    Client,
+    /// The side that accepted the connection
+    Server,
+}
+
+/// Carries multiple independent logical streams over a single [`Connection`] using the
+/// [yamux](https://github.com/hashicorp/yamux/blob/master/spec.md) protocol.
+///
+/// Each stream has its own flow control window, so a slow reader on one stream won't block the
+/// others.
+///
+/// The multiplexer is a [`Stream`] of substreams opened by the remote side. It must be polled
+/// continuously for any of the substreams to make progress, even if the remote side never opens
+/// any streams. New outbound streams can be opened with a [`MuxControl`].
+pub struct Multiplexer {
+    connection: yamux::Connection<Compat<Connection>>,
+    requests: mpsc::UnboundedReceiver<OpenRequest>,
+    pending: VecDeque<OpenRequest>,
+    control: mpsc::UnboundedSender<OpenRequest>,
+}
+
+impl Multiplexer {
+    /// Starts multiplexing the connection.
+    pub fn new(connection: Connection, mode: MuxMode) -> Self {
+        let mode = match mode {
+            MuxMode::Client => yamux::Mode::Client,
+            MuxMode::Server => yamux::Mode::Server,
+        };
+        let (control, requests) = mpsc::unbounded();
+        Self {
+            connection: yamux::Connection::new(connection.compat(), yamux::Config::default(), mode),
+            requests,
+            pending: VecDeque::new(),
+            control,
+        }
+    }
+
+    /// Returns a handle that can be used to open new outbound streams.
+    pub fn control(&self) -> MuxControl {
+        MuxControl {
+            requests: self.control.clone(),
+        }
+    }
+
+    fn poll_open_requests(&mut self, cx: &mut Context<'_>) {
+        // We hold a sender ourselves, so this will never return `None`
+        while let Poll::Ready(Some(request)) = self.requests.poll_next_unpin(cx) {
+            self.pending.push_back(request);
+        }
+        while !self.pending.is_empty() {
+            match self.connection.poll_new_outbound(cx) {
+                Poll::Ready(result) => {
+                    if let Some(request) = self.pending.pop_front() {
+                        let _ = request.send(result.map(MuxStream::new).map_err(io::Error::other));
+                    }
+                }
+                Poll::Pending => break,
+            }
+        }
+    }
+}
+
+impl Stream for Multiplexer {
+    type Item = io::Result<MuxStream>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = Pin::into_inner(self);
+        this.poll_open_requests(cx);
+        this.connection
+            .poll_next_inbound(cx)
+            .map(|stream| stream.map(|s| s.map(MuxStream::new).map_err(io::Error::other)))
+    }
+}
+
+/// Handle for opening new outbound streams on a [`Multiplexer`].
+#[derive(Clone, Debug)]
+pub struct MuxControl {
+    requests: mpsc::UnboundedSender<OpenRequest>,
+}
+
+impl MuxControl {
+    /// Opens a new stream to the remote side.
+    ///
+    /// This will not complete unless the [`Multiplexer`] is being polled.
+    pub async fn open_stream(&self) -> io::Result<MuxStream> {
+        let (tx, rx) = oneshot::channel();
+        self.requests
+            .unbounded_send(tx)
+            .map_err(|_| multiplexer_closed())?;
+        rx.await.map_err(|_| multiplexer_closed())?
+    }
+}
+
+fn multiplexer_closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "The multiplexer was closed")
+}
+
+/// A single logical stream carried over a [`Multiplexer`].
+pub struct MuxStream {
+    inner: Compat<yamux::Stream>,
+}
+
+impl MuxStream {
+    fn new(stream: yamux::Stream) -> Self {
+        Self {
+            inner: stream.compat(),
+        }
+    }
+}
+
+impl AsyncRead for MuxStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner).poll_read(ctx, buf)
+    }
+}
+
+impl AsyncWrite for MuxStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner).poll_write(ctx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner).poll_flush(ctx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner).poll_shutdown(ctx)
+    }
+}
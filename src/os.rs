@@ -0,0 +1,112 @@
+//! Platform-specific extensions.
+
+#[cfg(unix)]
+pub mod unix {
+    //! Unix-specific extensions.
+
+    use crate::EndpointBuilder;
+
+    /// Unix-specific options for [`EndpointBuilder`].
+    pub trait EndpointBuilderExt: super::private::Sealed {
+        /// Remove the socket file when the stream of incoming connections is dropped or shut
+        /// down. See [`Endpoint::set_remove_on_drop`](crate::Endpoint::set_remove_on_drop).
+        fn remove_on_drop(self, remove_on_drop: bool) -> Self;
+
+        /// Maximum number of pending connections queued by the OS before the server accepts
+        /// them. The OS may silently cap this, for example to `net.core.somaxconn` on Linux.
+        /// Defaults to the standard library's value, which is 128 on most platforms.
+        fn backlog(self, backlog: u32) -> Self;
+
+        /// Lock a companion file so only one server runs on the path at a time. See
+        /// [`Endpoint::set_lock_file`](crate::Endpoint::set_lock_file).
+        fn lock_file(self, lock_file: bool) -> Self;
+    }
+
+    impl EndpointBuilderExt for EndpointBuilder {
+        fn remove_on_drop(self, remove_on_drop: bool) -> Self {
+            self.with(|e| e.set_remove_on_drop(remove_on_drop))
+        }
+
+        fn backlog(self, backlog: u32) -> Self {
+            self.with(|e| e.inner.set_backlog(backlog))
+        }
+
+        fn lock_file(self, lock_file: bool) -> Self {
+            self.with(|e| e.set_lock_file(lock_file))
+        }
+    }
+}
+
+#[cfg(windows)]
+pub mod windows {
+    //! Windows-specific extensions.
+
+    use crate::{EndpointBuilder, PipeMode};
+
+    /// Windows-specific options for [`EndpointBuilder`].
+    pub trait EndpointBuilderExt: super::private::Sealed {
+        /// Mode of the pipe instances. See
+        /// [`Endpoint::set_pipe_mode`](crate::Endpoint::set_pipe_mode).
+        fn pipe_mode(self, pipe_mode: PipeMode) -> Self;
+
+        /// Maximum number of pipe instances. See
+        /// [`Endpoint::set_max_instances`](crate::Endpoint::set_max_instances).
+        fn max_instances(self, max_instances: u8) -> Self;
+
+        /// Create instances of a pipe owned by another server. See
+        /// [`Endpoint::set_join_existing`](crate::Endpoint::set_join_existing).
+        fn join_existing(self, join_existing: bool) -> Self;
+
+        /// Create pipe instances with `FILE_FLAG_WRITE_THROUGH`. See
+        /// [`Endpoint::set_write_through`](crate::Endpoint::set_write_through).
+        fn write_through(self, write_through: bool) -> Self;
+
+        /// Reject clients on other machines. See
+        /// [`Endpoint::set_reject_remote_clients`](crate::Endpoint::set_reject_remote_clients).
+        fn reject_remote_clients(self, reject_remote_clients: bool) -> Self;
+
+        /// Reuse the pipe instances of closed connections. See
+        /// [`Endpoint::set_reuse_instances`](crate::Endpoint::set_reuse_instances).
+        fn reuse_instances(self, reuse_instances: bool) -> Self;
+
+        /// Number of pipe instances waiting for clients at once. See
+        /// [`Endpoint::set_standby_instances`](crate::Endpoint::set_standby_instances).
+        fn standby_instances(self, standby_instances: usize) -> Self;
+    }
+
+    impl EndpointBuilderExt for EndpointBuilder {
+        fn pipe_mode(self, pipe_mode: PipeMode) -> Self {
+            self.with(|e| e.set_pipe_mode(pipe_mode))
+        }
+
+        fn max_instances(self, max_instances: u8) -> Self {
+            self.with(|e| e.set_max_instances(max_instances))
+        }
+
+        fn join_existing(self, join_existing: bool) -> Self {
+            self.with(|e| e.set_join_existing(join_existing))
+        }
+
+        fn write_through(self, write_through: bool) -> Self {
+            self.with(|e| e.set_write_through(write_through))
+        }
+
+        fn reject_remote_clients(self, reject_remote_clients: bool) -> Self {
+            self.with(|e| e.set_reject_remote_clients(reject_remote_clients))
+        }
+
+        fn reuse_instances(self, reuse_instances: bool) -> Self {
+            self.with(|e| e.set_reuse_instances(reuse_instances))
+        }
+
+        fn standby_instances(self, standby_instances: usize) -> Self {
+            self.with(|e| e.set_standby_instances(standby_instances))
+        }
+    }
+}
+
+mod private {
+    pub trait Sealed {}
+
+    impl Sealed for crate::EndpointBuilder {}
+}
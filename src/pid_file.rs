@@ -0,0 +1,26 @@
+use std::path::PathBuf;
+use std::{fs, io};
+
+/// File containing the pid of the server, which is removed once the server shuts down.
+pub(crate) struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub(crate) fn create(path: PathBuf) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, format!("{}\n", std::process::id()))?;
+        trace!(?path, "wrote pid file");
+        Ok(Self { path })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Ok(()) = fs::remove_file(&self.path) {
+            trace!(path = ?self.path, "removed pid file");
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::io::{self, IoSlice};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{ConnectOptions, Connection, Endpoint, IntoIpcPath};
+
+/// Pool of client connections to a single endpoint.
+///
+/// The pool opens `size` connections up front and hands them out with [`IpcPool::get`]. At most
+/// `size` connections can be checked out at once; further calls wait until a connection is
+/// returned. Connections are returned to the pool when the [`PooledConnection`] is dropped,
+/// unless an I/O error occurred or the server closed the connection, in which case a
+/// replacement is opened in the background. Idle connections are checked before they're handed
+/// out, so connections that the server closed while they sat in the pool, such as when the server
+/// restarted, are replaced rather than returned.
+///
+/// The pool is cheap to clone and all clones share the same connections.
+#[derive(Clone)]
+pub struct IpcPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    path: PathBuf,
+    options: ConnectOptions,
+    size: usize,
+    idle: Mutex<Vec<Connection>>,
+    permits: Arc<Semaphore>,
+}
+
+impl PoolInner {
+    async fn connect(&self) -> io::Result<Connection> {
+        Ok(Endpoint::connect_with_options(self.path.clone(), self.options.clone()).await?)
+    }
+
+    fn idle(&self) -> MutexGuard<'_, Vec<Connection>> {
+        // The list is always left in a consistent state, so poisoning can be ignored
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn put_back(&self, conn: Connection) {
+        let mut idle = self.idle();
+        if idle.len() < self.size {
+            idle.push(conn);
+        }
+    }
+
+    fn replace(self: Arc<Self>) {
+        // Replacements are best-effort, a new connection will be opened on checkout if needed
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move {
+                if let Ok(conn) = self.connect().await {
+                    self.put_back(conn);
+                }
+            });
+        }
+    }
+}
+
+impl IpcPool {
+    /// Creates a new pool and opens `size` connections to the given path.
+    pub async fn new(
+        path: impl IntoIpcPath,
+        options: ConnectOptions,
+        size: usize,
+    ) -> io::Result<Self> {
+        if size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pool size must be greater than zero",
+            ));
+        }
+        let inner = PoolInner {
+            path: path.into_ipc_path()?,
+            options,
+            size,
+            idle: Mutex::new(Vec::with_capacity(size)),
+            permits: Arc::new(Semaphore::new(size)),
+        };
+        for _ in 0..size {
+            let conn = inner.connect().await?;
+            inner.idle().push(conn);
+        }
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Checks out a connection, waiting for one to become available if all of them are in use.
+    pub async fn get(&self) -> io::Result<PooledConnection> {
+        let permit = self
+            .inner
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let conn = loop {
+            let idle = self.inner.idle().pop();
+            let Some(mut conn) = idle else {
+                break self.inner.connect().await?;
+            };
+            if is_alive(&mut conn) {
+                break conn;
+            }
+            trace!("discarding closed pooled connection");
+        };
+        Ok(PooledConnection {
+            conn: Some(conn),
+            pool: self.inner.clone(),
+            broken: false,
+            _permit: permit,
+        })
+    }
+
+    /// Maximum number of connections managed by the pool.
+    pub fn size(&self) -> usize {
+        self.inner.size
+    }
+
+    /// Number of connections that are currently available for checkout.
+    pub fn idle(&self) -> usize {
+        self.inner.idle().len()
+    }
+}
+
+// Idle connections shouldn't have anything to read, so a read that doesn't block means the server
+// closed the connection or sent something unexpected
+fn is_alive(conn: &mut Connection) -> bool {
+    let mut buf = [0; 1];
+    matches!(conn.try_read(&mut buf), Err(e) if e.kind() == io::ErrorKind::WouldBlock)
+}
+
+/// Connection checked out from an [`IpcPool`]. The connection is returned to the pool when this
+/// is dropped.
+///
+/// This dereferences to [`Connection`] for methods that don't do any I/O. Reads and writes go
+/// through this type instead so that a failed connection isn't returned to the pool.
+pub struct PooledConnection {
+    conn: Option<Connection>,
+    pool: Arc<PoolInner>,
+    broken: bool,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledConnection {
+    /// Prevents the connection from being returned to the pool. Use this if the connection was
+    /// left in an unusable state, such as after abandoning a partially written message.
+    pub fn discard(&mut self) {
+        self.broken = true;
+    }
+
+    /// See [`Connection::try_read`].
+    pub fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.conn_mut().try_read(buf);
+        self.check_read(res, buf.len())
+    }
+
+    /// See [`Connection::try_write`].
+    pub fn try_write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = self.conn_mut().try_write(buf);
+        self.check_result(res)
+    }
+
+    /// See [`Connection::peek`].
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let res = self.conn_mut().peek(buf).await;
+        self.check_read(res, buf.len())
+    }
+
+    fn conn_mut(&mut self) -> &mut Connection {
+        self.conn
+            .as_mut()
+            .expect("connection is only taken on drop")
+    }
+
+    fn conn(&mut self) -> Pin<&mut Connection> {
+        Pin::new(self.conn_mut())
+    }
+
+    fn check<T>(&mut self, res: Poll<io::Result<T>>) -> Poll<io::Result<T>> {
+        if matches!(res, Poll::Ready(Err(_))) {
+            self.broken = true;
+        }
+        res
+    }
+
+    fn check_result<T>(&mut self, res: io::Result<T>) -> io::Result<T> {
+        if matches!(&res, Err(e) if e.kind() != io::ErrorKind::WouldBlock) {
+            self.broken = true;
+        }
+        res
+    }
+
+    fn check_read(&mut self, res: io::Result<usize>, len: usize) -> io::Result<usize> {
+        // The server closed the connection
+        if matches!(res, Ok(0)) && len > 0 {
+            self.broken = true;
+        }
+        self.check_result(res)
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn
+            .as_ref()
+            .expect("connection is only taken on drop")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        let Some(conn) = self.conn.take() else {
+            return;
+        };
+        if self.broken {
+            drop(conn);
+            self.pool.clone().replace();
+        } else {
+            self.pool.put_back(conn);
+        }
+    }
+}
+
+impl AsyncRead for PooledConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        let filled = buf.filled().len();
+        let res = this.conn().poll_read(ctx, buf);
+        // The server closed the connection
+        if matches!(res, Poll::Ready(Ok(()))) && buf.filled().len() == filled && buf.remaining() > 0
+        {
+            this.broken = true;
+        }
+        this.check(res)
+    }
+}
+
+impl AsyncWrite for PooledConnection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        let res = this.conn().poll_write(ctx, buf);
+        this.check(res)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        let res = this.conn().poll_write_vectored(ctx, bufs);
+        this.check(res)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.deref().is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        let res = this.conn().poll_flush(ctx);
+        this.check(res)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        let res = this.conn().poll_shutdown(ctx);
+        // A connection that's been shut down can't be reused
+        this.broken = true;
+        this.check(res)
+    }
+}
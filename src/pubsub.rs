@@ -0,0 +1,249 @@
+use std::collections::HashSet;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{future, SinkExt, Stream, StreamExt};
+use tokio::sync::broadcast;
+use tokio_util::codec::{Framed, FramedRead, FramedWrite, LengthDelimitedCodec};
+
+use crate::{Connection, OwnedReadHalf, OwnedWriteHalf};
+
+const SUBSCRIBE: u8 = 0;
+const UNSUBSCRIBE: u8 = 1;
+const MESSAGE: u8 = 2;
+const LAGGED: u8 = 3;
+
+/// What to do with a subscriber that falls so far behind that its buffer fills up.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum LagPolicy {
+    /// Drop the oldest messages the subscriber hasn't received yet and tell it how many were
+    /// skipped with [`PubSubEvent::Lagged`]
+    #[default]
+    Skip,
+    /// Disconnect the subscriber
+    Disconnect,
+}
+
+/// Server side of a topic-based publish/subscribe system.
+///
+/// Clients connect with a [`Subscriber`] and choose which topics they're interested in.
+/// Published messages are only sent to clients subscribed to the message's topic. Each
+/// subscriber is served by its own task, so a slow subscriber doesn't hold up the others.
+///
+/// Cloning the publisher gives another handle to the same set of subscribers.
+#[derive(Clone)]
+pub struct Publisher {
+    tx: broadcast::Sender<(Arc<str>, Bytes)>,
+    lag_policy: LagPolicy,
+}
+
+impl Publisher {
+    /// Creates a publisher that buffers up to `capacity` messages for each subscriber. Clamped
+    /// to at least 1.
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity.max(1));
+        Self {
+            tx,
+            lag_policy: LagPolicy::default(),
+        }
+    }
+
+    /// What to do with subscribers that fall behind. Defaults to [`LagPolicy::Skip`].
+    pub fn lag_policy(mut self, lag_policy: LagPolicy) -> Self {
+        self.lag_policy = lag_policy;
+        self
+    }
+
+    /// Starts sending messages to the client on the other end of the connection. Must be called
+    /// from within a tokio runtime.
+    ///
+    /// The client is removed once it disconnects.
+    pub fn add_subscriber(&self, connection: Connection) {
+        let rx = self.tx.subscribe();
+        let lag_policy = self.lag_policy;
+        tokio::spawn(async move {
+            let (reader, writer) = connection.into_split();
+            let topics = Arc::new(Mutex::new(HashSet::new()));
+            let read = Box::pin(read_subscriptions(reader, topics.clone()));
+            let write = Box::pin(write_messages(writer, rx, topics, lag_policy));
+            // Stop as soon as either direction fails or the client goes away
+            let (res, _) = future::select(read, write).await.factor_first();
+            if let Err(e) = res {
+                trace!("removing subscriber: {e:?}");
+            }
+        });
+    }
+
+    /// Sends a message to all clients subscribed to the topic.
+    pub fn publish(&self, topic: &str, payload: impl Into<Bytes>) {
+        // An error just means there are no subscribers
+        let _ = self.tx.send((topic.into(), payload.into()));
+    }
+}
+
+async fn read_subscriptions(
+    reader: OwnedReadHalf,
+    topics: Arc<Mutex<HashSet<String>>>,
+) -> io::Result<()> {
+    let mut reader = FramedRead::new(reader, LengthDelimitedCodec::new());
+    while let Some(frame) = reader.next().await {
+        let mut frame = frame?;
+        if frame.is_empty() {
+            return Err(invalid_data("empty pub/sub message"));
+        }
+        let kind = frame.get_u8();
+        let topic = String::from_utf8(frame.to_vec()).map_err(invalid_data)?;
+        let mut topics = topics.lock().unwrap_or_else(PoisonError::into_inner);
+        match kind {
+            SUBSCRIBE => {
+                trace!(?topic, "subscribed");
+                topics.insert(topic);
+            }
+            UNSUBSCRIBE => {
+                trace!(?topic, "unsubscribed");
+                topics.remove(&topic);
+            }
+            _ => return Err(invalid_data("unexpected pub/sub message")),
+        }
+    }
+    Ok(())
+}
+
+async fn write_messages(
+    writer: OwnedWriteHalf,
+    mut rx: broadcast::Receiver<(Arc<str>, Bytes)>,
+    topics: Arc<Mutex<HashSet<String>>>,
+    lag_policy: LagPolicy,
+) -> io::Result<()> {
+    let mut writer = FramedWrite::new(writer, LengthDelimitedCodec::new());
+    loop {
+        let frame = match rx.recv().await {
+            Ok((topic, payload)) => {
+                let subscribed = topics
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .contains(&*topic);
+                if !subscribed {
+                    continue;
+                }
+                let mut frame = BytesMut::with_capacity(1 + 2 + topic.len() + payload.len());
+                frame.put_u8(MESSAGE);
+                frame.put_u16(topic.len().try_into().map_err(invalid_data)?);
+                frame.put_slice(topic.as_bytes());
+                frame.put_slice(&payload);
+                frame
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                if lag_policy == LagPolicy::Disconnect {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "subscriber fell too far behind",
+                    ));
+                }
+                let mut frame = BytesMut::with_capacity(1 + 8);
+                frame.put_u8(LAGGED);
+                frame.put_u64(skipped);
+                frame
+            }
+            // The publisher was dropped
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        writer.send(frame.freeze()).await?;
+    }
+}
+
+/// Event received by a [`Subscriber`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PubSubEvent {
+    /// A message was published to one of the subscribed topics
+    Message {
+        /// Topic the message was published to
+        topic: String,
+        /// Contents of the message
+        payload: Bytes,
+    },
+    /// The subscriber fell behind and the given number of messages were skipped. Only sent when
+    /// the publisher uses [`LagPolicy::Skip`].
+    ///
+    /// The count includes messages published to topics the subscriber isn't subscribed to, so
+    /// it's possible that none of the skipped messages were relevant.
+    Lagged(u64),
+}
+
+/// Client side of a topic-based publish/subscribe system. Receives messages from a
+/// [`Publisher`] for the topics it's subscribed to.
+pub struct Subscriber {
+    inner: Framed<Connection, LengthDelimitedCodec>,
+}
+
+impl Subscriber {
+    /// Wraps a connection to a server that added it with [`Publisher::add_subscriber`].
+    pub fn new(connection: Connection) -> Self {
+        Self {
+            inner: Framed::new(connection, LengthDelimitedCodec::new()),
+        }
+    }
+
+    /// Starts receiving messages published to the topic.
+    ///
+    /// Messages published before the server processes the subscription aren't received.
+    pub async fn subscribe(&mut self, topic: &str) -> io::Result<()> {
+        self.send(SUBSCRIBE, topic).await
+    }
+
+    /// Stops receiving messages published to the topic.
+    pub async fn unsubscribe(&mut self, topic: &str) -> io::Result<()> {
+        self.send(UNSUBSCRIBE, topic).await
+    }
+
+    async fn send(&mut self, kind: u8, topic: &str) -> io::Result<()> {
+        let mut frame = BytesMut::with_capacity(1 + topic.len());
+        frame.put_u8(kind);
+        frame.put_slice(topic.as_bytes());
+        self.inner.send(frame.freeze()).await
+    }
+}
+
+impl Stream for Subscriber {
+    type Item = io::Result<PubSubEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner)
+            .poll_next(cx)
+            .map(|frame| frame.map(|frame| decode_event(frame?.freeze())))
+    }
+}
+
+fn decode_event(mut frame: Bytes) -> io::Result<PubSubEvent> {
+    match (frame.len(), frame.first()) {
+        (len, Some(&MESSAGE)) if len >= 3 => {
+            frame.advance(1);
+            let topic_len = usize::from(frame.get_u16());
+            if frame.len() < topic_len {
+                return Err(invalid_data("truncated pub/sub message"));
+            }
+            let topic = frame.split_to(topic_len);
+            let topic = String::from_utf8(topic.to_vec()).map_err(invalid_data)?;
+            Ok(PubSubEvent::Message {
+                topic,
+                payload: frame,
+            })
+        }
+        (9, Some(&LAGGED)) => {
+            frame.advance(1);
+            Ok(PubSubEvent::Lagged(frame.get_u64()))
+        }
+        _ => Err(invalid_data("unexpected pub/sub message")),
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
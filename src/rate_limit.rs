@@ -0,0 +1,94 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::time::{Instant, Sleep};
+
+/// Limits how quickly an endpoint accepts new connections, using a token bucket.
+///
+/// Connections that arrive faster than the limit are left waiting in the OS until a token becomes
+/// available, so a client that repeatedly reconnects can't monopolize the accept loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    interval: Duration,
+    burst: u32,
+}
+
+impl RateLimit {
+    /// Allow `accepts` connections per `per` on average. `accepts` is clamped to at least 1.
+    ///
+    /// The burst size defaults to `accepts`.
+    pub fn new(accepts: u32, per: Duration) -> Self {
+        let accepts = accepts.max(1);
+        Self {
+            interval: per / accepts,
+            burst: accepts,
+        }
+    }
+
+    /// Maximum number of connections that can be accepted back-to-back after a quiet period.
+    /// Clamped to at least 1.
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+}
+
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    tokens: u32,
+    last_refill: Instant,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        let now = Instant::now();
+        Self {
+            limit,
+            tokens: limit.burst,
+            last_refill: now,
+            sleep: Box::pin(tokio::time::sleep_until(now)),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        if self.limit.interval.is_zero() {
+            self.tokens = self.limit.burst;
+            self.last_refill = now;
+            return;
+        }
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let new_tokens = elapsed.as_nanos() / self.limit.interval.as_nanos();
+        let new_tokens = u32::try_from(new_tokens).unwrap_or(u32::MAX);
+        let tokens = self.tokens.saturating_add(new_tokens);
+        if tokens >= self.limit.burst {
+            self.tokens = self.limit.burst;
+            self.last_refill = now;
+        } else {
+            self.tokens = tokens;
+            self.last_refill += self.limit.interval * new_tokens;
+        }
+    }
+
+    /// Waits until a token is available without consuming it.
+    pub(crate) fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            self.refill();
+            if self.tokens > 0 {
+                return Poll::Ready(());
+            }
+            let next = self.last_refill + self.limit.interval;
+            self.sleep.as_mut().reset(next);
+            if self.sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+    }
+
+    pub(crate) fn consume(&mut self) {
+        self.tokens = self.tokens.saturating_sub(1);
+    }
+}
@@ -0,0 +1,284 @@
+use std::io::{self, IoSlice};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::watch;
+
+use crate::{Backoff, ConnectOptions, Connection, Endpoint, IntoIpcPath};
+
+/// Controls how a [`ReconnectingConnection`] retries after losing its connection.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    initial_interval: Duration,
+    backoff: Backoff,
+    max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_interval: Duration::from_millis(100),
+            backoff: Backoff::Exponential {
+                max: Duration::from_secs(5),
+            },
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// New default reconnect policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Interval to wait before the second reconnect attempt. The first attempt is made
+    /// immediately. Defaults to 100 milliseconds.
+    pub fn initial_interval(mut self, initial_interval: Duration) -> Self {
+        self.initial_interval = initial_interval;
+        self
+    }
+
+    /// Strategy used to adjust the interval between reconnect attempts. Defaults to
+    /// [`Backoff::Exponential`] with a maximum of 5 seconds.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Maximum number of consecutive reconnect attempts before giving up. Defaults to retrying
+    /// forever. See [`ReconnectingConnection::reconnect`] for starting over after giving up.
+    pub fn max_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+}
+
+/// State of a [`ReconnectingConnection`], published through
+/// [`ReconnectingConnection::subscribe`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ConnectionState {
+    /// Connected to the server. Contains the [`Connection::id`] of the underlying connection.
+    Connected(u64),
+    /// The connection was lost and the given reconnect attempt is in progress, starting at 1.
+    Reconnecting(u32),
+    /// The maximum number of reconnect attempts was reached. The connection stays in this state
+    /// until [`ReconnectingConnection::reconnect`] is called.
+    Failed,
+}
+
+/// Client connection that transparently reconnects when the connection to the server is lost.
+///
+/// When a read or write fails because the server went away, or a read reaches the end of the
+/// stream, the connection is re-established according to the [`ReconnectPolicy`] and the
+/// operation is retried on the new connection. Any data that was in flight when the connection
+/// was lost is not resent, so protocols built on top of this should be able to resume from a
+/// fresh connection.
+///
+/// Once [`ReconnectPolicy::max_attempts`] is reached, the error from the last attempt is
+/// returned and every later read or write fails with [`io::ErrorKind::NotConnected`] until
+/// [`ReconnectingConnection::reconnect`] is called.
+pub struct ReconnectingConnection {
+    state: State,
+    reconnect: Arc<Reconnect>,
+}
+
+enum State {
+    Connected(Connection),
+    Connecting(BoxFuture<'static, io::Result<Connection>>),
+    Failed,
+}
+
+struct Reconnect {
+    path: PathBuf,
+    options: ConnectOptions,
+    policy: ReconnectPolicy,
+    events: watch::Sender<ConnectionState>,
+}
+
+impl Reconnect {
+    async fn run(self: Arc<Self>) -> io::Result<Connection> {
+        let mut interval = self.policy.initial_interval;
+        let mut attempt = 1;
+        loop {
+            self.events
+                .send_replace(ConnectionState::Reconnecting(attempt));
+            match Endpoint::connect_with_options(self.path.clone(), self.options.clone()).await {
+                Ok(conn) => {
+                    debug!(id = conn.id(), attempt, "reconnected");
+                    self.events
+                        .send_replace(ConnectionState::Connected(conn.id()));
+                    return Ok(conn);
+                }
+                Err(e) if self.policy.max_attempts.is_some_and(|max| attempt >= max) => {
+                    debug!(error = ?e, attempt, "giving up reconnecting");
+                    self.events.send_replace(ConnectionState::Failed);
+                    return Err(e.into());
+                }
+                Err(_) => {
+                    tokio::time::sleep(interval).await;
+                    interval = self.policy.backoff.next_interval(interval);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+impl ReconnectingConnection {
+    /// Connects to the given path. The initial connection attempt isn't retried, so an error is
+    /// returned immediately if the server isn't available.
+    pub async fn connect(
+        path: impl IntoIpcPath,
+        options: ConnectOptions,
+        policy: ReconnectPolicy,
+    ) -> io::Result<Self> {
+        let path = path.into_ipc_path()?;
+        let conn = Endpoint::connect_with_options(path.clone(), options.clone()).await?;
+        let (events, _) = watch::channel(ConnectionState::Connected(conn.id()));
+        Ok(Self {
+            state: State::Connected(conn),
+            reconnect: Arc::new(Reconnect {
+                path,
+                options,
+                policy,
+                events,
+            }),
+        })
+    }
+
+    /// Returns a receiver that's notified whenever the connection state changes.
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.reconnect.events.subscribe()
+    }
+
+    /// Starts reconnecting again after giving up, with the attempt count reset to 1. Does
+    /// nothing unless the state is [`ConnectionState::Failed`].
+    pub fn reconnect(&mut self) {
+        if matches!(self.state, State::Failed) {
+            self.start_reconnecting();
+        }
+    }
+
+    fn start_reconnecting(&mut self) {
+        debug!("connection lost, reconnecting");
+        self.state = State::Connecting(self.reconnect.clone().run().boxed());
+    }
+
+    fn poll_connected(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&mut Connection>> {
+        if let State::Connecting(connect) = &mut self.state {
+            match ready!(connect.poll_unpin(cx)) {
+                Ok(conn) => self.state = State::Connected(conn),
+                Err(e) => {
+                    // Stay failed until the caller asks to start over
+                    self.state = State::Failed;
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+        match &mut self.state {
+            State::Connected(conn) => Poll::Ready(Ok(conn)),
+            State::Connecting(_) => unreachable!("connection was established above"),
+            State::Failed => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "gave up reconnecting to the server",
+            ))),
+        }
+    }
+}
+
+fn is_disconnect(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+impl AsyncRead for ReconnectingConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        loop {
+            let conn = ready!(this.poll_connected(cx))?;
+            let filled = buf.filled().len();
+            match ready!(Pin::new(conn).poll_read(cx, buf)) {
+                // The server closed the connection
+                Ok(()) if buf.filled().len() == filled && buf.remaining() > 0 => {
+                    this.start_reconnecting();
+                }
+                Ok(()) => return Poll::Ready(Ok(())),
+                Err(e) if is_disconnect(&e) => this.start_reconnecting(),
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for ReconnectingConnection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        loop {
+            let conn = ready!(this.poll_connected(cx))?;
+            match ready!(Pin::new(conn).poll_write(cx, buf)) {
+                Err(e) if is_disconnect(&e) => this.start_reconnecting(),
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        loop {
+            let conn = ready!(this.poll_connected(cx))?;
+            match ready!(Pin::new(conn).poll_write_vectored(cx, bufs)) {
+                Err(e) if is_disconnect(&e) => this.start_reconnecting(),
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match &self.state {
+            State::Connected(conn) => conn.is_write_vectored(),
+            State::Connecting(_) | State::Failed => false,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        match &mut this.state {
+            State::Connected(conn) => Pin::new(conn).poll_flush(cx),
+            // Nothing has been written to the new connection yet
+            State::Connecting(_) | State::Failed => Poll::Ready(Ok(())),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        match &mut this.state {
+            State::Connected(conn) => Pin::new(conn).poll_shutdown(cx),
+            State::Connecting(_) | State::Failed => Poll::Ready(Ok(())),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::channel::oneshot;
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use tokio::task::JoinHandle;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use crate::Connection;
+
+const REQUEST: u8 = 0;
+const RESPONSE: u8 = 1;
+const ERROR: u8 = 2;
+// Message kind followed by the request id
+const HEADER_LEN: usize = 1 + 8;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+type Sink = SplitSink<Framed<Connection, LengthDelimitedCodec>, Bytes>;
+// Set to `None` once the reader task exits, so new calls fail right away instead of waiting for
+// a response that can't arrive
+type Pending = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<io::Result<Bytes>>>>>>;
+
+/// Handles requests sent by an [`RpcClient`]. Used with [`serve_rpc`].
+///
+/// This is implemented for async closures that take the request payload and return either a
+/// response payload or an error message.
+pub trait RpcHandler: Send + Sync + 'static {
+    /// Handles a single request. Returning an error sends the message back to the caller, where
+    /// it's surfaced as an [`io::Error`].
+    fn handle(&self, request: Bytes) -> impl Future<Output = Result<Bytes, String>> + Send;
+}
+
+impl<F, Fut> RpcHandler for F
+where
+    F: Fn(Bytes) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Bytes, String>> + Send,
+{
+    fn handle(&self, request: Bytes) -> impl Future<Output = Result<Bytes, String>> + Send {
+        self(request)
+    }
+}
+
+/// Answers requests from an [`RpcClient`] on the other end of the connection until it
+/// disconnects.
+///
+/// Each request is handled in its own task, so slow requests don't hold up the rest. Responses
+/// are sent as soon as they're ready, which may be in a different order than the requests.
+pub async fn serve_rpc<H: RpcHandler>(connection: Connection, handler: H) -> io::Result<()> {
+    let handler = Arc::new(handler);
+    let (sink, mut stream) = Framed::new(connection, LengthDelimitedCodec::new()).split();
+    let sink = Arc::new(tokio::sync::Mutex::new(sink));
+    while let Some(frame) = stream.next().await {
+        let (kind, id, payload) = decode(frame?.freeze())?;
+        if kind != REQUEST {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "expected an RPC request",
+            ));
+        }
+        let handler = handler.clone();
+        let sink = sink.clone();
+        tokio::spawn(async move {
+            let frame = match handler.handle(payload).await {
+                Ok(response) => encode(RESPONSE, id, &response),
+                Err(message) => encode(ERROR, id, message.as_bytes()),
+            };
+            // Failing to send means the client is gone, which ends the read loop as well
+            let _ = sink.lock().await.send(frame).await;
+        });
+    }
+    Ok(())
+}
+
+/// Client that sends requests to a server running [`serve_rpc`] and waits for the responses.
+///
+/// Any number of requests can be in flight at once. Each one is tagged with an id so its response
+/// can be matched up with it, regardless of the order in which the server answers. The client can
+/// be cloned cheaply to make calls from multiple tasks.
+#[derive(Clone)]
+pub struct RpcClient {
+    inner: Arc<ClientInner>,
+}
+
+struct ClientInner {
+    sink: tokio::sync::Mutex<Sink>,
+    pending: Pending,
+    next_id: AtomicU64,
+    reader: JoinHandle<()>,
+}
+
+impl Drop for ClientInner {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl RpcClient {
+    /// Creates a client that sends requests over the connection. Must be called from within a
+    /// tokio runtime, since responses are read in a background task.
+    pub fn new(connection: Connection) -> Self {
+        let (sink, stream) = Framed::new(connection, LengthDelimitedCodec::new()).split();
+        let pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        let reader = tokio::spawn(read_responses(stream, pending.clone()));
+        Self {
+            inner: Arc::new(ClientInner {
+                sink: tokio::sync::Mutex::new(sink),
+                pending,
+                next_id: AtomicU64::new(0),
+                reader,
+            }),
+        }
+    }
+
+    /// Sends a request and waits up to 30 seconds for the response.
+    pub async fn call(&self, request: impl Into<Bytes>) -> io::Result<Bytes> {
+        self.call_with_timeout(request, DEFAULT_TIMEOUT).await
+    }
+
+    /// Sends a request and waits up to `timeout` for the response.
+    ///
+    /// The timeout covers sending the request as well as waiting for the response. If it
+    /// elapses, an error with [`io::ErrorKind::TimedOut`] is returned and the response is
+    /// discarded if it arrives later. Fails with [`io::ErrorKind::BrokenPipe`] without sending
+    /// anything if the connection has already closed.
+    pub async fn call_with_timeout(
+        &self,
+        request: impl Into<Bytes>,
+        timeout: Duration,
+    ) -> io::Result<Bytes> {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        lock_pending(&self.inner.pending)
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "RPC connection is closed"))?
+            .insert(id, tx);
+        // Make sure the request doesn't linger in the map if this future is dropped early
+        let _guard = PendingGuard {
+            pending: &self.inner.pending,
+            id,
+        };
+
+        let frame = encode(REQUEST, id, &request.into());
+        let response = async {
+            // Sending can block if the server stops reading, so it counts towards the timeout
+            self.inner.sink.lock().await.send(frame).await?;
+            rx.await.unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "connection closed before the response was received",
+                ))
+            })
+        };
+        tokio::time::timeout(timeout, response)
+            .await
+            .unwrap_or_else(|_| {
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out waiting for the response",
+                ))
+            })
+    }
+}
+
+struct PendingGuard<'a> {
+    pending: &'a Pending,
+    id: u64,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(pending) = lock_pending(self.pending).as_mut() {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+async fn read_responses(
+    mut stream: SplitStream<Framed<Connection, LengthDelimitedCodec>>,
+    pending: Pending,
+) {
+    while let Some(Ok(frame)) = stream.next().await {
+        let (id, response) = match decode(frame.freeze()) {
+            Ok((RESPONSE, id, payload)) => (id, Ok(payload)),
+            Ok((ERROR, id, payload)) => (
+                id,
+                Err(io::Error::other(
+                    String::from_utf8_lossy(&payload).into_owned(),
+                )),
+            ),
+            _ => break,
+        };
+        if let Some(tx) = lock_pending(&pending)
+            .as_mut()
+            .and_then(|pending| pending.remove(&id))
+        {
+            let _ = tx.send(response);
+        }
+    }
+    // Dropping the senders wakes up any callers that are still waiting
+    lock_pending(&pending).take();
+}
+
+fn lock_pending(
+    pending: &Pending,
+) -> std::sync::MutexGuard<'_, Option<HashMap<u64, oneshot::Sender<io::Result<Bytes>>>>> {
+    pending.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn encode(kind: u8, id: u64, payload: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(HEADER_LEN + payload.len());
+    frame.put_u8(kind);
+    frame.put_u64(id);
+    frame.put_slice(payload);
+    frame.freeze()
+}
+
+fn decode(mut frame: Bytes) -> io::Result<(u8, u64, Bytes)> {
+    if frame.len() < HEADER_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "RPC message is missing its header",
+        ));
+    }
+    let kind = frame.get_u8();
+    let id = frame.get_u64();
+    Ok((kind, id, frame))
+}
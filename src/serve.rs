@@ -0,0 +1,156 @@
+use std::future::{Future, IntoFuture};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{self, BoxFuture, Either};
+use futures::{FutureExt, StreamExt};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::{Connection, Endpoint};
+
+// Errors like running out of file descriptors usually persist for a while, so retrying right away
+// would just spin
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+impl Endpoint {
+    /// Accepts connections and handles each one in its own task using `handler`.
+    ///
+    /// The returned value must be awaited to start the server. It runs until the endpoint stops
+    /// accepting connections or the signal passed to [`Serve::with_graceful_shutdown`] completes,
+    /// and then waits for the tasks that are still running to finish. Errors accepting individual
+    /// connections are logged and skipped rather than stopping the server, after a short delay so
+    /// persistent errors don't keep the CPU busy.
+    pub fn serve<F, Fut>(self, handler: F) -> Serve<F>
+    where
+        F: Fn(Connection) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        Serve {
+            endpoint: self,
+            handler,
+            max_connections: None,
+            signal: None,
+        }
+    }
+}
+
+/// Future that runs an accept loop, created by [`Endpoint::serve`].
+pub struct Serve<F> {
+    endpoint: Endpoint,
+    handler: F,
+    max_connections: Option<usize>,
+    signal: Option<BoxFuture<'static, ()>>,
+}
+
+impl<F, Fut> Serve<F>
+where
+    F: Fn(Connection) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    /// Limit the number of connections that are handled at once. Once the limit is reached, no
+    /// more connections are accepted until one of the handlers finishes. Defaults to unlimited.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections.max(1));
+        self
+    }
+
+    /// Stop accepting connections once the given future completes.
+    ///
+    /// The server resolves once all of the handlers that are still running have finished.
+    pub fn with_graceful_shutdown<S>(mut self, signal: S) -> Self
+    where
+        S: Future<Output = ()> + Send + 'static,
+    {
+        self.signal = Some(Box::pin(signal));
+        self
+    }
+
+    async fn run(self) -> io::Result<()> {
+        let mut incoming = self.endpoint.incoming()?;
+        let mut signal = self.signal.unwrap_or_else(|| Box::pin(future::pending()));
+        let limit = self
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+        let handler = Arc::new(self.handler);
+        let mut tasks = JoinSet::new();
+        loop {
+            let permit = match &limit {
+                Some(limit) => {
+                    match future::select(Box::pin(limit.clone().acquire_owned()), &mut signal).await
+                    {
+                        // The semaphore is never closed
+                        Either::Left((permit, _)) => permit.ok(),
+                        Either::Right(_) => {
+                            debug!("shutdown signal received");
+                            break;
+                        }
+                    }
+                }
+                None => None,
+            };
+            let conn = match future::select(incoming.next(), &mut signal).await {
+                Either::Left((Some(Ok(conn)), _)) => conn,
+                Either::Left((Some(Err(e)), _)) => {
+                    debug!("error accepting connection: {e:?}");
+                    if !wait_after_accept_error(&mut signal).await {
+                        debug!("shutdown signal received");
+                        break;
+                    }
+                    continue;
+                }
+                Either::Left((None, _)) => break,
+                Either::Right(_) => {
+                    debug!("shutdown signal received");
+                    break;
+                }
+            };
+            let handler = handler.clone();
+            tasks.spawn(async move {
+                handler(conn).await;
+                drop(permit);
+            });
+            // Remove tasks that have already finished so the set doesn't keep growing
+            while let Some(Some(res)) = tasks.join_next().now_or_never() {
+                log_panic(res);
+            }
+        }
+        incoming.shutdown_handle().shutdown();
+        drop(incoming);
+        debug!(
+            active = tasks.len(),
+            "waiting for connection handlers to finish"
+        );
+        while let Some(res) = tasks.join_next().await {
+            log_panic(res);
+        }
+        Ok(())
+    }
+}
+
+impl<F, Fut> IntoFuture for Serve<F>
+where
+    F: Fn(Connection) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    type Output = io::Result<()>;
+    type IntoFuture = BoxFuture<'static, io::Result<()>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.run())
+    }
+}
+
+fn log_panic(res: Result<(), tokio::task::JoinError>) {
+    if let Err(e) = res {
+        debug!("connection handler panicked: {e:?}");
+    }
+}
+
+// Waits before accepting again after an error. Returns `false` if the shutdown signal completed in
+// the meantime.
+pub(crate) async fn wait_after_accept_error(signal: &mut BoxFuture<'static, ()>) -> bool {
+    let delay = Box::pin(tokio::time::sleep(ACCEPT_ERROR_DELAY));
+    matches!(future::select(delay, signal).await, Either::Left(_))
+}
@@ -0,0 +1,113 @@
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Snapshot of the activity on a connection, returned by
+/// [`Connection::stats`](crate::Connection::stats).
+///
+/// The halves of a split connection share the same statistics.
+#[derive(Clone, Copy, Debug)]
+pub struct ConnectionStats {
+    bytes_read: u64,
+    bytes_written: u64,
+    created_at: SystemTime,
+    last_activity: SystemTime,
+    idle_time: Duration,
+}
+
+impl ConnectionStats {
+    /// Total number of bytes read from the connection.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Total number of bytes written to the connection.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Time the connection was accepted or established.
+    pub fn created_at(&self) -> SystemTime {
+        self.created_at
+    }
+
+    /// Time of the last successful read or write, or the creation time if there hasn't been one.
+    pub fn last_activity(&self) -> SystemTime {
+        self.last_activity
+    }
+
+    /// How long it's been since the last successful read or write when the snapshot was taken.
+    ///
+    /// This is measured with a monotonic clock, so unlike comparing [`last_activity`] with the
+    /// current time, it isn't affected by changes to the system clock.
+    ///
+    /// [`last_activity`]: ConnectionStats::last_activity
+    pub fn idle_time(&self) -> Duration {
+        self.idle_time
+    }
+}
+
+pub(crate) struct StatsTracker {
+    created_at: SystemTime,
+    started: Instant,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    // Nanoseconds between `started` and the last activity
+    last_activity: AtomicU64,
+}
+
+impl StatsTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            created_at: SystemTime::now(),
+            started: Instant::now(),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            last_activity: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn record_read(&self, res: &Poll<io::Result<()>>, bytes: usize) {
+        if matches!(res, Poll::Ready(Ok(()))) {
+            self.add_bytes_read(bytes);
+        }
+    }
+
+    pub(crate) fn record_write(&self, res: &Poll<io::Result<usize>>) {
+        if let Poll::Ready(Ok(bytes)) = res {
+            self.add_bytes_written(*bytes);
+        }
+    }
+
+    pub(crate) fn add_bytes_read(&self, bytes: usize) {
+        if bytes > 0 {
+            self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+            self.touch();
+        }
+    }
+
+    pub(crate) fn add_bytes_written(&self, bytes: usize) {
+        if bytes > 0 {
+            self.bytes_written
+                .fetch_add(bytes as u64, Ordering::Relaxed);
+            self.touch();
+        }
+    }
+
+    fn touch(&self) {
+        let elapsed = u64::try_from(self.started.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.last_activity.fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ConnectionStats {
+        let last_activity = Duration::from_nanos(self.last_activity.load(Ordering::Relaxed));
+        ConnectionStats {
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            created_at: self.created_at,
+            last_activity: self.created_at + last_activity,
+            idle_time: self.started.elapsed().saturating_sub(last_activity),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::io::{self, IoSlice};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::{Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::{Connection, Endpoint, IpcStream, ShutdownHandle};
+
+impl Endpoint {
+    /// Stream of incoming connections from both the IPC endpoint and a TCP listener bound to
+    /// `addr`. Must be called from within a tokio runtime.
+    ///
+    /// This is meant for clients that can't reach the native socket or pipe, such as processes
+    /// running inside WSL or a container. Only loopback addresses are allowed; anything else fails
+    /// with [`io::ErrorKind::InvalidInput`]. Use port 0 to let the OS pick a free port and
+    /// [`DualIncoming::local_tcp_addr`] to find out which one it chose.
+    ///
+    /// Any local process can connect to a loopback port regardless of file permissions, and
+    /// settings like [`Endpoint::set_token_auth`] and [`Endpoint::set_peer_filter`] only apply to
+    /// IPC connections. Authenticate TCP clients yourself if that matters.
+    pub fn incoming_with_tcp(self, addr: SocketAddr) -> io::Result<DualIncoming> {
+        if !addr.ip().is_loopback() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unable to listen on {addr} because it isn't a loopback address"),
+            ));
+        }
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        debug!(addr = ?listener.local_addr(), "bound TCP fallback");
+        Ok(DualIncoming {
+            ipc: self.incoming()?,
+            tcp: listener,
+            poll_tcp_first: false,
+        })
+    }
+}
+
+/// Connection accepted by [`DualIncoming`].
+pub enum DualConnection {
+    /// Connection made through the IPC endpoint
+    Ipc(Connection),
+    /// Connection made through the TCP fallback
+    Tcp(TcpStream),
+}
+
+/// Stream of incoming connections from both an IPC endpoint and a loopback TCP listener,
+/// created by [`Endpoint::incoming_with_tcp`].
+///
+/// The stream ends once the IPC stream ends, which includes when it's shut down with the handle
+/// returned by [`DualIncoming::shutdown_handle`].
+pub struct DualIncoming {
+    ipc: IpcStream,
+    tcp: TcpListener,
+    // Alternate which listener is checked first so a busy one can't starve the other
+    poll_tcp_first: bool,
+}
+
+impl DualIncoming {
+    /// Returns a handle that can be used to stop accepting connections from another task.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.ipc.shutdown_handle()
+    }
+
+    /// Address the TCP listener is bound to.
+    pub fn local_tcp_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp.local_addr()
+    }
+
+    fn poll_tcp(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<DualConnection>> {
+        self.tcp.poll_accept(cx).map_ok(|(stream, addr)| {
+            trace!(?addr, "accepted TCP connection");
+            DualConnection::Tcp(stream)
+        })
+    }
+}
+
+impl Stream for DualIncoming {
+    type Item = io::Result<DualConnection>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        this.poll_tcp_first = !this.poll_tcp_first;
+        if this.poll_tcp_first {
+            if let Poll::Ready(res) = this.poll_tcp(cx) {
+                return Poll::Ready(Some(res));
+            }
+        }
+        if let Poll::Ready(conn) = this.ipc.poll_next_unpin(cx) {
+            return Poll::Ready(conn.map(|conn| conn.map(DualConnection::Ipc)));
+        }
+        if !this.poll_tcp_first {
+            if let Poll::Ready(res) = this.poll_tcp(cx) {
+                return Poll::Ready(Some(res));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl AsyncRead for DualConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match Pin::into_inner(self) {
+            Self::Ipc(c) => Pin::new(c).poll_read(ctx, buf),
+            Self::Tcp(t) => Pin::new(t).poll_read(ctx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for DualConnection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match Pin::into_inner(self) {
+            Self::Ipc(c) => Pin::new(c).poll_write(ctx, buf),
+            Self::Tcp(t) => Pin::new(t).poll_write(ctx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match Pin::into_inner(self) {
+            Self::Ipc(c) => Pin::new(c).poll_write_vectored(ctx, bufs),
+            Self::Tcp(t) => Pin::new(t).poll_write_vectored(ctx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Self::Ipc(c) => c.is_write_vectored(),
+            Self::Tcp(t) => t.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match Pin::into_inner(self) {
+            Self::Ipc(c) => Pin::new(c).poll_flush(ctx),
+            Self::Tcp(t) => Pin::new(t).poll_flush(ctx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match Pin::into_inner(self) {
+            Self::Ipc(c) => Pin::new(c).poll_shutdown(ctx),
+            Self::Tcp(t) => Pin::new(t).poll_shutdown(ctx),
+        }
+    }
+}
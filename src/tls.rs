@@ -0,0 +1,123 @@
+use std::io::{self, IoSlice};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, ServerConfig};
+use tokio_rustls::{client, server, TlsAcceptor, TlsConnector};
+
+use crate::Connection;
+
+enum TlsStream {
+    Client(Box<client::TlsStream<Connection>>),
+    Server(Box<server::TlsStream<Connection>>),
+}
+
+/// A [`Connection`] encrypted with TLS.
+///
+/// Local sockets and pipes are usually protected by filesystem permissions or ACLs, but that may
+/// not be sufficient if the endpoint lives in a shared location. TLS ensures that data can't be
+/// read or modified by anyone else and that the server is who it claims to be.
+///
+/// This doesn't enable any of [`rustls`](tokio_rustls::rustls)' crypto providers. You'll need to
+/// enable one in your own dependency on `rustls` or pass one to the config builders explicitly.
+pub struct SecureConnection {
+    inner: TlsStream,
+}
+
+impl SecureConnection {
+    /// Performs the client side of the TLS handshake over the connection.
+    pub async fn connect(
+        connection: Connection,
+        config: Arc<ClientConfig>,
+        server_name: ServerName<'static>,
+    ) -> io::Result<Self> {
+        let stream = TlsConnector::from(config)
+            .connect(server_name, connection)
+            .await?;
+        Ok(Self {
+            inner: TlsStream::Client(Box::new(stream)),
+        })
+    }
+
+    /// Performs the server side of the TLS handshake over the connection.
+    pub async fn accept(connection: Connection, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let stream = TlsAcceptor::from(config).accept(connection).await?;
+        Ok(Self {
+            inner: TlsStream::Server(Box::new(stream)),
+        })
+    }
+
+    /// Returns a reference to the underlying connection.
+    pub fn get_ref(&self) -> &Connection {
+        match &self.inner {
+            TlsStream::Client(c) => c.get_ref().0,
+            TlsStream::Server(s) => s.get_ref().0,
+        }
+    }
+}
+
+impl AsyncRead for SecureConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        match this.inner {
+            TlsStream::Client(ref mut c) => Pin::new(c).poll_read(ctx, buf),
+            TlsStream::Server(ref mut s) => Pin::new(s).poll_read(ctx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for SecureConnection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        match this.inner {
+            TlsStream::Client(ref mut c) => Pin::new(c).poll_write(ctx, buf),
+            TlsStream::Server(ref mut s) => Pin::new(s).poll_write(ctx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        match this.inner {
+            TlsStream::Client(ref mut c) => Pin::new(c).poll_write_vectored(ctx, bufs),
+            TlsStream::Server(ref mut s) => Pin::new(s).poll_write_vectored(ctx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self.inner {
+            TlsStream::Client(ref c) => c.is_write_vectored(),
+            TlsStream::Server(ref s) => s.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        match this.inner {
+            TlsStream::Client(ref mut c) => Pin::new(c).poll_flush(ctx),
+            TlsStream::Server(ref mut s) => Pin::new(s).poll_flush(ctx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        match this.inner {
+            TlsStream::Client(ref mut c) => Pin::new(c).poll_shutdown(ctx),
+            TlsStream::Server(ref mut s) => Pin::new(s).poll_shutdown(ctx),
+        }
+    }
+}
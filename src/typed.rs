@@ -0,0 +1,25 @@
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use crate::Connection;
+
+/// A [`Connection`] that sends and receives values serialized with a [`tokio_serde`] format,
+/// created by [`Connection::serde_framed`].
+///
+/// This implements `Stream<Item = Result<Item, _>>` and `Sink<SinkItem>`.
+pub type SerdeConnection<Item, SinkItem, Codec> =
+    tokio_serde::Framed<Framed<Connection, LengthDelimitedCodec>, Item, SinkItem, Codec>;
+
+impl Connection {
+    /// Wraps the connection with length-delimited framing and serializes each message with the
+    /// given format, such as `tokio_serde::formats::Json`.
+    ///
+    /// Messages are framed the same way as [`MessageConnection`](crate::MessageConnection), so
+    /// the other end doesn't have to use this to read them. The formats are enabled through
+    /// features of the `tokio-serde` crate.
+    pub fn serde_framed<Item, SinkItem, Codec>(
+        self,
+        codec: Codec,
+    ) -> SerdeConnection<Item, SinkItem, Codec> {
+        tokio_serde::Framed::new(self.framed(LengthDelimitedCodec::new()), codec)
+    }
+}
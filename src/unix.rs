@@ -1,39 +1,79 @@
 use std::env::temp_dir;
-use std::ffi::CString;
-use std::fs;
-use std::io::{self, Error};
+use std::ffi::{CString, OsStr};
+use std::io::{self, Error, Read};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, FileExt, FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 use std::task::{Context, Poll};
+use std::{fs, mem, ptr};
 
 use futures::Stream;
 use libc::chmod;
+use tokio::io::Interest;
+pub(crate) use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::{UnixListener, UnixStream};
-use tracing::trace;
 
-use crate::{IntoIpcPath, OnConflict, ServerId};
+use crate::{
+    Access, ConnectOptions, Direction, EndpointStatus, IntoIpcPath, OnConflict, PeerCredentials,
+    PeerProcess, RUNTIME_DIR_ENV,
+};
 
 pub(crate) struct SecurityAttributes {
     // read/write permissions for owner, group and others in unix octal.
     mode: Option<u16>,
+    // verify that the socket is owned by the current user after binding.
+    owner_only: bool,
+    owner: Option<libc::uid_t>,
+    group: Option<libc::gid_t>,
 }
 
 impl SecurityAttributes {
-    fn apply_permissions(&self, path: &str) -> io::Result<()> {
-        if let Some(mode) = self.mode {
+    fn apply_permissions(&self, path: &str, mode: Option<u16>) -> io::Result<()> {
+        if self.changes_ownership() {
+            let path = CString::new(path)?;
+            // -1 leaves the corresponding id unchanged
+            let owner = self.owner.unwrap_or(libc::uid_t::MAX);
+            let group = self.group.unwrap_or(libc::gid_t::MAX);
+            if unsafe { libc::chown(path.as_ptr(), owner, group) } == -1 {
+                return Err(Error::last_os_error());
+            }
+            trace!(?path, owner = ?self.owner, group = ?self.group, "changed socket ownership");
+        }
+        if let Some(mode) = mode {
             let path = CString::new(path)?;
             // mode_t doesn't need into() on mac but does on linux
             #[allow(clippy::useless_conversion)]
             if unsafe { chmod(path.as_ptr(), mode.into()) } == -1 {
                 return Err(Error::last_os_error());
             }
+            trace!(?path, mode = %format_args!("{mode:o}"), "applied socket permissions");
+        }
+        if self.owner_only && fs::metadata(path)?.uid() != unsafe { libc::geteuid() } {
+            return Err(Error::new(
+                io::ErrorKind::PermissionDenied,
+                "socket is not owned by the current user",
+            ));
         }
 
         Ok(())
     }
 
+    fn changes_ownership(&self) -> bool {
+        self.owner.is_some() || self.group.is_some()
+    }
+
     pub(crate) fn empty() -> Self {
-        Self { mode: Some(0o600) }
+        Self {
+            mode: Some(0o600),
+            owner_only: false,
+            owner: None,
+            group: None,
+        }
     }
 
     pub(crate) fn allow_everyone_connect(mut self) -> io::Result<Self> {
@@ -47,62 +87,338 @@ impl SecurityAttributes {
     }
 
     pub(crate) fn allow_everyone_create() -> io::Result<Self> {
-        Ok(Self { mode: None })
-    }
-}
-
-impl<T> ServerId<T>
-where
-    T: Into<String> + Send,
-{
-    pub(crate) fn into_ipc_path(self) -> io::Result<PathBuf> {
-        let sock_name = format!("{}.sock", self.0.into());
-        #[cfg(target_os = "macos")]
-        let path = match dirs::home_dir() {
-            Some(home) => {
-                let dir = home.join("Library/Caches/TemporaryItems");
-                if dir.exists() {
-                    dir.join(sock_name)
-                } else {
-                    temp_dir().join(sock_name)
-                }
-            }
-            None => temp_dir().join(sock_name),
-        };
+        Ok(Self {
+            mode: None,
+            ..Self::empty()
+        })
+    }
+
+    pub(crate) fn current_user_only() -> io::Result<Self> {
+        Ok(Self {
+            owner_only: true,
+            ..Self::empty()
+        })
+    }
+
+    pub(crate) fn set_owner(mut self, uid: u32) -> io::Result<Self> {
+        self.owner = Some(uid);
+        Ok(self)
+    }
+
+    pub(crate) fn set_group(mut self, gid: u32) -> io::Result<Self> {
+        self.group = Some(gid);
+        Ok(self)
+    }
+
+    pub(crate) fn allow_user(mut self, uid: u32, access: Access) -> io::Result<Self> {
+        self.owner = Some(uid);
+        self.mode = Some(self.mode.unwrap_or(0o600) & !0o700 | access_bits(access) << 6);
+        Ok(self)
+    }
+
+    pub(crate) fn allow_group(mut self, gid: u32, access: Access) -> io::Result<Self> {
+        self.group = Some(gid);
+        self.mode = Some(self.mode.unwrap_or(0o600) & !0o070 | access_bits(access) << 3);
+        Ok(self)
+    }
 
-        #[cfg(not(target_os = "macos"))]
-        let path = match dirs::runtime_dir() {
-            Some(runtime_dir) => runtime_dir.join(sock_name),
-            None => temp_dir().join(sock_name),
+    pub(crate) fn set_group_name(self, name: &str) -> io::Result<Self> {
+        let gid = group_id(name)?;
+        self.set_group(gid)
+    }
+}
+
+// Permission bits for a single class (owner, group or others)
+fn access_bits(access: Access) -> u16 {
+    match access {
+        Access::Read => 0o4,
+        Access::Write => 0o2,
+        Access::ReadWrite => 0o6,
+    }
+}
+
+fn group_id(name: &str) -> io::Result<libc::gid_t> {
+    let name = CString::new(name)?;
+    let mut group: libc::group = unsafe { mem::zeroed() };
+    let mut result = ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let code = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut group,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
         };
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)?;
+        match code {
+            0 if result.is_null() => {
+                return Err(Error::new(io::ErrorKind::NotFound, "group not found"));
+            }
+            0 => return Ok(group.gr_gid),
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            code => return Err(Error::from_raw_os_error(code)),
         }
-        Ok(path)
     }
 }
 
+pub(crate) fn default_path(name: &str) -> io::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os(RUNTIME_DIR_ENV).filter(|dir| !dir.is_empty()) {
+        return dir_path(Path::new(&dir), name);
+    }
+
+    #[cfg(target_os = "macos")]
+    let dir = match dirs::home_dir() {
+        Some(home) => {
+            let dir = home.join("Library/Caches/TemporaryItems");
+            if dir.exists() {
+                dir
+            } else {
+                temp_dir()
+            }
+        }
+        None => temp_dir(),
+    };
+
+    #[cfg(not(target_os = "macos"))]
+    let dir = dirs::runtime_dir().unwrap_or_else(temp_dir);
+    dir_path(&dir, name)
+}
+
+pub(crate) fn current_user_id() -> io::Result<String> {
+    Ok(unsafe { libc::geteuid() }.to_string())
+}
+
+pub(crate) fn dir_path(dir: &Path, name: &str) -> io::Result<PathBuf> {
+    let path = dir.join(format!("{name}.sock"));
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(path)
+}
+
+// Environment variables used to pass a listening socket to a child process
+const LISTEN_FD_ENV: &str = "TIPSY_LISTEN_FD";
+const LISTEN_PATH_ENV: &str = "TIPSY_LISTEN_PATH";
+const LOCK_FD_ENV: &str = "TIPSY_LOCK_FD";
+
 /// Endpoint implementation for unix systems
 pub(crate) struct Endpoint {
     path: PathBuf,
     security_attributes: SecurityAttributes,
+    on_conflict: OnConflict,
+    in_buffer_size: Option<u32>,
+    out_buffer_size: Option<u32>,
+    remove_on_drop: bool,
+    direction: Direction,
+    backlog: Option<u32>,
+    lock_file: bool,
+    // Listening socket handed off by the parent process, used instead of binding a new one
+    inherited: Option<OwnedFd>,
+    // Lock file that the parent process held, which is still locked
+    inherited_lock: Option<fs::File>,
 }
 
 impl Endpoint {
     /// Inner platform-dependant state of the endpoint
     pub(crate) fn inner(&self) -> io::Result<UnixListener> {
-        UnixListener::bind(&self.path)
+        let listener = self.bind()?;
+        listener.set_nonblocking(true)?;
+        UnixListener::from_std(listener)
+    }
+
+    fn bind(&self) -> io::Result<std::os::unix::net::UnixListener> {
+        if let Some(fd) = &self.inherited {
+            return Ok(fd.try_clone()?.into());
+        }
+        // The file is only removed here instead of in `new` so that it can't be removed out from
+        // under a server that holds the lock file
+        if self.on_conflict == OnConflict::Overwrite && self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        let listener = match self.bind_with_permissions() {
+            Err(e)
+                if e.kind() == io::ErrorKind::AddrInUse
+                    && self.on_conflict == OnConflict::OverwriteIfStale =>
+            {
+                if !is_stale_socket(&self.path) {
+                    return Err(e);
+                }
+                trace!("Removing stale socket file at: {:?}", self.path);
+                fs::remove_file(&self.path)?;
+                self.bind_with_permissions()
+            }
+            result => result,
+        }?;
+        set_buffer_sizes(listener.as_fd(), self.in_buffer_size, self.out_buffer_size)?;
+        if let Some(backlog) = self.backlog {
+            // Calling listen again on a listening socket only updates the backlog
+            let backlog = libc::c_int::try_from(backlog).unwrap_or(libc::c_int::MAX);
+            if unsafe { libc::listen(listener.as_raw_fd(), backlog) } == -1 {
+                return Err(Error::last_os_error());
+            }
+        }
+        debug!(path = ?self.path, "bound endpoint");
+        Ok(listener)
     }
 
-    pub(crate) fn incoming(self) -> io::Result<IpcStream> {
+    /// Binds the socket and applies its permissions before it becomes reachable at `path`.
+    ///
+    /// Changing the permissions after binding would leave a window where the socket is accessible
+    /// with the default mode, so the socket is bound inside a private directory, where its
+    /// permissions and ownership are applied, and then moved into place. This also allows
+    /// replacing an existing socket atomically.
+    ///
+    /// If the temporary path would be too long for a socket or the filesystem doesn't support
+    /// hard links, the socket is bound in place with a restrictive umask instead. The umask is
+    /// shared by the whole process, so this is only a fallback.
+    fn bind_with_permissions(&self) -> io::Result<std::os::unix::net::UnixListener> {
+        let attributes = &self.security_attributes;
+        let replace = self.on_conflict == OnConflict::Replace;
+        if !replace && attributes.mode.is_none() && !attributes.changes_ownership() {
+            return std::os::unix::net::UnixListener::bind(&self.path);
+        }
+        match PrivateDir::create(&self.path)? {
+            Some(dir) => match self.bind_in_private_dir(&dir, replace) {
+                // Some filesystems don't support hard links
+                Err(e) if !replace && is_link_unsupported(&e) => {
+                    trace!(error = ?e, "failed to link socket into place");
+                }
+                result => return result,
+            },
+            None if replace => {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{:?} is too long to be replaced, since the new socket has to be bound at \
+                         a longer temporary path first",
+                        self.path
+                    ),
+                ));
+            }
+            None => {}
+        }
+
+        let mask = match attributes.mode {
+            // Nobody but root can connect until the ownership has been changed
+            _ if attributes.changes_ownership() => 0o777,
+            Some(mode) => 0o777 & !libc::mode_t::from(mode),
+            None => return std::os::unix::net::UnixListener::bind(&self.path),
+        };
+        let (listener, umask) = bind_with_umask(&self.path, mask)?;
+        // Sockets are created with every permission that the umask allows. mode_t is u16 on mac
+        // but u32 on linux.
+        #[allow(clippy::unnecessary_cast)]
+        let mode = attributes.mode.unwrap_or(0o777 & !umask as u16);
+        attributes.apply_permissions(&self.path.to_string_lossy(), Some(mode))?;
+        Ok(listener)
+    }
+
+    fn bind_in_private_dir(
+        &self,
+        dir: &PrivateDir,
+        replace: bool,
+    ) -> io::Result<std::os::unix::net::UnixListener> {
+        let tmp_path = dir.socket_path();
+        let listener = std::os::unix::net::UnixListener::bind(&tmp_path)?;
+        let attributes = &self.security_attributes;
+        attributes.apply_permissions(&tmp_path.to_string_lossy(), attributes.mode)?;
+        if replace {
+            fs::rename(&tmp_path, &self.path)?;
+            trace!(path = ?self.path, "replaced socket");
+        } else {
+            // Linking fails if something already exists at the path, just like binding
+            match fs::hard_link(&tmp_path, &self.path) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    Err(Error::from_raw_os_error(libc::EADDRINUSE))
+                }
+                result => result,
+            }?;
+        }
+        Ok(listener)
+    }
+
+    /// Takes an exclusive lock on `{path}.lock` and writes the current pid to it. The lock is
+    /// released when the returned file is closed.
+    fn lock(&mut self) -> io::Result<Option<fs::File>> {
+        // The lock is shared with the parent process that handed off the socket, so it stays held
+        // once the parent exits
+        if let Some(file) = self.inherited_lock.take() {
+            write_lock_owner(&file)?;
+            return Ok(Some(file));
+        }
+        if !self.lock_file {
+            return Ok(None);
+        }
+        let mut lock_path = self.path.clone().into_os_string();
+        lock_path.push(".lock");
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(&lock_path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == -1 {
+            let e = Error::last_os_error();
+            if e.kind() != io::ErrorKind::WouldBlock {
+                return Err(e);
+            }
+            let mut owner = String::new();
+            file.read_to_string(&mut owner)?;
+            let owner = match owner.trim() {
+                "" => String::new(),
+                pid => format!(" (pid {pid})"),
+            };
+            return Err(Error::new(
+                io::ErrorKind::AddrInUse,
+                format!(
+                    "another server is already running on {:?}{owner}",
+                    self.path
+                ),
+            ));
+        }
+        // The file is never removed since another process could lock the old file after it's
+        // unlinked, which would allow two servers to run at once
+        write_lock_owner(&file)?;
+        trace!(?lock_path, "acquired lock file");
+        Ok(Some(file))
+    }
+
+    pub(crate) fn incoming(mut self) -> io::Result<IpcStream> {
+        let lock = self.lock()?;
         let listener = self.inner()?;
-        // the call to bind in `inner()` creates the file
-        // `apply_permission()` will set the file permissions.
-        self.security_attributes
-            .apply_permissions(&self.path.to_string_lossy())?;
         Ok(IpcStream {
-            path: Some(self.path),
+            cleanup: Cleanup::new(Some(self.path), self.remove_on_drop),
+            lock,
             listener,
+            in_buffer_size: self.in_buffer_size,
+            out_buffer_size: self.out_buffer_size,
+            direction: self.direction,
+        })
+    }
+
+    pub(crate) fn into_listener(self) -> io::Result<UnixListener> {
+        if self.lock_file {
+            return Err(Error::new(
+                io::ErrorKind::Unsupported,
+                "lock files are not supported by raw listeners",
+            ));
+        }
+        self.inner()
+    }
+
+    pub(crate) fn incoming_blocking(mut self) -> io::Result<BlockingIncoming> {
+        let lock = self.lock()?;
+        let listener = self.bind()?;
+        Ok(BlockingIncoming {
+            cleanup: Cleanup::new(Some(self.path), self.remove_on_drop),
+            _lock: lock,
+            listener,
+            in_buffer_size: self.in_buffer_size,
+            out_buffer_size: self.out_buffer_size,
+            direction: self.direction,
         })
     }
 
@@ -110,8 +426,56 @@ impl Endpoint {
         self.security_attributes = security_attributes;
     }
 
-    pub(crate) async fn connect(path: impl IntoIpcPath) -> io::Result<Connection> {
-        UnixStream::connect(path.into_ipc_path()?).await
+    pub(crate) fn set_remove_on_drop(&mut self, remove_on_drop: bool) {
+        self.remove_on_drop = remove_on_drop;
+    }
+
+    pub(crate) fn set_direction(&mut self, direction: Direction) {
+        self.direction = direction;
+    }
+
+    pub(crate) fn set_backlog(&mut self, backlog: u32) {
+        self.backlog = Some(backlog);
+    }
+
+    pub(crate) fn set_lock_file(&mut self, lock_file: bool) {
+        self.lock_file = lock_file;
+    }
+
+    pub(crate) fn set_in_buffer_size(&mut self, size: u32) {
+        self.in_buffer_size = Some(size);
+    }
+
+    pub(crate) fn set_out_buffer_size(&mut self, size: u32) {
+        self.out_buffer_size = Some(size);
+    }
+
+    pub(crate) async fn connect(
+        path: impl IntoIpcPath,
+        options: &ConnectOptions,
+    ) -> io::Result<Connection> {
+        let conn = UnixStream::connect(path.into_ipc_path()?).await?;
+        set_buffer_sizes(
+            conn.as_fd(),
+            options.in_buffer_size,
+            options.out_buffer_size,
+        )?;
+        restrict_direction(conn.as_fd(), options.direction, false)?;
+        Ok(conn)
+    }
+
+    pub(crate) fn connect_blocking(
+        path: impl IntoIpcPath,
+        options: &ConnectOptions,
+    ) -> io::Result<BlockingConnection> {
+        let conn = std::os::unix::net::UnixStream::connect(path.into_ipc_path()?)?;
+        set_buffer_sizes(
+            conn.as_fd(),
+            options.in_buffer_size,
+            options.out_buffer_size,
+        )?;
+        restrict_direction(conn.as_fd(), options.direction, false)?;
+        Ok(conn)
     }
 
     pub(crate) fn path(&self) -> &Path {
@@ -120,6 +484,7 @@ impl Endpoint {
 
     pub(crate) fn new(endpoint: impl IntoIpcPath, on_conflict: OnConflict) -> io::Result<Self> {
         let path = endpoint.into_ipc_path()?;
+        validate_path(&path)?;
         if std::path::Path::new(&path).exists() {
             match on_conflict {
                 OnConflict::Error => {
@@ -128,18 +493,377 @@ impl Endpoint {
                         format!("Unable to bind to {path:?} because the path already exists"),
                     ));
                 }
-                OnConflict::Overwrite => {
-                    fs::remove_file(&path)?;
-                }
-                OnConflict::Ignore => {}
+                // The file is removed when binding
+                OnConflict::Overwrite
+                | OnConflict::Ignore
+                | OnConflict::OverwriteIfStale
+                | OnConflict::Replace => {}
             }
         }
 
         Ok(Self {
             path,
             security_attributes: SecurityAttributes::empty(),
+            on_conflict,
+            in_buffer_size: None,
+            out_buffer_size: None,
+            remove_on_drop: true,
+            direction: Direction::Duplex,
+            backlog: None,
+            lock_file: false,
+            inherited: None,
+            inherited_lock: None,
         })
     }
+
+    pub(crate) fn from_inherited() -> io::Result<Option<Self>> {
+        let Some(fd) = std::env::var_os(LISTEN_FD_ENV) else {
+            return Ok(None);
+        };
+        let path = std::env::var_os(LISTEN_PATH_ENV);
+        let lock_fd = std::env::var_os(LOCK_FD_ENV);
+        // Don't pass the variables on to our own children. Changing the environment isn't
+        // thread-safe, which is why this has to be called before any threads are started.
+        std::env::remove_var(LISTEN_FD_ENV);
+        std::env::remove_var(LISTEN_PATH_ENV);
+        std::env::remove_var(LOCK_FD_ENV);
+
+        let path = path.ok_or_else(|| {
+            Error::new(
+                io::ErrorKind::InvalidInput,
+                "inherited socket path is missing",
+            )
+        })?;
+        let fd = inherited_fd(&fd, libc::S_IFSOCK, "socket")?;
+        let lock = lock_fd
+            .map(|lock_fd| inherited_fd(&lock_fd, libc::S_IFREG, "lock file"))
+            .transpose()?;
+        debug!(?path, "inherited listening socket");
+
+        let mut endpoint = Self::new(PathBuf::from(path), OnConflict::Ignore)?;
+        endpoint.inherited = Some(fd);
+        endpoint.inherited_lock = lock.map(fs::File::from);
+        Ok(Some(endpoint))
+    }
+}
+
+// Binds with the given umask and returns the umask that was in place before. The umask is shared
+// by the whole process, so files created by other threads in the meantime get it as well.
+fn bind_with_umask(
+    path: &Path,
+    mask: libc::mode_t,
+) -> io::Result<(std::os::unix::net::UnixListener, libc::mode_t)> {
+    // Keeps concurrent binds from restoring each other's umask
+    static UMASK_LOCK: Mutex<()> = Mutex::new(());
+
+    let _guard = UMASK_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let old_mask = unsafe { libc::umask(mask) };
+    let result = std::os::unix::net::UnixListener::bind(path);
+    unsafe { libc::umask(old_mask) };
+    Ok((result?, old_mask))
+}
+
+fn is_link_unsupported(e: &Error) -> bool {
+    // These are the same value on some platforms
+    e.raw_os_error()
+        .is_some_and(|code| [libc::EPERM, libc::EOPNOTSUPP, libc::ENOTSUP].contains(&code))
+}
+
+// Longest path that fits in sun_path, which needs room for the null terminator
+fn max_path_len() -> usize {
+    let addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_path.len() - 1
+}
+
+// Takes ownership of a descriptor that was passed in an environment variable by
+// `IpcStream::hand_off`
+fn inherited_fd(value: &OsStr, file_type: libc::mode_t, what: &str) -> io::Result<OwnedFd> {
+    let invalid = |message: String| Error::new(io::ErrorKind::InvalidInput, message);
+    let fd: RawFd = value
+        .to_str()
+        .and_then(|fd| fd.parse().ok())
+        .ok_or_else(|| invalid(format!("inherited {what} descriptor is invalid")))?;
+    let mut stat = unsafe { mem::zeroed::<libc::stat>() };
+    if unsafe { libc::fstat(fd, &mut stat) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    if stat.st_mode & libc::S_IFMT != file_type {
+        return Err(invalid(format!("inherited descriptor is not a {what}")));
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+// Records which process holds the lock so other servers can report it
+fn write_lock_owner(file: &fs::File) -> io::Result<()> {
+    file.set_len(0)?;
+    file.write_all_at(std::process::id().to_string().as_bytes(), 0)
+}
+
+// Catch paths that can never be bound so the caller gets a useful error message instead of
+// whatever the OS reports when binding
+pub(crate) fn validate_path(path: &Path) -> io::Result<()> {
+    let invalid = |reason: String| {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid socket path {path:?}: {reason}"),
+        ))
+    };
+    let bytes = path.as_os_str().as_bytes();
+    if bytes.is_empty() {
+        return invalid("the path is empty".to_owned());
+    }
+    if bytes.contains(&0) {
+        return invalid("the path contains a null byte".to_owned());
+    }
+    if path.file_name().is_none() {
+        return invalid("the path must end in a file name".to_owned());
+    }
+    let max_len = max_path_len();
+    if bytes.len() > max_len {
+        return invalid(format!(
+            "the path is {} bytes long, but socket paths are limited to {max_len} bytes. Consider \
+             using a shorter directory",
+            bytes.len()
+        ));
+    }
+    Ok(())
+}
+
+const PRIVATE_DIR_PREFIX: &str = ".tipsy-";
+const PRIVATE_SOCKET_NAME: &str = "s";
+
+/// Directory only accessible by the current user, created next to a socket path. The directory and
+/// everything in it are removed when this is dropped.
+struct PrivateDir {
+    path: PathBuf,
+}
+
+impl PrivateDir {
+    /// Returns `None` if a socket inside the directory would be over the socket path limit.
+    fn create(socket_path: &Path) -> io::Result<Option<Self>> {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let parent = match socket_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        // Keep the name short since socket paths have a small length limit
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let path = parent.join(format!("{PRIVATE_DIR_PREFIX}{}-{id}", std::process::id()));
+        if path.join(PRIVATE_SOCKET_NAME).as_os_str().len() > max_path_len() {
+            return Ok(None);
+        }
+        remove_stale_private_dirs(parent);
+        fs::DirBuilder::new().mode(0o700).create(&path)?;
+        Ok(Some(Self { path }))
+    }
+
+    fn socket_path(&self) -> PathBuf {
+        self.path.join(PRIVATE_SOCKET_NAME)
+    }
+}
+
+// Removes directories left behind by processes that exited while binding
+fn remove_stale_private_dirs(parent: &Path) {
+    let Ok(entries) = fs::read_dir(parent) else {
+        return;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let name = entry.file_name();
+        let Some(pid) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(PRIVATE_DIR_PREFIX))
+            .and_then(|name| name.split('-').next())
+            .and_then(|pid| pid.parse::<libc::pid_t>().ok())
+            .filter(|&pid| pid > 0)
+        else {
+            continue;
+        };
+        let exited = unsafe { libc::kill(pid, 0) } == -1
+            && Error::last_os_error().raw_os_error() == Some(libc::ESRCH);
+        if exited {
+            trace!(path = ?entry.path(), "removing stale private directory");
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+impl Drop for PrivateDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+fn is_stale_socket(path: &Path) -> bool {
+    // Never remove anything that isn't a socket
+    let is_socket = fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket());
+    is_socket
+        && matches!(
+            std::os::unix::net::UnixStream::connect(path),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused
+        )
+}
+
+pub(crate) fn probe(path: &Path) -> io::Result<EndpointStatus> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{path:?} is not a socket"),
+            ));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(EndpointStatus::NotFound),
+        _ => {}
+    }
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Ok(EndpointStatus::Listening),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(EndpointStatus::Stale),
+        // The socket may have been removed after we checked for it
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(EndpointStatus::NotFound),
+        Err(e) => Err(e),
+    }
+}
+
+pub(crate) async fn peek(conn: &mut Connection, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        conn.readable().await?;
+        match conn.try_io(Interest::READABLE, || {
+            let len = unsafe {
+                libc::recv(
+                    conn.as_raw_fd(),
+                    buf.as_mut_ptr().cast(),
+                    buf.len(),
+                    libc::MSG_PEEK,
+                )
+            };
+            if len == -1 {
+                return Err(Error::last_os_error());
+            }
+            Ok(len as usize)
+        }) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            res => return res,
+        }
+    }
+}
+
+/// Intermediate pipe for moving data between sockets with splice(2), which requires one side to
+/// be a pipe. Data is moved through it without ever being copied into userspace.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) struct SplicePipe {
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+impl SplicePipe {
+    pub(crate) fn new() -> io::Result<Self> {
+        let (read, write) = pipe()?;
+        Ok(Self { read, write })
+    }
+
+    /// Moves up to `len` bytes from `from` into the pipe, returning 0 at EOF.
+    pub(crate) async fn fill(&self, from: &mut Connection, len: usize) -> io::Result<usize> {
+        loop {
+            from.readable().await?;
+            match from.try_io(Interest::READABLE, || {
+                splice_fd(from.as_raw_fd(), self.write.as_raw_fd(), len)
+            }) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                res => return res,
+            }
+        }
+    }
+
+    /// Moves exactly `len` bytes that were put in the pipe by `fill` into `to`.
+    pub(crate) async fn drain(&self, to: &mut Connection, mut len: usize) -> io::Result<()> {
+        while len > 0 {
+            to.writable().await?;
+            match to.try_io(Interest::WRITABLE, || {
+                splice_fd(self.read.as_raw_fd(), to.as_raw_fd(), len)
+            }) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                res => len -= res?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn splice_fd(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+    let moved = unsafe {
+        libc::splice(
+            from,
+            ptr::null_mut(),
+            to,
+            ptr::null_mut(),
+            len,
+            libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+        )
+    };
+    if moved == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(moved as usize)
+}
+
+// Shuts down the half of the socket that isn't used in the given direction
+fn restrict_direction(fd: BorrowedFd<'_>, direction: Direction, server: bool) -> io::Result<()> {
+    let how = match (direction, server) {
+        (Direction::Duplex, _) => return Ok(()),
+        (Direction::Inbound, true) | (Direction::Outbound, false) => libc::SHUT_WR,
+        (Direction::Inbound, false) | (Direction::Outbound, true) => libc::SHUT_RD,
+    };
+    if unsafe { libc::shutdown(fd.as_raw_fd(), how) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Accepted sockets don't inherit buffer sizes from the listener on every platform, so these are
+// applied to each socket individually.
+fn set_buffer_sizes(
+    socket: BorrowedFd<'_>,
+    in_buffer_size: Option<u32>,
+    out_buffer_size: Option<u32>,
+) -> io::Result<()> {
+    for (option, size) in [
+        (libc::SO_RCVBUF, in_buffer_size),
+        (libc::SO_SNDBUF, out_buffer_size),
+    ] {
+        let Some(size) = size else {
+            continue;
+        };
+        let size = libc::c_int::try_from(size)
+            .map_err(|_| Error::new(io::ErrorKind::InvalidInput, "buffer size is too large"))?;
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                option,
+                (&size as *const libc::c_int).cast(),
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if result == -1 {
+            return Err(Error::last_os_error());
+        }
+    }
+    Ok(())
 }
 
 pub(crate) async fn from_std_stream(
@@ -149,9 +873,417 @@ pub(crate) async fn from_std_stream(
     UnixStream::from_std(stream)
 }
 
+pub(crate) fn into_split(conn: Connection) -> (OwnedReadHalf, OwnedWriteHalf) {
+    conn.into_split()
+}
+
+pub(crate) fn peer_addr(conn: &Connection) -> Option<PathBuf> {
+    conn.peer_addr()
+        .ok()
+        .and_then(|addr| addr.as_pathname().map(Path::to_path_buf))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn peer_credentials(conn: &Connection) -> io::Result<PeerCredentials> {
+    let mut ucred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            conn.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut ucred as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+    if result == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(PeerCredentials {
+        uid: Some(ucred.uid),
+        gid: Some(ucred.gid),
+        pid: Some(ucred.pid as u32),
+    })
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn peer_security_label(conn: &Connection) -> io::Result<String> {
+    let mut buf = vec![0u8; 256];
+    loop {
+        let mut len = buf.len() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                conn.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERSEC,
+                buf.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        if result == 0 {
+            buf.truncate(len as usize);
+            break;
+        }
+        let e = Error::last_os_error();
+        match e.raw_os_error() {
+            // The kernel reports the required size when the buffer is too small
+            Some(libc::ERANGE) if len as usize > buf.len() => buf.resize(len as usize, 0),
+            Some(libc::ENOPROTOOPT) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "No security module that labels sockets is active",
+                ))
+            }
+            _ => return Err(e),
+        }
+    }
+    // The label may or may not include a null terminator depending on the security module
+    while buf.last() == Some(&0) {
+        buf.pop();
+    }
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn autobind() -> io::Result<std::os::unix::net::UnixListener> {
+    let fd = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) };
+    if fd == -1 {
+        return Err(Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    // Passing an address that only contains the family tells the kernel to pick a unique name
+    // in the abstract namespace
+    let result = unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            ptr::addr_of!(addr).cast(),
+            mem::size_of::<libc::sa_family_t>() as libc::socklen_t,
+        )
+    };
+    if result == -1 {
+        return Err(Error::last_os_error());
+    }
+    if unsafe { libc::listen(fd.as_raw_fd(), libc::SOMAXCONN) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(fd.into())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn abstract_name(socket: BorrowedFd<'_>) -> io::Result<Option<Vec<u8>>> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_un>() as libc::socklen_t;
+    let result =
+        unsafe { libc::getsockname(socket.as_raw_fd(), ptr::addr_of_mut!(addr).cast(), &mut len) };
+    if result == -1 {
+        return Err(Error::last_os_error());
+    }
+    let path_len = (len as usize).saturating_sub(mem::size_of::<libc::sa_family_t>());
+    // Abstract names start with a null byte, unlike paths
+    match addr.sun_path[..path_len].split_first() {
+        Some((0, name)) => Ok(Some(name.iter().map(|&c| c as u8).collect())),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) async fn connect_abstract(name: Vec<u8>) -> io::Result<Connection> {
+    #[cfg(target_os = "android")]
+    use std::os::android::net::SocketAddrExt;
+    #[cfg(target_os = "linux")]
+    use std::os::linux::net::SocketAddrExt;
+
+    let stream = tokio::task::spawn_blocking(move || {
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        std::os::unix::net::UnixStream::connect_addr(&addr)
+    })
+    .await
+    .map_err(io::Error::other)??;
+    from_std_stream(stream).await
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+pub(crate) fn peer_credentials(conn: &Connection) -> io::Result<PeerCredentials> {
+    let mut uid = 0;
+    let mut gid = 0;
+    if unsafe { libc::getpeereid(conn.as_raw_fd(), &mut uid, &mut gid) } == -1 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(PeerCredentials {
+        uid: Some(uid),
+        gid: Some(gid),
+        pid: peer_pid(conn),
+    })
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn peer_pid(conn: &Connection) -> Option<u32> {
+    let mut pid: libc::pid_t = 0;
+    let mut len = mem::size_of::<libc::pid_t>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            conn.as_raw_fd(),
+            libc::SOL_LOCAL,
+            libc::LOCAL_PEERPID,
+            (&mut pid as *mut libc::pid_t).cast(),
+            &mut len,
+        )
+    };
+    (result == 0).then_some(pid as u32)
+}
+
+// getpeereid doesn't report the pid and there's no portable way to get it on the other BSDs
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+fn peer_pid(_conn: &Connection) -> Option<u32> {
+    None
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+pub(crate) fn peer_credentials(_conn: &Connection) -> io::Result<PeerCredentials> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Peer credentials are not supported on this platform",
+    ))
+}
+
+pub(crate) fn peer_process(conn: &Connection) -> io::Result<PeerProcess> {
+    let pid = peer_credentials(conn)?.pid().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Peer process id is not available on this platform",
+        )
+    })?;
+    Ok(PeerProcess::new(pid, process_exe(pid)))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn process_exe(pid: u32) -> Option<PathBuf> {
+    // Reading another user's exe link requires ptrace access, so this is best-effort
+    std::fs::read_link(format!("/proc/{pid}/exe")).ok()
+}
+
+#[cfg(target_os = "macos")]
+fn process_exe(pid: u32) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut buf = vec![0u8; libc::PROC_PIDPATHINFO_MAXSIZE as usize];
+    let len = unsafe { libc::proc_pidpath(pid as i32, buf.as_mut_ptr().cast(), buf.len() as u32) };
+    if len <= 0 {
+        return None;
+    }
+    Some(PathBuf::from(std::ffi::OsStr::from_bytes(
+        &buf[..len as usize],
+    )))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos")))]
+fn process_exe(_pid: u32) -> Option<PathBuf> {
+    None
+}
+
+pub(crate) async fn send_handle(conn: &mut Connection, fd: BorrowedFd<'_>) -> io::Result<()> {
+    loop {
+        conn.writable().await?;
+        match conn.try_io(Interest::WRITABLE, || {
+            send_fd(conn.as_raw_fd(), fd.as_raw_fd())
+        }) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            res => return res,
+        }
+    }
+}
+
+pub(crate) async fn recv_handle(conn: &mut Connection) -> io::Result<OwnedFd> {
+    loop {
+        conn.readable().await?;
+        match conn.try_io(Interest::READABLE, || recv_fd(conn.as_raw_fd())) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            res => return res,
+        }
+    }
+}
+
+// Large enough and suitably aligned to hold a control message with a single file descriptor
+type ControlBuffer = [u64; 4];
+
+fn send_fd(socket: RawFd, fd: RawFd) -> io::Result<()> {
+    // At least one byte of regular data needs to be sent along with the control message
+    let mut data = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+    let mut control: ControlBuffer = [0; 4];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd);
+    }
+    if unsafe { libc::sendmsg(socket, &msg, 0) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn recv_fd(socket: RawFd) -> io::Result<OwnedFd> {
+    let mut data = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr().cast(),
+        iov_len: data.len(),
+    };
+    let mut control: ControlBuffer = [0; 4];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of::<ControlBuffer>() as _;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    let flags = libc::MSG_CMSG_CLOEXEC;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    let flags = 0;
+    let received = unsafe { libc::recvmsg(socket, &mut msg, flags) };
+    if received == -1 {
+        return Err(Error::last_os_error());
+    }
+    if received == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let mut received_fd = None;
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                for i in 0..len / mem::size_of::<RawFd>() {
+                    // Take ownership of every descriptor so any unexpected extras get closed
+                    let fd = OwnedFd::from_raw_fd(ptr::read_unaligned(data.add(i)));
+                    received_fd.get_or_insert(fd);
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    let fd = received_fd
+        .ok_or_else(|| Error::new(io::ErrorKind::InvalidData, "no file descriptor received"))?;
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+/// Removes the socket file once the listener is no longer in use.
+#[derive(Clone)]
+pub(crate) struct Cleanup {
+    path: Arc<Mutex<Option<PathBuf>>>,
+    // Whether the file is removed on drop and shutdown, or only when `remove` is called
+    automatic: bool,
+    // Device and inode of the socket file, so a socket that has since been replaced by another
+    // server isn't removed
+    file_id: Option<(u64, u64)>,
+}
+
+impl Cleanup {
+    fn new(path: Option<PathBuf>, automatic: bool) -> Self {
+        let file_id = path
+            .as_ref()
+            .and_then(|path| fs::symlink_metadata(path).ok())
+            .map(|metadata| (metadata.dev(), metadata.ino()));
+        Self {
+            path: Arc::new(Mutex::new(path)),
+            automatic,
+            file_id,
+        }
+    }
+
+    // Moves the path into a new cleanup so that running this one no longer removes it
+    pub(crate) fn take(&self) -> Self {
+        Self {
+            path: Arc::new(Mutex::new(
+                self.path
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take(),
+            )),
+            automatic: self.automatic,
+            file_id: self.file_id,
+        }
+    }
+
+    pub(crate) fn run(&self) {
+        if self.automatic {
+            self.remove();
+        }
+    }
+
+    pub(crate) fn remove(&self) {
+        let path = match self.path.lock() {
+            Ok(mut path) => path.take(),
+            Err(e) => e.into_inner().take(),
+        };
+        if let Some(path) = path {
+            let current_id = fs::symlink_metadata(&path)
+                .ok()
+                .map(|metadata| (metadata.dev(), metadata.ino()));
+            if self.file_id.is_some() && current_id != self.file_id {
+                trace!("Socket file at {:?} was replaced, not removing it", path);
+                return;
+            }
+            if let Ok(()) = fs::remove_file(&path) {
+                trace!("Removed socket file at: {:?}", path);
+            }
+        }
+    }
+}
+
 pub(crate) struct IpcStream {
-    path: Option<PathBuf>,
+    cleanup: Cleanup,
+    // Dropped after the socket file is cleaned up so another server can't bind in between
+    lock: Option<fs::File>,
     listener: UnixListener,
+    in_buffer_size: Option<u32>,
+    out_buffer_size: Option<u32>,
+    direction: Direction,
 }
 
 impl IpcStream {
@@ -159,11 +1291,98 @@ impl IpcStream {
         listener: std::os::unix::net::UnixListener,
     ) -> io::Result<Self> {
         listener.set_nonblocking(true)?;
-        let listener = UnixListener::from_std(listener)?;
-        Ok(Self {
-            path: None,
+        Ok(Self::from_tokio_listener(
+            UnixListener::from_std(listener)?,
+            None,
+        ))
+    }
+
+    pub(crate) fn from_tokio_listener(listener: UnixListener, path: Option<PathBuf>) -> Self {
+        Self {
+            cleanup: Cleanup::new(path, true),
+            lock: None,
             listener,
-        })
+            in_buffer_size: None,
+            out_buffer_size: None,
+            direction: Direction::Duplex,
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn abstract_name(&self) -> io::Result<Option<Vec<u8>>> {
+        abstract_name(self.listener.as_fd())
+    }
+
+    /// Retrieves the socket that launchd created for the given entry in the job's `Sockets`
+    /// dictionary.
+    #[cfg(target_os = "macos")]
+    pub(crate) fn from_launchd(name: &str) -> io::Result<Self> {
+        extern "C" {
+            fn launch_activate_socket(
+                name: *const libc::c_char,
+                fds: *mut *mut libc::c_int,
+                cnt: *mut libc::size_t,
+            ) -> libc::c_int;
+        }
+
+        let name = CString::new(name)?;
+        let mut fds: *mut libc::c_int = ptr::null_mut();
+        let mut count = 0;
+        let result = unsafe { launch_activate_socket(name.as_ptr(), &mut fds, &mut count) };
+        if result != 0 {
+            return Err(Error::from_raw_os_error(result));
+        }
+        // Take ownership of every descriptor so any extras get closed
+        let fds = unsafe {
+            let owned: Vec<_> = (0..count)
+                .map(|i| OwnedFd::from_raw_fd(*fds.add(i)))
+                .collect();
+            libc::free(fds.cast());
+            owned
+        };
+        let fd = fds.into_iter().next().ok_or_else(|| {
+            Error::new(
+                io::ErrorKind::NotFound,
+                "launchd did not provide any sockets",
+            )
+        })?;
+        Self::from_std_listener(fd.into())
+    }
+
+    pub(crate) fn cleanup(&self) -> Cleanup {
+        self.cleanup.clone()
+    }
+
+    pub(crate) fn hand_off(
+        &self,
+        path: &Path,
+        command: &mut std::process::Command,
+    ) -> io::Result<std::process::Child> {
+        let fd = self.listener.as_raw_fd();
+        let lock_fd = self.lock.as_ref().map(AsRawFd::as_raw_fd);
+        command
+            .env(LISTEN_FD_ENV, fd.to_string())
+            .env(LISTEN_PATH_ENV, path);
+        match lock_fd {
+            Some(lock_fd) => command.env(LOCK_FD_ENV, lock_fd.to_string()),
+            None => command.env_remove(LOCK_FD_ENV),
+        };
+        // Close-on-exec is only cleared in the child so the descriptors don't leak into any other
+        // processes that are started in the meantime
+        unsafe {
+            command.pre_exec(move || {
+                for fd in std::iter::once(fd).chain(lock_fd) {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) == -1 {
+                        return Err(Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        // The child takes over the socket file, so it's no longer removed on drop
+        self.cleanup.take();
+        Ok(child)
     }
 }
 
@@ -176,17 +1395,46 @@ impl Stream for IpcStream {
         let this = Pin::into_inner(self);
         match Pin::new(&mut this.listener).poll_accept(cx) {
             Poll::Pending => Poll::Pending,
-            Poll::Ready(result) => Poll::Ready(Some(result.map(|(stream, _addr)| stream))),
+            Poll::Ready(result) => Poll::Ready(Some(result.and_then(|(stream, _addr)| {
+                set_buffer_sizes(stream.as_fd(), this.in_buffer_size, this.out_buffer_size)?;
+                restrict_direction(stream.as_fd(), this.direction, true)?;
+                Ok(stream)
+            }))),
         }
     }
 }
 
 impl Drop for IpcStream {
     fn drop(&mut self) {
-        if let Some(path) = &self.path {
-            if let Ok(()) = fs::remove_file(path) {
-                trace!("Removed socket file at: {:?}", path);
-            }
-        }
+        self.cleanup.run();
+    }
+}
+
+pub(crate) type BlockingConnection = std::os::unix::net::UnixStream;
+
+pub(crate) struct BlockingIncoming {
+    cleanup: Cleanup,
+    _lock: Option<fs::File>,
+    listener: std::os::unix::net::UnixListener,
+    in_buffer_size: Option<u32>,
+    out_buffer_size: Option<u32>,
+    direction: Direction,
+}
+
+impl Iterator for BlockingIncoming {
+    type Item = io::Result<BlockingConnection>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.listener.accept().and_then(|(stream, _addr)| {
+            set_buffer_sizes(stream.as_fd(), self.in_buffer_size, self.out_buffer_size)?;
+            restrict_direction(stream.as_fd(), self.direction, true)?;
+            Ok(stream)
+        }))
+    }
+}
+
+impl Drop for BlockingIncoming {
+    fn drop(&mut self) {
+        self.cleanup.run();
     }
 }
@@ -1,96 +1,263 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, IoSlice};
+use std::mem::ManuallyDrop;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::os::windows::io::{
+    AsHandle, AsRawHandle, BorrowedHandle, FromRawHandle, IntoRawHandle, OwnedHandle, RawHandle,
+};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
-use std::{io, marker, mem, ptr};
+use std::{marker, mem, ptr};
 
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
 use futures::{Stream, StreamExt};
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, Interest, ReadHalf, Ready, WriteHalf,
+};
 use tokio::net::windows::named_pipe;
+use tokio::sync::mpsc;
+use windows_sys::core::PWSTR;
 use windows_sys::Win32::Foundation::{
-    LocalFree, ERROR_PIPE_BUSY, ERROR_SUCCESS, GENERIC_READ, GENERIC_WRITE, HLOCAL, PSID,
+    DuplicateHandle, LocalFree, DUPLICATE_SAME_ACCESS, ERROR_ACCESS_DENIED, ERROR_FILE_NOT_FOUND,
+    ERROR_PIPE_BUSY, ERROR_PIPE_CONNECTED, ERROR_SEM_TIMEOUT, ERROR_SUCCESS, GENERIC_ALL,
+    GENERIC_READ, GENERIC_WRITE, HANDLE, HLOCAL, INVALID_HANDLE_VALUE, PSID,
 };
 use windows_sys::Win32::Security::Authorization::{
-    SetEntriesInAclW, ACCESS_MODE, EXPLICIT_ACCESS_W, SET_ACCESS, TRUSTEE_IS_SID,
+    ConvertSecurityDescriptorToStringSecurityDescriptorW, ConvertSidToStringSidW,
+    ConvertStringSecurityDescriptorToSecurityDescriptorW, ConvertStringSidToSidW, SetEntriesInAclW,
+    ACCESS_MODE, DENY_ACCESS, EXPLICIT_ACCESS_W, GRANT_ACCESS, SDDL_REVISION_1, SET_ACCESS,
+    TRUSTEE_IS_NAME, TRUSTEE_IS_SID, TRUSTEE_IS_UNKNOWN, TRUSTEE_IS_USER,
     TRUSTEE_IS_WELL_KNOWN_GROUP, TRUSTEE_TYPE,
 };
 use windows_sys::Win32::Security::{
-    AllocateAndInitializeSid, FreeSid, InitializeSecurityDescriptor, SetSecurityDescriptorDacl,
-    ACL, PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES, SECURITY_DESCRIPTOR, SID_IDENTIFIER_AUTHORITY,
+    AllocateAndInitializeSid, FreeSid, GetTokenInformation, InitializeSecurityDescriptor,
+    IsValidSecurityDescriptor, RevertToSelf, SetSecurityDescriptorDacl, TokenUser, ACL,
+    DACL_SECURITY_INFORMATION, GROUP_SECURITY_INFORMATION, LABEL_SECURITY_INFORMATION,
+    OWNER_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR, SACL_SECURITY_INFORMATION,
+    SECURITY_ATTRIBUTES, SECURITY_DESCRIPTOR, SID_IDENTIFIER_AUTHORITY, TOKEN_QUERY, TOKEN_USER,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    CreateFileW, FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, FILE_FLAG_WRITE_THROUGH,
+    FILE_READ_ATTRIBUTES, FILE_WRITE_ATTRIBUTES, FILE_WRITE_DATA, OPEN_EXISTING,
+    PIPE_ACCESS_DUPLEX, PIPE_ACCESS_INBOUND, PIPE_ACCESS_OUTBOUND, SECURITY_IDENTIFICATION,
+    SECURITY_SQOS_PRESENT,
 };
-use windows_sys::Win32::Storage::FileSystem::FILE_WRITE_DATA;
 use windows_sys::Win32::System::Memory::{LocalAlloc, LPTR};
+use windows_sys::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, GetNamedPipeClientProcessId, GetNamedPipeClientSessionId,
+    GetNamedPipeHandleStateW, GetNamedPipeInfo, GetNamedPipeServerProcessId,
+    GetNamedPipeServerSessionId, ImpersonateNamedPipeClient, SetNamedPipeHandleState,
+    WaitNamedPipeW, PIPE_ACCEPT_REMOTE_CLIENTS, PIPE_READMODE_BYTE, PIPE_READMODE_MESSAGE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_SERVER_END, PIPE_TYPE_BYTE, PIPE_TYPE_MESSAGE,
+    PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use windows_sys::Win32::System::RemoteDesktop::ProcessIdToSessionId;
 use windows_sys::Win32::System::SystemServices::{
     SECURITY_DESCRIPTOR_REVISION, SECURITY_WORLD_RID,
 };
+use windows_sys::Win32::System::Threading::{
+    GetCurrentProcess, OpenProcess, OpenProcessToken, QueryFullProcessImageNameW,
+    PROCESS_ACCESS_RIGHTS, PROCESS_DUP_HANDLE, PROCESS_NAME_WIN32,
+    PROCESS_QUERY_LIMITED_INFORMATION,
+};
 
-use crate::{IntoIpcPath, OnConflict, ServerId};
+use crate::{
+    Access, ConnectOptions, Direction, EndpointStatus, IntoIpcPath, NamedPipe, OnConflict,
+    PeerCredentials, PeerProcess, PipeInfo, PipeMode, Trustee,
+};
 
-enum NamedPipe {
-    Server(named_pipe::NamedPipeServer),
-    Client(named_pipe::NamedPipeClient),
+const DEFAULT_BUFFER_SIZE: u32 = 65536;
+// How often the server checks whether an instance has been freed up once `max_instances` is hit
+const INSTANCE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+impl AsRawHandle for NamedPipe {
+    fn as_raw_handle(&self) -> RawHandle {
+        match self {
+            Self::Server(s) => s.as_raw_handle(),
+            Self::Client(c) => c.as_raw_handle(),
+        }
+    }
 }
 
-const PIPE_AVAILABILITY_TIMEOUT: Duration = Duration::from_secs(5);
+impl AsHandle for NamedPipe {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        match self {
+            Self::Server(s) => s.as_handle(),
+            Self::Client(c) => c.as_handle(),
+        }
+    }
+}
 
-impl<T> ServerId<T>
-where
-    T: Into<String> + Send,
-{
-    pub(crate) fn into_ipc_path(self) -> io::Result<PathBuf> {
-        Ok(PathBuf::from(format!(
-            r"\\.\pipe\{}",
-            self.0.into().replace('/', "\\")
-        )))
+impl From<PipeMode> for named_pipe::PipeMode {
+    fn from(value: PipeMode) -> Self {
+        match value {
+            PipeMode::Byte => Self::Byte,
+            PipeMode::Message => Self::Message,
+        }
     }
 }
 
+pub(crate) fn default_path(name: &str) -> io::Result<PathBuf> {
+    Ok(PathBuf::from(format!(
+        r"\\.\pipe\{}",
+        name.replace('/', "\\")
+    )))
+}
+
+// Named pipes don't live in the file system, so there's no directory to put them in
+pub(crate) fn dir_path(_dir: &Path, name: &str) -> io::Result<PathBuf> {
+    default_path(name)
+}
+
 pub(crate) struct Endpoint {
     path: PathBuf,
     security_attributes: SecurityAttributes,
+    pipe_mode: PipeMode,
+    in_buffer_size: u32,
+    out_buffer_size: u32,
+    max_instances: Option<u8>,
+    join_existing: bool,
+    write_through: bool,
+    reject_remote_clients: bool,
+    direction: Direction,
+    reuse_instances: bool,
+    standby_instances: usize,
     created_listener: bool,
 }
 
+// Pipe instance waiting for a client to connect
+type PendingListener = BoxFuture<'static, io::Result<named_pipe::NamedPipeServer>>;
+
+fn wait_for_client(listener: named_pipe::NamedPipeServer) -> PendingListener {
+    Box::pin(async move {
+        listener.connect().await?;
+        Ok(listener)
+    })
+}
+
 impl Endpoint {
-    fn create_listener(&mut self) -> io::Result<named_pipe::NamedPipeServer> {
-        let server = unsafe {
-            named_pipe::ServerOptions::new()
-                .first_pipe_instance(!self.created_listener)
-                .reject_remote_clients(true)
-                .access_inbound(true)
-                .access_outbound(true)
-                .in_buffer_size(65536)
-                .out_buffer_size(65536)
-                .create_with_security_attributes_raw(
-                    &self.path,
-                    self.security_attributes.as_ptr().cast_mut().cast(),
-                )
-        }?;
-        self.created_listener = true;
+    pub(crate) fn create_listener(&mut self) -> io::Result<named_pipe::NamedPipeServer> {
+        // tokio's `ServerOptions` can't set FILE_FLAG_WRITE_THROUGH, so the pipe is created
+        // directly and registered with the reactor afterwards
+        let pipe = self.create_pipe(FILE_FLAG_OVERLAPPED)?;
+        unsafe { named_pipe::NamedPipeServer::from_raw_handle(pipe.into_raw_handle()) }
+    }
+
+    fn is_first_instance(&self) -> bool {
+        !self.created_listener && !self.join_existing
+    }
+
+    // Creating the first instance fails with ERROR_ACCESS_DENIED if another server already owns
+    // the pipe name. Replace the unhelpful default message with one that says who the owner is.
+    fn conflict_error(&self, e: io::Error) -> io::Error {
+        if !self.is_first_instance() || e.raw_os_error() != Some(ERROR_ACCESS_DENIED as i32) {
+            return e;
+        }
+        let message = match pipe_owner_pid(&self.path) {
+            Some(pid) => format!(
+                "Unable to bind to {:?} because it is already in use by process {pid}",
+                self.path
+            ),
+            None => format!(
+                "Unable to bind to {:?} because it is already in use by another process",
+                self.path
+            ),
+        };
+        io::Error::new(io::ErrorKind::AddrInUse, message)
+    }
+
+    // Returns an instance that was freed up by a closed connection, or creates a new one
+    fn next_listener(
+        &mut self,
+        recycled: &mut mpsc::UnboundedReceiver<named_pipe::NamedPipeServer>,
+    ) -> io::Result<named_pipe::NamedPipeServer> {
+        match recycled.try_recv() {
+            Ok(listener) => {
+                trace!("reusing pipe instance");
+                Ok(listener)
+            }
+            Err(_) => self.create_listener(),
+        }
+    }
 
-        Ok(server)
+    // Adds pipe instances until `standby_instances` of them are waiting for clients at once.
+    // Stops early if the instance limit has been reached.
+    fn fill_standby(
+        &mut self,
+        pending: &mut FuturesUnordered<PendingListener>,
+        recycled: &mut mpsc::UnboundedReceiver<named_pipe::NamedPipeServer>,
+    ) -> io::Result<()> {
+        while pending.len() < self.standby_instances {
+            match self.next_listener(recycled) {
+                Ok(listener) => pending.push(wait_for_client(listener)),
+                // Wait for a connection to close before creating the next instance
+                Err(e) if is_instance_limit(&e) => {
+                    debug!("reached the maximum number of pipe instances");
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // Creates the next pipe instance, waiting for an existing instance to be closed if the
+    // instance limit has been reached.
+    async fn wait_for_listener(
+        &mut self,
+        recycled: &mut mpsc::UnboundedReceiver<named_pipe::NamedPipeServer>,
+    ) -> io::Result<named_pipe::NamedPipeServer> {
+        loop {
+            match self.next_listener(recycled) {
+                Err(e) if is_instance_limit(&e) => {
+                    tokio::time::sleep(INSTANCE_POLL_INTERVAL).await;
+                }
+                res => return res,
+            }
+        }
     }
 
-    pub(crate) async fn connect(path: impl IntoIpcPath) -> io::Result<Connection> {
+    pub(crate) async fn connect(
+        path: impl IntoIpcPath,
+        options: &ConnectOptions,
+    ) -> io::Result<Connection> {
         let path = path.into_ipc_path()?;
+        let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
 
-        // There is not async equivalent of waiting for a named pipe in Windows,
-        // so we keep trying or sleeping for a bit, until we hit a timeout
         let attempt_start = Instant::now();
         let client = loop {
             match named_pipe::ClientOptions::new()
-                .read(true)
-                .write(true)
+                .read(options.direction != Direction::Inbound)
+                .write(options.direction != Direction::Outbound)
+                .pipe_mode(options.pipe_mode.into())
+                // tokio passes these flags straight through to CreateFileW, which is the only way
+                // to request write-through
+                .security_qos_flags(SECURITY_IDENTIFICATION | write_through_flag(options))
                 .open(&path)
             {
                 Ok(client) => break client,
                 Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {
-                    if attempt_start.elapsed() < PIPE_AVAILABILITY_TIMEOUT {
-                        tokio::time::sleep(Duration::from_millis(50)).await;
-                        continue;
-                    } else {
+                    let remaining = options.busy_timeout.saturating_sub(attempt_start.elapsed());
+                    if remaining.is_zero() {
                         return Err(e);
                     }
+                    debug!(?remaining, "all pipe instances are busy, waiting");
+                    // There's no async equivalent of waiting for a named pipe, so the wait runs
+                    // on the blocking thread pool. It keeps running until an instance is
+                    // available or the timeout elapses, even if this future is dropped.
+                    let wide_path = wide_path.clone();
+                    let available =
+                        tokio::task::spawn_blocking(move || wait_named_pipe(&wide_path, remaining))
+                            .await
+                            .map_err(io::Error::other)??;
+                    if !available {
+                        return Err(e);
+                    }
+                    // Another client may take the instance first, in which case we wait again
                 }
                 Err(e) => return Err(e),
             }
@@ -103,18 +270,192 @@ impl Endpoint {
         IpcStream::new(self)
     }
 
+    // Creates a pipe instance for synchronous use. Unlike `create_listener`, the handle isn't
+    // registered with the tokio reactor.
+    fn create_blocking_listener(&mut self) -> io::Result<OwnedHandle> {
+        self.create_pipe(0)
+    }
+
+    fn create_pipe(&mut self, flags: u32) -> io::Result<OwnedHandle> {
+        let path: Vec<u16> = self.path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let access = match self.direction {
+            Direction::Duplex => PIPE_ACCESS_DUPLEX,
+            Direction::Inbound => PIPE_ACCESS_INBOUND,
+            Direction::Outbound => PIPE_ACCESS_OUTBOUND,
+        };
+        let mut open_mode = access | flags;
+        if self.write_through {
+            open_mode |= FILE_FLAG_WRITE_THROUGH;
+        }
+        if self.is_first_instance() {
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+        let pipe_mode = match self.pipe_mode {
+            PipeMode::Byte => PIPE_TYPE_BYTE | PIPE_READMODE_BYTE,
+            PipeMode::Message => PIPE_TYPE_MESSAGE | PIPE_READMODE_MESSAGE,
+        };
+        let remote_clients = if self.reject_remote_clients {
+            PIPE_REJECT_REMOTE_CLIENTS
+        } else {
+            PIPE_ACCEPT_REMOTE_CLIENTS
+        };
+        let handle = unsafe {
+            CreateNamedPipeW(
+                path.as_ptr(),
+                open_mode,
+                pipe_mode | PIPE_WAIT | remote_clients,
+                self.max_instances
+                    .map_or(PIPE_UNLIMITED_INSTANCES, u32::from),
+                self.out_buffer_size,
+                self.in_buffer_size,
+                0,
+                self.security_attributes.as_ptr(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(self.conflict_error(io::Error::last_os_error()));
+        }
+        if !self.created_listener {
+            debug!(path = ?self.path, "bound endpoint");
+        }
+        self.created_listener = true;
+        Ok(unsafe { OwnedHandle::from_raw_handle(handle as RawHandle) })
+    }
+
+    fn wait_for_blocking_listener(&mut self) -> io::Result<OwnedHandle> {
+        loop {
+            match self.create_blocking_listener() {
+                Err(e) if is_instance_limit(&e) => std::thread::sleep(INSTANCE_POLL_INTERVAL),
+                res => return res,
+            }
+        }
+    }
+
+    pub(crate) fn incoming_blocking(mut self) -> io::Result<BlockingIncoming> {
+        let pipe = self.create_blocking_listener()?;
+        Ok(BlockingIncoming {
+            endpoint: self,
+            next: Some(pipe),
+        })
+    }
+
+    pub(crate) fn connect_blocking(
+        path: impl IntoIpcPath,
+        options: &ConnectOptions,
+    ) -> io::Result<BlockingConnection> {
+        let path = path.into_ipc_path()?;
+        let wide_path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+
+        let attempt_start = Instant::now();
+        let pipe = loop {
+            let handle = unsafe {
+                CreateFileW(
+                    wide_path.as_ptr(),
+                    client_access(options),
+                    0,
+                    ptr::null(),
+                    OPEN_EXISTING,
+                    // Match tokio's client defaults so the server can't impersonate us
+                    SECURITY_SQOS_PRESENT | SECURITY_IDENTIFICATION | write_through_flag(options),
+                    0,
+                )
+            };
+            if handle != INVALID_HANDLE_VALUE {
+                break unsafe { OwnedHandle::from_raw_handle(handle as RawHandle) };
+            }
+            let e = io::Error::last_os_error();
+            let remaining = options.busy_timeout.saturating_sub(attempt_start.elapsed());
+            if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) && !remaining.is_zero() {
+                debug!(?remaining, "all pipe instances are busy, waiting");
+                if wait_named_pipe(&wide_path, remaining)? {
+                    continue;
+                }
+            }
+            return Err(e);
+        };
+
+        if options.pipe_mode == PipeMode::Message {
+            let mode = PIPE_READMODE_MESSAGE;
+            let result = unsafe {
+                SetNamedPipeHandleState(
+                    pipe.as_raw_handle() as HANDLE,
+                    &mode,
+                    ptr::null(),
+                    ptr::null(),
+                )
+            };
+            if result == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(File::from(pipe))
+    }
+
     pub(crate) fn set_security_attributes(&mut self, security_attributes: SecurityAttributes) {
         self.security_attributes = security_attributes;
     }
 
+    pub(crate) fn set_pipe_mode(&mut self, pipe_mode: PipeMode) {
+        self.pipe_mode = pipe_mode;
+    }
+
+    pub(crate) fn set_in_buffer_size(&mut self, size: u32) {
+        self.in_buffer_size = size;
+    }
+
+    pub(crate) fn set_out_buffer_size(&mut self, size: u32) {
+        self.out_buffer_size = size;
+    }
+
+    pub(crate) fn set_max_instances(&mut self, max_instances: u8) {
+        self.max_instances = Some(max_instances.clamp(1, 254));
+    }
+
+    pub(crate) fn set_join_existing(&mut self, join_existing: bool) {
+        self.join_existing = join_existing;
+    }
+
+    pub(crate) fn set_write_through(&mut self, write_through: bool) {
+        self.write_through = write_through;
+    }
+
+    pub(crate) fn set_reject_remote_clients(&mut self, reject_remote_clients: bool) {
+        self.reject_remote_clients = reject_remote_clients;
+    }
+
+    pub(crate) fn set_direction(&mut self, direction: Direction) {
+        self.direction = direction;
+    }
+
+    pub(crate) fn set_reuse_instances(&mut self, reuse_instances: bool) {
+        self.reuse_instances = reuse_instances;
+    }
+
+    pub(crate) fn set_standby_instances(&mut self, standby_instances: usize) {
+        self.standby_instances = standby_instances.max(1);
+    }
+
     pub(crate) fn path(&self) -> &Path {
         &self.path
     }
 
     pub(crate) fn new(path: impl IntoIpcPath, _on_conflict: OnConflict) -> io::Result<Self> {
+        let path = path.into_ipc_path()?;
+        validate_path(&path)?;
         Ok(Self {
-            path: path.into_ipc_path()?,
+            path,
             security_attributes: SecurityAttributes::empty(),
+            pipe_mode: PipeMode::Byte,
+            in_buffer_size: DEFAULT_BUFFER_SIZE,
+            out_buffer_size: DEFAULT_BUFFER_SIZE,
+            max_instances: None,
+            join_existing: false,
+            write_through: false,
+            reject_remote_clients: true,
+            direction: Direction::Duplex,
+            reuse_instances: false,
+            standby_instances: 1,
             created_listener: false,
         })
     }
@@ -126,22 +467,62 @@ pub(crate) struct IpcStream {
 
 impl IpcStream {
     pub(crate) fn new(mut endpoint: Endpoint) -> io::Result<Self> {
-        let pipe = endpoint.create_listener()?;
-
-        let stream =
-            futures::stream::try_unfold((pipe, endpoint), |(listener, mut endpoint)| async move {
-                listener.connect().await?;
-                let new_listener = endpoint.create_listener()?;
-                let conn = Connection::wrap(NamedPipe::Server(listener));
+        // Closed connections send their pipe instance back here to be reused. The sender is only
+        // handed out if reuse is enabled, so nothing is ever received otherwise.
+        let (recycle, mut recycled) = mpsc::unbounded_channel();
+        let mut pending = FuturesUnordered::new();
+        pending.push(wait_for_client(endpoint.create_listener()?));
+        endpoint.fill_standby(&mut pending, &mut recycled)?;
+
+        let stream = futures::stream::try_unfold(
+            (pending, endpoint, recycle, recycled),
+            |(mut pending, mut endpoint, recycle, mut recycled)| async move {
+                if pending.is_empty() {
+                    let listener = endpoint.wait_for_listener(&mut recycled).await?;
+                    pending.push(wait_for_client(listener));
+                }
+                // There's always at least one instance waiting at this point
+                let Some(listener) = pending.next().await else {
+                    return Ok(None);
+                };
+                let listener = listener?;
+                // Replace the instance that was just connected before handing out the
+                // connection, so new clients don't have to wait for the stream to be polled again
+                endpoint.fill_standby(&mut pending, &mut recycled)?;
+                let mut conn = Connection::wrap(NamedPipe::Server(listener));
+                if endpoint.reuse_instances {
+                    conn.recycle = Some(recycle.clone());
+                }
 
-                Ok(Some((conn, (new_listener, endpoint))))
-            });
+                Ok(Some((conn, (pending, endpoint, recycle, recycled))))
+            },
+        );
         Ok(Self {
             inner: Box::pin(stream),
         })
     }
 }
 
+impl IpcStream {
+    pub(crate) fn cleanup(&self) -> Cleanup {
+        Cleanup
+    }
+}
+
+/// Named pipes are removed by the OS once all handles are closed, so there's nothing to clean up.
+#[derive(Clone)]
+pub(crate) struct Cleanup;
+
+impl Cleanup {
+    pub(crate) fn take(&self) -> Self {
+        Self
+    }
+
+    pub(crate) fn run(&self) {}
+
+    pub(crate) fn remove(&self) {}
+}
+
 impl Stream for IpcStream {
     type Item = io::Result<Connection>;
 
@@ -151,17 +532,551 @@ impl Stream for IpcStream {
     }
 }
 
+// Catch paths that can never be used to create a pipe so the caller gets a useful error message
+// instead of whatever the OS reports when creating the first instance
+pub(crate) fn validate_path(path: &Path) -> io::Result<()> {
+    const PREFIX: &str = r"\\.\pipe\";
+    const MAX_LEN: usize = 256;
+
+    let invalid = |reason: String| {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid pipe path {path:?}: {reason}"),
+        ))
+    };
+    let wide: Vec<u16> = path.as_os_str().encode_wide().collect();
+    let prefix: Vec<u16> = PREFIX.encode_utf16().collect();
+    let has_prefix = wide.len() >= prefix.len()
+        && String::from_utf16_lossy(&wide[..prefix.len()]).eq_ignore_ascii_case(PREFIX);
+    if !has_prefix {
+        return invalid(format!(
+            "named pipe paths must start with {PREFIX}. Use ServerId to generate a valid path"
+        ));
+    }
+    if wide.len() == prefix.len() {
+        return invalid("the pipe name is empty".to_owned());
+    }
+    if wide.contains(&0) {
+        return invalid("the path contains a null character".to_owned());
+    }
+    if wide.len() > MAX_LEN {
+        return invalid(format!(
+            "the path is {} characters long, but pipe paths are limited to {MAX_LEN} characters",
+            wide.len()
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn probe(path: &Path) -> io::Result<EndpointStatus> {
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    // Unlike opening the pipe, waiting for it doesn't use up an instance
+    if unsafe { WaitNamedPipeW(path.as_ptr(), 1) } != 0 {
+        return Ok(EndpointStatus::Listening);
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error().map(|code| code as u32) {
+        Some(ERROR_FILE_NOT_FOUND) => Ok(EndpointStatus::NotFound),
+        // All instances are connected to other clients, but the server is still running
+        Some(ERROR_SEM_TIMEOUT) => Ok(EndpointStatus::Listening),
+        _ => Err(e),
+    }
+}
+
+// Blocks until an instance of the pipe is available to connect to. Returns `false` if the timeout
+// elapses first.
+fn wait_named_pipe(path: &[u16], timeout: Duration) -> io::Result<bool> {
+    // 0 means the server's default timeout and u32::MAX means forever, so keep it between those
+    let timeout = u32::try_from(timeout.as_millis())
+        .unwrap_or(u32::MAX)
+        .clamp(1, u32::MAX - 1);
+    if unsafe { WaitNamedPipeW(path.as_ptr(), timeout) } != 0 {
+        return Ok(true);
+    }
+    let e = io::Error::last_os_error();
+    if e.raw_os_error() == Some(ERROR_SEM_TIMEOUT as i32) {
+        Ok(false)
+    } else {
+        Err(e)
+    }
+}
+
+// Looks up the process that owns an existing pipe. This has to briefly connect to the pipe, so
+// the owner will see a client that disconnects immediately.
+fn pipe_owner_pid(path: &Path) -> Option<u32> {
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let handle = unsafe {
+        CreateFileW(
+            path.as_ptr(),
+            FILE_READ_ATTRIBUTES,
+            0,
+            ptr::null(),
+            OPEN_EXISTING,
+            SECURITY_SQOS_PRESENT | SECURITY_IDENTIFICATION,
+            0,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return None;
+    }
+    let handle = unsafe { OwnedHandle::from_raw_handle(handle as RawHandle) };
+    let mut pid = 0;
+    if unsafe { GetNamedPipeServerProcessId(handle.as_raw_handle() as HANDLE, &mut pid) } == 0 {
+        return None;
+    }
+    Some(pid)
+}
+
+// Creating a pipe instance fails with ERROR_PIPE_BUSY once the instance limit has been reached
+fn is_instance_limit(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32)
+}
+
+fn client_access(options: &ConnectOptions) -> u32 {
+    match options.direction {
+        Direction::Duplex => GENERIC_READ | GENERIC_WRITE,
+        Direction::Inbound => GENERIC_WRITE,
+        // Read-only handles need FILE_WRITE_ATTRIBUTES to switch to message mode
+        Direction::Outbound if options.pipe_mode == PipeMode::Message => {
+            GENERIC_READ | FILE_WRITE_ATTRIBUTES
+        }
+        Direction::Outbound => GENERIC_READ,
+    }
+}
+
+fn write_through_flag(options: &ConnectOptions) -> u32 {
+    if options.write_through {
+        FILE_FLAG_WRITE_THROUGH
+    } else {
+        0
+    }
+}
+
 pub(crate) struct Connection {
-    inner: NamedPipe,
+    // Only taken out when the connection is dropped or converted with `into_inner`
+    inner: ManuallyDrop<NamedPipe>,
+    // Where to send the pipe instance once the connection closes so the server can reuse it
+    recycle: Option<mpsc::UnboundedSender<named_pipe::NamedPipeServer>>,
+    // Data returned by `peek` that hasn't been read yet
+    peeked: Vec<u8>,
 }
 
 impl Connection {
     /// Wraps an existing named pipe
     fn wrap(pipe: NamedPipe) -> Self {
-        Self { inner: pipe }
+        Self {
+            inner: ManuallyDrop::new(pipe),
+            recycle: None,
+            peeked: Vec::new(),
+        }
+    }
+
+    pub(crate) async fn ready(&self, interest: Interest) -> io::Result<Ready> {
+        if interest.is_readable() && !self.peeked.is_empty() {
+            return Ok(Ready::READABLE);
+        }
+        match *self.inner {
+            NamedPipe::Client(ref c) => c.ready(interest).await,
+            NamedPipe::Server(ref s) => s.ready(interest).await,
+        }
+    }
+
+    pub(crate) async fn readable(&self) -> io::Result<()> {
+        if !self.peeked.is_empty() {
+            return Ok(());
+        }
+        match *self.inner {
+            NamedPipe::Client(ref c) => c.readable().await,
+            NamedPipe::Server(ref s) => s.readable().await,
+        }
+    }
+
+    pub(crate) async fn writable(&self) -> io::Result<()> {
+        match *self.inner {
+            NamedPipe::Client(ref c) => c.writable().await,
+            NamedPipe::Server(ref s) => s.writable().await,
+        }
+    }
+
+    pub(crate) fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.peeked.is_empty() {
+            let len = self.peeked.len().min(buf.len());
+            buf[..len].copy_from_slice(&self.peeked[..len]);
+            self.peeked.drain(..len);
+            return Ok(len);
+        }
+        match *self.inner {
+            NamedPipe::Client(ref c) => c.try_read(buf),
+            NamedPipe::Server(ref s) => s.try_read(buf),
+        }
+    }
+
+    pub(crate) fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        match *self.inner {
+            NamedPipe::Client(ref c) => c.try_write(buf),
+            NamedPipe::Server(ref s) => s.try_write(buf),
+        }
+    }
+
+    // Data buffered by `peek` has already been read from the pipe, so it's discarded here
+    pub(crate) fn into_inner(self) -> NamedPipe {
+        let mut this = ManuallyDrop::new(self);
+        // `this` is never dropped, so the other fields have to be dropped by hand. The caller
+        // owns the pipe from now on, so it can't be reused.
+        drop(mem::take(&mut this.peeked));
+        drop(this.recycle.take());
+        // Safety: `this` is never dropped, so the pipe is only taken out once
+        unsafe { ManuallyDrop::take(&mut this.inner) }
     }
 }
 
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Safety: the pipe isn't accessed again after being taken out
+        let pipe = unsafe { ManuallyDrop::take(&mut self.inner) };
+        let (NamedPipe::Server(server), Some(recycle)) = (pipe, self.recycle.take()) else {
+            return;
+        };
+        // Disconnecting puts the instance back into a state where it can accept another client.
+        // If anything goes wrong, the instance is closed as it would be without reuse.
+        match server.disconnect() {
+            Ok(()) => {
+                let _ = recycle.send(server);
+            }
+            Err(e) => debug!("failed to disconnect pipe instance: {e:?}"),
+        }
+    }
+}
+
+impl AsRawHandle for Connection {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.inner.as_raw_handle()
+    }
+}
+
+impl AsHandle for Connection {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        self.inner.as_handle()
+    }
+}
+
+// Named pipes don't support splitting natively, so we fall back to tokio's lock-based split.
+pub(crate) type OwnedReadHalf = ReadHalf<Connection>;
+pub(crate) type OwnedWriteHalf = WriteHalf<Connection>;
+
+pub(crate) fn into_split(conn: Connection) -> (OwnedReadHalf, OwnedWriteHalf) {
+    tokio::io::split(conn)
+}
+
+pub(crate) fn peer_credentials(conn: &Connection) -> io::Result<PeerCredentials> {
+    Ok(PeerCredentials {
+        uid: None,
+        gid: None,
+        pid: Some(peer_pid(conn)?),
+    })
+}
+
+pub(crate) fn impersonate(conn: &Connection) -> io::Result<()> {
+    let NamedPipe::Server(server) = &*conn.inner else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only server-side connections can impersonate the client",
+        ));
+    };
+    if unsafe { ImpersonateNamedPipeClient(server.as_raw_handle() as HANDLE) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub(crate) fn revert_to_self() {
+    // Continuing to run as the client would be a privilege escalation, so bail out entirely
+    if unsafe { RevertToSelf() } == 0 {
+        std::process::abort();
+    }
+}
+
+pub(crate) fn peer_addr(_conn: &Connection) -> Option<PathBuf> {
+    None
+}
+
+fn peer_pid(conn: &Connection) -> io::Result<u32> {
+    let handle = conn.inner.as_raw_handle() as HANDLE;
+    let mut pid = 0;
+    let result = unsafe {
+        match *conn.inner {
+            NamedPipe::Server(_) => GetNamedPipeClientProcessId(handle, &mut pid),
+            NamedPipe::Client(_) => GetNamedPipeServerProcessId(handle, &mut pid),
+        }
+    };
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(pid)
+}
+
+pub(crate) fn peer_session_id(conn: &Connection) -> io::Result<u32> {
+    let handle = conn.inner.as_raw_handle() as HANDLE;
+    let mut session_id = 0;
+    let result = unsafe {
+        match *conn.inner {
+            NamedPipe::Server(_) => GetNamedPipeClientSessionId(handle, &mut session_id),
+            NamedPipe::Client(_) => GetNamedPipeServerSessionId(handle, &mut session_id),
+        }
+    };
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(session_id)
+}
+
+pub(crate) fn pipe_info(conn: &Connection) -> io::Result<PipeInfo> {
+    let handle = conn.inner.as_raw_handle() as HANDLE;
+    let mut flags = 0;
+    let mut out_buffer_size = 0;
+    let mut in_buffer_size = 0;
+    let mut max_instances = 0;
+    let result = unsafe {
+        GetNamedPipeInfo(
+            handle,
+            &mut flags,
+            &mut out_buffer_size,
+            &mut in_buffer_size,
+            &mut max_instances,
+        )
+    };
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut state = 0;
+    let mut current_instances = 0;
+    // The collection settings and user name can't be queried for local pipes, so they're skipped
+    let result = unsafe {
+        GetNamedPipeHandleStateW(
+            handle,
+            &mut state,
+            &mut current_instances,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            0,
+        )
+    };
+    if result == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mode = |message| {
+        if message {
+            PipeMode::Message
+        } else {
+            PipeMode::Byte
+        }
+    };
+    Ok(PipeInfo {
+        is_server: flags & PIPE_SERVER_END != 0,
+        pipe_mode: mode(flags & PIPE_TYPE_MESSAGE != 0),
+        read_mode: mode(state & PIPE_READMODE_MESSAGE != 0),
+        out_buffer_size,
+        in_buffer_size,
+        max_instances: (max_instances != PIPE_UNLIMITED_INSTANCES).then_some(max_instances),
+        current_instances,
+    })
+}
+
+pub(crate) async fn peek(conn: &mut Connection, buf: &mut [u8]) -> io::Result<usize> {
+    // tokio reads ahead from the pipe into its own buffer, so `PeekNamedPipe` can miss data that
+    // already arrived. Instead, the data is read into a buffer that later reads are served from.
+    if conn.peeked.is_empty() && !buf.is_empty() {
+        let mut peeked = vec![0; buf.len()];
+        let len = conn.read(&mut peeked).await?;
+        peeked.truncate(len);
+        conn.peeked = peeked;
+    }
+    let len = buf.len().min(conn.peeked.len());
+    buf[..len].copy_from_slice(&conn.peeked[..len]);
+    Ok(len)
+}
+
+pub(crate) fn current_session_id() -> io::Result<u32> {
+    let mut session_id = 0;
+    if unsafe { ProcessIdToSessionId(std::process::id(), &mut session_id) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(session_id)
+}
+
+fn open_peer_process(conn: &Connection, access: PROCESS_ACCESS_RIGHTS) -> io::Result<OwnedHandle> {
+    let process = unsafe { OpenProcess(access, 0, peer_pid(conn)?) };
+    if process == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedHandle::from_raw_handle(process as RawHandle) })
+}
+
+/// Returns the string representation of the SID of the user running the peer process.
+pub(crate) fn peer_sid(conn: &Connection) -> io::Result<String> {
+    let process = open_peer_process(conn, PROCESS_QUERY_LIMITED_INFORMATION)?;
+    process_user_sid(process.as_raw_handle() as HANDLE)
+}
+
+pub(crate) fn current_user_id() -> io::Result<String> {
+    current_user_sid()
+}
+
+fn current_user_sid() -> io::Result<String> {
+    // The pseudo handle returned by GetCurrentProcess doesn't need to be closed
+    process_user_sid(unsafe { GetCurrentProcess() })
+}
+
+fn process_user_sid(process: HANDLE) -> io::Result<String> {
+    let mut token = 0;
+    if unsafe { OpenProcessToken(process, TOKEN_QUERY, &mut token) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let token = unsafe { OwnedHandle::from_raw_handle(token as RawHandle) };
+
+    let mut len = 0;
+    unsafe {
+        GetTokenInformation(
+            token.as_raw_handle() as HANDLE,
+            TokenUser,
+            ptr::null_mut(),
+            0,
+            &mut len,
+        )
+    };
+    // Use a u64 buffer so the TOKEN_USER struct is properly aligned
+    let mut buf = vec![0u64; (len as usize).div_ceil(mem::size_of::<u64>())];
+    if unsafe {
+        GetTokenInformation(
+            token.as_raw_handle() as HANDLE,
+            TokenUser,
+            buf.as_mut_ptr().cast(),
+            len,
+            &mut len,
+        )
+    } == 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    let user = unsafe { &*buf.as_ptr().cast::<TOKEN_USER>() };
+
+    let mut string_sid: PWSTR = ptr::null_mut();
+    if unsafe { ConvertSidToStringSidW(user.User.Sid, &mut string_sid) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let sid = unsafe {
+        let len = (0..).take_while(|&i| *string_sid.add(i) != 0).count();
+        let sid = String::from_utf16_lossy(std::slice::from_raw_parts(string_sid, len));
+        LocalFree(string_sid as HLOCAL);
+        sid
+    };
+    Ok(sid)
+}
+
+pub(crate) fn peer_process(conn: &Connection) -> io::Result<PeerProcess> {
+    let pid = peer_pid(conn)?;
+    // The pid is still useful for auditing when the process can't be opened, e.g. because it runs
+    // at a higher integrity level
+    let exe = peer_process_path(conn).ok();
+    Ok(PeerProcess::new(pid, exe))
+}
+
+/// Returns the executable file name of the peer process.
+pub(crate) fn peer_process_name(conn: &Connection) -> io::Result<String> {
+    peer_process_path(conn)?
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "process has no file name"))
+}
+
+/// Returns the full path to the executable of the peer process.
+fn peer_process_path(conn: &Connection) -> io::Result<PathBuf> {
+    let process = open_peer_process(conn, PROCESS_QUERY_LIMITED_INFORMATION)?;
+    let mut buf = vec![0u16; 1024];
+    let mut len = buf.len() as u32;
+    if unsafe {
+        QueryFullProcessImageNameW(
+            process.as_raw_handle() as HANDLE,
+            PROCESS_NAME_WIN32,
+            buf.as_mut_ptr(),
+            &mut len,
+        )
+    } == 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PathBuf::from(OsString::from_wide(&buf[..len as usize])))
+}
+
+// The sender writes its pid and the value of its own handle, and the receiver duplicates the
+// handle out of the sender's process. Since the receiver never takes ownership of a raw value it
+// was sent, a bad value makes `DuplicateHandle` fail instead of aliasing one of our handles.
+const HANDLE_MESSAGE_LEN: usize = mem::size_of::<u32>() + mem::size_of::<i64>();
+
+pub(crate) async fn send_handle(
+    conn: &mut Connection,
+    handle: BorrowedHandle<'_>,
+) -> io::Result<()> {
+    let mut message = [0; HANDLE_MESSAGE_LEN];
+    message[..4].copy_from_slice(&std::process::id().to_le_bytes());
+    message[4..].copy_from_slice(&(handle.as_raw_handle() as isize as i64).to_le_bytes());
+    conn.write_all(&message).await?;
+    // The handle has to stay open until the peer has duplicated it
+    let mut ack = [0; 1];
+    conn.read_exact(&mut ack).await?;
+    if ack[0] != 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "peer failed to duplicate the handle",
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) async fn recv_handle(conn: &mut Connection) -> io::Result<OwnedHandle> {
+    let mut message = [0; HANDLE_MESSAGE_LEN];
+    conn.read_exact(&mut message).await?;
+    let result = duplicate_peer_handle(conn, &message);
+    conn.write_all(&[u8::from(result.is_ok())]).await?;
+    result
+}
+
+fn duplicate_peer_handle(
+    conn: &Connection,
+    message: &[u8; HANDLE_MESSAGE_LEN],
+) -> io::Result<OwnedHandle> {
+    let (pid, handle) = message.split_at(4);
+    let pid = u32::from_le_bytes([pid[0], pid[1], pid[2], pid[3]]);
+    let mut value = [0; 8];
+    value.copy_from_slice(handle);
+    let handle = i64::from_le_bytes(value) as isize as HANDLE;
+    // Only trust the pid if it matches the process on the other end of the pipe
+    if pid != peer_pid(conn)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "handle was sent by a different process than the peer",
+        ));
+    }
+    let peer = open_peer_process(conn, PROCESS_DUP_HANDLE)?;
+    let mut duplicated = 0;
+    if unsafe {
+        DuplicateHandle(
+            peer.as_raw_handle() as HANDLE,
+            handle,
+            GetCurrentProcess(),
+            &mut duplicated,
+            0,
+            0,
+            DUPLICATE_SAME_ACCESS,
+        )
+    } == 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedHandle::from_raw_handle(duplicated as RawHandle) })
+}
+
 impl AsyncRead for Connection {
     fn poll_read(
         self: Pin<&mut Self>,
@@ -169,7 +1084,13 @@ impl AsyncRead for Connection {
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
         let this = Pin::into_inner(self);
-        match this.inner {
+        if !this.peeked.is_empty() {
+            let len = this.peeked.len().min(buf.remaining());
+            buf.put_slice(&this.peeked[..len]);
+            this.peeked.drain(..len);
+            return Poll::Ready(Ok(()));
+        }
+        match *this.inner {
             NamedPipe::Client(ref mut c) => Pin::new(c).poll_read(ctx, buf),
             NamedPipe::Server(ref mut s) => Pin::new(s).poll_read(ctx, buf),
         }
@@ -183,15 +1104,34 @@ impl AsyncWrite for Connection {
         buf: &[u8],
     ) -> Poll<Result<usize, io::Error>> {
         let this = Pin::into_inner(self);
-        match this.inner {
+        match *this.inner {
             NamedPipe::Client(ref mut c) => Pin::new(c).poll_write(ctx, buf),
             NamedPipe::Server(ref mut s) => Pin::new(s).poll_write(ctx, buf),
         }
     }
 
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        match *this.inner {
+            NamedPipe::Client(ref mut c) => Pin::new(c).poll_write_vectored(ctx, bufs),
+            NamedPipe::Server(ref mut s) => Pin::new(s).poll_write_vectored(ctx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match *self.inner {
+            NamedPipe::Client(ref c) => c.is_write_vectored(),
+            NamedPipe::Server(ref s) => s.is_write_vectored(),
+        }
+    }
+
     fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
         let this = Pin::into_inner(self);
-        match this.inner {
+        match *this.inner {
             NamedPipe::Client(ref mut c) => Pin::new(c).poll_flush(ctx),
             NamedPipe::Server(ref mut s) => Pin::new(s).poll_flush(ctx),
         }
@@ -199,7 +1139,7 @@ impl AsyncWrite for Connection {
 
     fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
         let this = Pin::into_inner(self);
-        match this.inner {
+        match *this.inner {
             NamedPipe::Client(ref mut c) => Pin::new(c).poll_shutdown(ctx),
             NamedPipe::Server(ref mut s) => Pin::new(s).poll_shutdown(ctx),
         }
@@ -208,9 +1148,17 @@ impl AsyncWrite for Connection {
 
 pub(crate) struct SecurityAttributes {
     attributes: Option<InnerAttributes>,
+    entries: Vec<AccessEntry>,
+}
+
+struct AccessEntry {
+    trustee: Trustee,
+    access_mode: ACCESS_MODE,
+    permissions: u32,
 }
 
 const DEFAULT_SECURITY_ATTRIBUTES: SecurityAttributes = SecurityAttributes {
+    entries: Vec::new(),
     attributes: Some(InnerAttributes {
         descriptor: SecurityDescriptor {
             descriptor_ptr: ptr::null_mut(),
@@ -239,10 +1187,11 @@ impl SecurityAttributes {
     }
 
     pub(crate) fn allow_everyone_connect(self) -> io::Result<Self> {
-        let attributes = Some(InnerAttributes::allow_everyone(
+        self.add_entry(
+            Trustee::Everyone,
+            SET_ACCESS,
             GENERIC_READ | FILE_WRITE_DATA,
-        )?);
-        Ok(Self { attributes })
+        )
     }
 
     pub(crate) fn set_mode(self, _mode: u16) -> io::Result<Self> {
@@ -251,17 +1200,103 @@ impl SecurityAttributes {
     }
 
     pub(crate) fn allow_everyone_create() -> io::Result<Self> {
-        let attributes = Some(InnerAttributes::allow_everyone(
-            GENERIC_READ | GENERIC_WRITE,
-        )?);
-        Ok(Self { attributes })
+        Self::empty().add_entry(Trustee::Everyone, SET_ACCESS, GENERIC_READ | GENERIC_WRITE)
+    }
+
+    pub(crate) fn from_sddl(sddl: &str) -> io::Result<Self> {
+        let attributes = Some(InnerAttributes::from_sddl(sddl)?);
+        Ok(Self {
+            attributes,
+            entries: Vec::new(),
+        })
+    }
+
+    pub(crate) unsafe fn from_security_descriptor(
+        descriptor: PSECURITY_DESCRIPTOR,
+    ) -> io::Result<Self> {
+        let sddl = descriptor_to_sddl(descriptor)?;
+        Self::from_sddl(&sddl)
+    }
+
+    pub(crate) fn allow(self, trustee: Trustee) -> io::Result<Self> {
+        self.add_entry(trustee, GRANT_ACCESS, GENERIC_READ | GENERIC_WRITE)
+    }
+
+    pub(crate) fn allow_user(self, account: &str, access: Access) -> io::Result<Self> {
+        let trustee = if account.starts_with("S-") {
+            Trustee::Sid(account.to_owned())
+        } else {
+            Trustee::Name(account.to_owned())
+        };
+        // Read-only clients need FILE_WRITE_ATTRIBUTES to switch to message mode
+        let permissions = match access {
+            Access::Read => GENERIC_READ | FILE_WRITE_ATTRIBUTES,
+            Access::Write => GENERIC_WRITE,
+            Access::ReadWrite => GENERIC_READ | GENERIC_WRITE,
+        };
+        self.add_entry(trustee, GRANT_ACCESS, permissions)
+    }
+
+    pub(crate) fn deny(self, trustee: Trustee) -> io::Result<Self> {
+        self.add_entry(trustee, DENY_ACCESS, GENERIC_ALL)
+    }
+
+    pub(crate) fn current_user_only() -> io::Result<Self> {
+        Self::empty().allow(Trustee::CurrentUser)
+    }
+
+    fn add_entry(
+        mut self,
+        trustee: Trustee,
+        access_mode: ACCESS_MODE,
+        permissions: u32,
+    ) -> io::Result<Self> {
+        self.entries.push(AccessEntry {
+            trustee,
+            access_mode,
+            permissions,
+        });
+        self.attributes = Some(InnerAttributes::from_entries(&self.entries)?);
+        Ok(self)
     }
 }
 
 unsafe impl Send for SecurityAttributes {}
 
+// Round-tripping through SDDL copies the descriptor into one that we own and works for both
+// absolute and self-relative descriptors
+unsafe fn descriptor_to_sddl(descriptor: PSECURITY_DESCRIPTOR) -> io::Result<String> {
+    if descriptor.is_null() || IsValidSecurityDescriptor(descriptor) == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid security descriptor",
+        ));
+    }
+    let mut sddl: PWSTR = ptr::null_mut();
+    if ConvertSecurityDescriptorToStringSecurityDescriptorW(
+        descriptor,
+        SDDL_REVISION_1,
+        OWNER_SECURITY_INFORMATION
+            | GROUP_SECURITY_INFORMATION
+            | DACL_SECURITY_INFORMATION
+            | SACL_SECURITY_INFORMATION
+            | LABEL_SECURITY_INFORMATION,
+        &mut sddl,
+        ptr::null_mut(),
+    ) == 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    let len = (0..).take_while(|&i| *sddl.add(i) != 0).count();
+    let string = String::from_utf16_lossy(std::slice::from_raw_parts(sddl, len));
+    LocalFree(sddl as HLOCAL);
+    Ok(string)
+}
+
 struct Sid {
     sid_ptr: PSID,
+    // SIDs parsed from strings are allocated with LocalAlloc instead of AllocateAndInitializeSid
+    local: bool,
 }
 
 impl Sid {
@@ -288,10 +1323,25 @@ impl Sid {
         if result == 0 {
             Err(io::Error::last_os_error())
         } else {
-            Ok(Self { sid_ptr })
+            Ok(Self {
+                sid_ptr,
+                local: false,
+            })
         }
     }
 
+    fn from_string(sid: &str) -> io::Result<Self> {
+        let sid: Vec<u16> = sid.encode_utf16().chain(Some(0)).collect();
+        let mut sid_ptr = ptr::null_mut();
+        if unsafe { ConvertStringSidToSidW(sid.as_ptr(), &mut sid_ptr) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            sid_ptr,
+            local: true,
+        })
+    }
+
     // Unsafe - the returned pointer is only valid for the lifetime of self.
     unsafe fn as_ptr(&self) -> PSID {
         self.sid_ptr
@@ -302,7 +1352,11 @@ impl Drop for Sid {
     fn drop(&mut self) {
         if !self.sid_ptr.is_null() {
             unsafe {
-                FreeSid(self.sid_ptr);
+                if self.local {
+                    LocalFree(self.sid_ptr as HLOCAL);
+                } else {
+                    FreeSid(self.sid_ptr);
+                }
             }
         }
     }
@@ -310,7 +1364,7 @@ impl Drop for Sid {
 
 struct AceWithSid<'a> {
     explicit_access: EXPLICIT_ACCESS_W,
-    _marker: marker::PhantomData<&'a Sid>,
+    _marker: marker::PhantomData<&'a ()>,
 }
 
 impl<'a> AceWithSid<'a> {
@@ -326,6 +1380,19 @@ impl<'a> AceWithSid<'a> {
         }
     }
 
+    // The name must be a nul-terminated wide string.
+    fn with_name(name: &'a [u16], trustee_type: TRUSTEE_TYPE) -> AceWithSid<'a> {
+        let mut explicit_access = unsafe { mem::zeroed::<EXPLICIT_ACCESS_W>() };
+        explicit_access.Trustee.TrusteeForm = TRUSTEE_IS_NAME;
+        explicit_access.Trustee.TrusteeType = trustee_type;
+        explicit_access.Trustee.ptstrName = name.as_ptr().cast_mut();
+
+        AceWithSid {
+            explicit_access,
+            _marker: marker::PhantomData,
+        }
+    }
+
     fn set_access_mode(&mut self, access_mode: ACCESS_MODE) -> &mut Self {
         self.explicit_access.grfAccessMode = access_mode;
         self
@@ -406,6 +1473,25 @@ impl SecurityDescriptor {
         Ok(Self { descriptor_ptr })
     }
 
+    fn from_sddl(sddl: &str) -> io::Result<Self> {
+        let sddl: Vec<u16> = sddl.encode_utf16().chain(Some(0)).collect();
+        let mut descriptor_ptr = ptr::null_mut();
+        // The descriptor is allocated with LocalAlloc, so it can be freed the same way as the
+        // ones we create ourselves
+        if unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1,
+                &mut descriptor_ptr,
+                ptr::null_mut(),
+            )
+        } == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { descriptor_ptr })
+    }
+
     fn set_dacl(&mut self, acl: &Acl) -> io::Result<()> {
         if unsafe {
             SetSecurityDescriptorDacl(self.descriptor_ptr, true as i32, acl.as_ptr(), false as i32)
@@ -430,6 +1516,31 @@ impl Drop for SecurityDescriptor {
     }
 }
 
+enum ResolvedTrustee {
+    Sid(Sid, TRUSTEE_TYPE),
+    Name(Vec<u16>),
+}
+
+impl ResolvedTrustee {
+    fn new(trustee: &Trustee) -> io::Result<Self> {
+        Ok(match trustee {
+            Trustee::Everyone => Self::Sid(Sid::everyone_sid()?, TRUSTEE_IS_WELL_KNOWN_GROUP),
+            Trustee::CurrentUser => {
+                Self::Sid(Sid::from_string(&current_user_sid()?)?, TRUSTEE_IS_USER)
+            }
+            Trustee::Sid(sid) => Self::Sid(Sid::from_string(sid)?, TRUSTEE_IS_UNKNOWN),
+            Trustee::Name(name) => Self::Name(name.encode_utf16().chain(Some(0)).collect()),
+        })
+    }
+
+    fn ace(&self) -> AceWithSid<'_> {
+        match self {
+            Self::Sid(sid, trustee_type) => AceWithSid::new(sid, *trustee_type),
+            Self::Name(name) => AceWithSid::with_name(name, TRUSTEE_IS_UNKNOWN),
+        }
+    }
+}
+
 struct InnerAttributes {
     descriptor: SecurityDescriptor,
     acl: Acl,
@@ -453,24 +1564,83 @@ impl InnerAttributes {
         })
     }
 
-    fn allow_everyone(permissions: u32) -> io::Result<Self> {
+    fn from_entries(entries: &[AccessEntry]) -> io::Result<Self> {
         let mut attributes = Self::empty()?;
-        let sid = Sid::everyone_sid()?;
-
-        let mut everyone_ace = AceWithSid::new(&sid, TRUSTEE_IS_WELL_KNOWN_GROUP);
-        everyone_ace
-            .set_access_mode(SET_ACCESS)
-            .set_access_permissions(permissions)
-            .allow_inheritance(false as u32);
-
-        let mut entries = vec![everyone_ace];
-        attributes.acl = Acl::new(&mut entries)?;
+        // Resolve all trustees up front since the ACEs borrow from them
+        let trustees = entries
+            .iter()
+            .map(|entry| ResolvedTrustee::new(&entry.trustee))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut aces: Vec<_> = entries
+            .iter()
+            .zip(&trustees)
+            .map(|(entry, trustee)| {
+                let mut ace = trustee.ace();
+                ace.set_access_mode(entry.access_mode)
+                    .set_access_permissions(entry.permissions)
+                    .allow_inheritance(false as u32);
+                ace
+            })
+            .collect();
+        attributes.acl = Acl::new(&mut aces)?;
         attributes.descriptor.set_dacl(&attributes.acl)?;
 
         Ok(attributes)
     }
 
+    fn from_sddl(sddl: &str) -> io::Result<Self> {
+        let descriptor = SecurityDescriptor::from_sddl(sddl)?;
+        let mut attrs = unsafe { mem::zeroed::<SECURITY_ATTRIBUTES>() };
+        attrs.nLength = mem::size_of::<SECURITY_ATTRIBUTES>() as u32;
+        attrs.lpSecurityDescriptor = unsafe { descriptor.as_ptr() };
+        attrs.bInheritHandle = false as i32;
+
+        // The DACL is part of the self-relative descriptor, so there's no separate ACL to free
+        Ok(Self {
+            acl: Acl {
+                acl_ptr: ptr::null(),
+            },
+            descriptor,
+            attrs,
+        })
+    }
+
     unsafe fn as_ptr(&mut self) -> *const SECURITY_ATTRIBUTES {
         &mut self.attrs
     }
 }
+
+pub(crate) type BlockingConnection = File;
+
+pub(crate) struct BlockingIncoming {
+    endpoint: Endpoint,
+    next: Option<OwnedHandle>,
+}
+
+impl Iterator for BlockingIncoming {
+    type Item = io::Result<BlockingConnection>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pipe = match self.next.take() {
+            Some(pipe) => pipe,
+            None => match self.endpoint.wait_for_blocking_listener() {
+                Ok(pipe) => pipe,
+                Err(e) => return Some(Err(e)),
+            },
+        };
+        let connected =
+            unsafe { ConnectNamedPipe(pipe.as_raw_handle() as HANDLE, ptr::null_mut()) };
+        // ERROR_PIPE_CONNECTED means the client connected before we started waiting
+        if connected == 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+                return Some(Err(e));
+            }
+        }
+        // Create the next instance right away so new clients don't see the pipe as busy. Errors
+        // are reported on the next call.
+        self.next = self.endpoint.create_blocking_listener().ok();
+        Some(Ok(File::from(pipe)))
+    }
+}
@@ -1,10 +1,11 @@
-use std::io;
+use std::io::{self, IoSlice};
 use std::time::Duration;
 
 use futures::channel::oneshot;
 use futures::{Future, StreamExt};
 use tipsy::{
-    Connection, Endpoint, IntoIpcPath, IpcStream, OnConflict, SecurityAttributes, ServerId,
+    Access, Backoff, ConnectOptions, Connection, Endpoint, IntoIpcPath, IpcStream, OnConflict,
+    SecurityAttributes, ServerId, TokenAuth,
 };
 use tokio::io::{split, AsyncReadExt, AsyncWriteExt};
 
@@ -41,10 +42,11 @@ async fn run_stream(incoming: IpcStream) {
     }
 }
 
-async fn run_clients<F, Fut>(create_client: F)
+async fn run_clients<F, Fut, E>(create_client: F)
 where
     F: Fn() -> Fut,
-    Fut: Future<Output = Result<Connection, io::Error>>,
+    Fut: Future<Output = Result<Connection, E>>,
+    E: std::fmt::Debug,
 {
     tokio::time::sleep(Duration::from_secs(2)).await;
 
@@ -85,6 +87,34 @@ async fn single_id() {
     smoke_test(endpoint).await;
 }
 
+#[tokio::test]
+async fn connect_with_options() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = run_server(endpoint) => {}
+            _ = shutdown_rx => {}
+        }
+    });
+
+    run_clients(|| {
+        Endpoint::connect_with_options(
+            path.clone(),
+            ConnectOptions::new()
+                .busy_timeout(Duration::from_secs(1))
+                .poll_interval(Duration::from_millis(10))
+                .backoff(Backoff::Exponential {
+                    max: Duration::from_millis(100),
+                }),
+        )
+    })
+    .await;
+    let _ = shutdown_tx.send(());
+}
+
 #[tokio::test]
 async fn nested_path() {
     let endpoint = Endpoint::new(dummy_endpoint("test/test1"), OnConflict::Overwrite).unwrap();
@@ -179,7 +209,8 @@ fn create_endpoint_with_permissions(attr: SecurityAttributes) -> ::std::io::Resu
 
     let mut endpoint = Endpoint::new(path, OnConflict::Overwrite).unwrap();
     endpoint.set_security_attributes(attr);
-    endpoint.incoming().map(|_| ())
+    endpoint.incoming()?;
+    Ok(())
 }
 
 #[tokio::test]
@@ -195,3 +226,2929 @@ async fn test_endpoint_permissions() {
     )
     .expect("failed with attributes for connecting");
 }
+
+#[cfg(any(
+    windows,
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+))]
+#[tokio::test]
+async fn peer_credentials() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    let pid = std::process::id();
+    assert_eq!(client.peer_credentials().unwrap().pid(), Some(pid));
+    assert_eq!(server.peer_credentials().unwrap().pid(), Some(pid));
+}
+
+#[cfg(any(
+    windows,
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+))]
+#[tokio::test]
+async fn peer_process() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    let process = server.peer_process().unwrap();
+    assert_eq!(process.pid(), std::process::id());
+    let exe = std::env::current_exe().unwrap();
+    assert_eq!(process.exe(), Some(exe.as_path()));
+    assert_eq!(
+        process.name().as_deref(),
+        exe.file_name().and_then(|name| name.to_str())
+    );
+    assert_eq!(client.peer_process().unwrap().pid(), std::process::id());
+}
+
+#[tokio::test]
+async fn owned_split() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    let (mut client_reader, mut client_writer) = client.into_split();
+    let (mut server_reader, mut server_writer) = server.into_split();
+
+    let echo = tokio::spawn(async move {
+        let mut buf = [0u8; 5];
+        server_reader.read_exact(&mut buf).await.unwrap();
+        server_writer.write_all(&buf).await.unwrap();
+    });
+    let write = tokio::spawn(async move {
+        client_writer.write_all(b"hello").await.unwrap();
+    });
+
+    let mut buf = [0u8; 5];
+    client_reader.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+    write.await.unwrap();
+    echo.await.unwrap();
+}
+
+#[tokio::test]
+async fn write_vectored() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    let bufs = [IoSlice::new(b"hel"), IoSlice::new(b"lo")];
+    let written = client.write_vectored(&bufs).await.unwrap();
+
+    let mut buf = vec![0u8; written];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, &b"hello"[..written]);
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn message_mode() {
+    use tipsy::PipeMode;
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_pipe_mode(PipeMode::Message);
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client =
+        Endpoint::connect_with_options(path, ConnectOptions::new().pipe_mode(PipeMode::Message))
+            .await
+            .unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    client.write_all(b"ab").await.unwrap();
+    client.write_all(b"cde").await.unwrap();
+
+    let mut buf = [0u8; 16];
+    let n = server.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"ab");
+    let n = server.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"cde");
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn sddl_security_attributes() {
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_security_attributes(SecurityAttributes::from_sddl("D:(A;;GA;;;WD)").unwrap());
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    assert!(SecurityAttributes::from_sddl("not sddl").is_err());
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn security_descriptor_attributes() {
+    use windows_sys::Win32::Foundation::{LocalFree, HLOCAL};
+    use windows_sys::Win32::Security::Authorization::{
+        ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+    };
+
+    let sddl: Vec<u16> = "D:(A;;GA;;;WD)".encode_utf16().chain(Some(0)).collect();
+    let mut descriptor = std::ptr::null_mut();
+    let converted = unsafe {
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            sddl.as_ptr(),
+            SDDL_REVISION_1,
+            &mut descriptor,
+            std::ptr::null_mut(),
+        )
+    };
+    assert_ne!(converted, 0);
+    let attributes = unsafe { SecurityAttributes::from_security_descriptor(descriptor) }.unwrap();
+    // The descriptor was copied, so it can be freed right away
+    unsafe { LocalFree(descriptor as HLOCAL) };
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_security_attributes(attributes);
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    assert!(unsafe { SecurityAttributes::from_security_descriptor(std::ptr::null_mut()) }.is_err());
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn access_control_entries() {
+    use tipsy::Trustee;
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_security_attributes(
+        SecurityAttributes::empty()
+            .allow(Trustee::Everyone)
+            .unwrap()
+            // Anonymous logon
+            .deny(Trustee::Sid("S-1-5-7".to_owned()))
+            .unwrap(),
+    );
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    assert!(SecurityAttributes::empty()
+        .allow(Trustee::Sid("not a sid".to_owned()))
+        .is_err());
+}
+
+#[cfg(feature = "tokio-util")]
+#[tokio::test]
+async fn message_connection() {
+    use futures::SinkExt;
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    tokio::spawn(async move {
+        let (mut sink, mut stream) = server.into_messages().split();
+        sink.send_all(&mut stream).await.unwrap();
+    });
+
+    let messages = vec![
+        bytes::Bytes::from_static(b"hello"),
+        bytes::Bytes::new(),
+        bytes::Bytes::from(vec![7u8; 100_000]),
+    ];
+    let mut client = client.into_messages();
+    for message in &messages {
+        client.send(message.clone()).await.unwrap();
+    }
+    for message in messages {
+        assert_eq!(client.next().await.unwrap().unwrap(), message);
+    }
+}
+
+#[cfg(feature = "channel")]
+#[tokio::test]
+async fn typed_channel() {
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    enum Request {
+        Add(u32, u32),
+    }
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    tokio::spawn(async move {
+        let (mut tx, mut rx) = tipsy::channel::<u32, Request>(server);
+        while let Some(Ok(Request::Add(a, b))) = rx.recv().await {
+            tx.send(&(a + b)).await.unwrap();
+        }
+    });
+
+    let (mut tx, mut rx) = tipsy::channel::<Request, u32>(client);
+    tx.send(&Request::Add(1, 2)).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().unwrap(), 3);
+    tx.send(&Request::Add(3, 4)).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap().unwrap(), 7);
+}
+
+#[cfg(feature = "tower")]
+#[tokio::test]
+async fn tower_connector() {
+    use tower_service::Service;
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let mut connector = tipsy::IpcConnector::new(endpoint.path().to_path_buf()).unwrap();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    futures::future::poll_fn(|cx| Service::<()>::poll_ready(&mut connector, cx))
+        .await
+        .unwrap();
+    let mut client = connector.call(()).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[cfg(feature = "tonic")]
+#[tokio::test]
+async fn tonic_channel() {
+    use tonic::service::Routes;
+    use tonic::transport::Server;
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let connector = tipsy::IpcConnector::new(endpoint.path().to_path_buf()).unwrap();
+    let incoming = endpoint.incoming().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_routes(Routes::default())
+            .serve_with_incoming(incoming),
+    );
+
+    connector.connect_tonic().await.unwrap();
+}
+
+#[cfg(feature = "hyper")]
+#[tokio::test]
+async fn hyper_server() {
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response};
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming_hyper().unwrap();
+    tokio::spawn(async move {
+        while let Some(Ok(io)) = incoming.next().await {
+            tokio::spawn(async move {
+                let service = service_fn(|req: Request<hyper::body::Incoming>| async move {
+                    Ok::<_, io::Error>(Response::new(format!("hello from {}", req.uri().path())))
+                });
+                http1::Builder::new()
+                    .serve_connection(io, service)
+                    .await
+                    .unwrap();
+            });
+        }
+    });
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let (mut sender, conn) =
+        hyper::client::conn::http1::handshake::<_, String>(hyper_util::rt::TokioIo::new(client))
+            .await
+            .unwrap();
+    tokio::spawn(conn);
+    let res = sender
+        .send_request(
+            Request::get("/test")
+                .header("host", "localhost")
+                .body(String::new())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+    let body = http_body_util::BodyExt::collect(res.into_body())
+        .await
+        .unwrap()
+        .to_bytes();
+    assert_eq!(&body[..], b"hello from /test");
+}
+
+#[cfg(feature = "axum")]
+#[tokio::test]
+async fn axum_server() {
+    use axum::routing::get;
+    use axum::Router;
+    use hyper::Request;
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let router = Router::new().route("/hello", get(|| async { "hello" }));
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let serve = tipsy::serve_axum(endpoint, router).with_graceful_shutdown(async move {
+        shutdown_rx.await.ok();
+    });
+    let server = tokio::spawn(async move { serve.await });
+
+    // The endpoint isn't bound until the server starts running
+    let options = ConnectOptions::new().retry_attempts(10);
+    let client = Endpoint::connect_with_options(path.clone(), options)
+        .await
+        .unwrap();
+    let (mut sender, conn) =
+        hyper::client::conn::http1::handshake::<_, String>(hyper_util::rt::TokioIo::new(client))
+            .await
+            .unwrap();
+    let conn = tokio::spawn(conn);
+    let res = sender
+        .send_request(
+            Request::get("/hello")
+                .header("host", "localhost")
+                .body(String::new())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = http_body_util::BodyExt::collect(res.into_body())
+        .await
+        .unwrap()
+        .to_bytes();
+    assert_eq!(&body[..], b"hello");
+
+    shutdown_tx.send(()).unwrap();
+    // The server closes the idle connection and stops once it's gone
+    conn.await.unwrap().unwrap();
+    server.await.unwrap().unwrap();
+    assert!(!Endpoint::is_listening(path));
+}
+
+#[cfg(feature = "jsonrpc")]
+#[tokio::test]
+async fn json_rpc_messages() {
+    use futures::SinkExt;
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path.clone()).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap().into_json_rpc();
+
+    // Several messages in one write, with one split across writes and brackets inside a string
+    client
+        .write_all(br#"{"id":1} [{"id":2},{"id":3}] {"id":4,"params":["}\"{"#)
+        .await
+        .unwrap();
+    client.flush().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    client.write_all(br#"]"]}"#).await.unwrap();
+
+    assert_eq!(server.next().await.unwrap().unwrap(), r#"{"id":1}"#);
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        r#"[{"id":2},{"id":3}]"#
+    );
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        r#"{"id":4,"params":["}\"{]"]}"#
+    );
+
+    let mut client = client.into_json_rpc();
+    server
+        .send(r#"{"jsonrpc":"2.0","id":1,"result":true}"#.to_owned())
+        .await
+        .unwrap();
+    assert_eq!(
+        client.next().await.unwrap().unwrap(),
+        r#"{"jsonrpc":"2.0","id":1,"result":true}"#
+    );
+
+    // Brackets that are balanced but don't match aren't treated as a complete message
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap().into_json_rpc();
+    client.write_all(br#"{"a":[1}]"#).await.unwrap();
+    let err = server.next().await.unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[cfg(feature = "rpc")]
+#[tokio::test]
+async fn rpc_calls() {
+    use bytes::Bytes;
+    use tipsy::{serve_rpc, RpcClient};
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = RpcClient::new(Endpoint::connect(path).await.unwrap());
+    let server = incoming.next().await.unwrap().unwrap();
+    tokio::spawn(serve_rpc(server, |request: Bytes| async move {
+        match &request[..] {
+            b"slow" => {
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                Ok(Bytes::from_static(b"done"))
+            }
+            b"fail" => Err("bad request".to_owned()),
+            _ => Ok(Bytes::from([b"echo: ", &request[..]].concat())),
+        }
+    }));
+
+    // Responses are matched to their requests even when they arrive out of order
+    let slow_client = client.clone();
+    let slow = tokio::spawn(async move { slow_client.call("slow").await });
+    assert_eq!(&client.call("hello").await.unwrap()[..], b"echo: hello");
+    assert_eq!(&slow.await.unwrap().unwrap()[..], b"done");
+
+    let err = client.call("fail").await.unwrap_err();
+    assert_eq!(err.to_string(), "bad request");
+
+    let err = client
+        .call_with_timeout("slow", Duration::from_millis(50))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert_eq!(&client.call("again").await.unwrap()[..], b"echo: again");
+}
+
+#[cfg(feature = "rpc")]
+#[tokio::test]
+async fn rpc_unresponsive_server() {
+    use bytes::Bytes;
+    use tipsy::RpcClient;
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    // The timeout applies while the request is stuck because the server isn't reading
+    let client = RpcClient::new(Endpoint::connect(path.clone()).await.unwrap());
+    let server = incoming.next().await.unwrap().unwrap();
+    let err = client
+        .call_with_timeout(
+            Bytes::from(vec![0; 4 * 1024 * 1024]),
+            Duration::from_millis(200),
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    // Calls fail right away once the connection is closed
+    drop(server);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let err = tokio::time::timeout(Duration::from_secs(1), client.call("hello"))
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[cfg(feature = "pubsub")]
+#[tokio::test]
+async fn publish_subscribe() {
+    use bytes::Bytes;
+    use tipsy::{LagPolicy, PubSubEvent, Publisher, Subscriber};
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let publisher = Publisher::new(4).lag_policy(LagPolicy::Skip);
+
+    let mut status = Subscriber::new(Endpoint::connect(path.clone()).await.unwrap());
+    publisher.add_subscriber(incoming.next().await.unwrap().unwrap());
+    let mut slow = Subscriber::new(Endpoint::connect(path).await.unwrap());
+    publisher.add_subscriber(incoming.next().await.unwrap().unwrap());
+
+    status.subscribe("status").await.unwrap();
+    slow.subscribe("status").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    publisher.publish("other", "ignored");
+    publisher.publish("status", "ready");
+    assert_eq!(
+        status.next().await.unwrap().unwrap(),
+        PubSubEvent::Message {
+            topic: "status".to_owned(),
+            payload: Bytes::from_static(b"ready"),
+        }
+    );
+
+    status.unsubscribe("status").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // The slow subscriber's task can't keep up with a burst, so it skips ahead
+    for i in 0..10 {
+        publisher.publish("status", format!("{i}"));
+    }
+    let mut lagged = false;
+    while let Some(event) = slow.next().await {
+        match event.unwrap() {
+            PubSubEvent::Lagged(_) => lagged = true,
+            PubSubEvent::Message { payload, .. } if &payload[..] == b"9" => break,
+            PubSubEvent::Message { .. } => {}
+        }
+    }
+    assert!(lagged);
+
+    // The unsubscribed client may be told it lagged, but doesn't get any of the messages
+    let next_message = async {
+        while let Some(event) = status.next().await {
+            if let PubSubEvent::Message { .. } = event.unwrap() {
+                return;
+            }
+        }
+    };
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), next_message)
+            .await
+            .is_err()
+    );
+}
+
+#[cfg(feature = "mux")]
+#[tokio::test]
+async fn multiplexed_streams() {
+    use tipsy::{Multiplexer, MuxMode};
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    tokio::spawn(async move {
+        let mut server = Multiplexer::new(server, MuxMode::Server);
+        while let Some(Ok(stream)) = server.next().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = split(stream);
+                tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+            });
+        }
+    });
+
+    let mut client = Multiplexer::new(client, MuxMode::Client);
+    let control = client.control();
+    tokio::spawn(async move { while client.next().await.is_some() {} });
+
+    let mut first = control.open_stream().await.unwrap();
+    let mut second = control.open_stream().await.unwrap();
+    first.write_all(b"first").await.unwrap();
+    second.write_all(b"second").await.unwrap();
+
+    let mut buf = [0u8; 6];
+    second.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"second");
+    let mut buf = [0u8; 5];
+    first.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"first");
+}
+
+#[tokio::test]
+async fn shutdown_handle() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let shutdown = incoming.shutdown_handle();
+
+    let accept = tokio::spawn(async move { incoming.next().await.is_none() });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    shutdown.shutdown();
+
+    #[cfg(unix)]
+    assert!(!path.exists());
+    assert!(accept.await.unwrap());
+    assert!(Endpoint::connect_with_options(
+        path,
+        ConnectOptions::new().busy_timeout(Duration::ZERO)
+    )
+    .await
+    .is_err());
+}
+
+#[cfg(feature = "tokio-util")]
+#[tokio::test]
+async fn cancellation_token() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let token = tokio_util::sync::CancellationToken::new();
+    let mut incoming = endpoint.incoming_with_cancellation(token.clone()).unwrap();
+
+    let accept = tokio::spawn(async move { incoming.next().await.is_none() });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    token.cancel();
+
+    assert!(accept.await.unwrap());
+    #[cfg(unix)]
+    assert!(!path.exists());
+    assert!(Endpoint::try_connect(path).await.is_err());
+}
+
+#[cfg(feature = "tls")]
+#[tokio::test]
+async fn secure_connection() {
+    use std::sync::Arc;
+
+    use tipsy::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+    use tipsy::rustls::{ClientConfig, RootCertStore, ServerConfig};
+    use tipsy::SecureConnection;
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let cert_der = cert.cert.der().clone();
+    let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert_der.clone()], key_der)
+        .unwrap();
+    let mut roots = RootCertStore::empty();
+    roots.add(cert_der).unwrap();
+    let client_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    tokio::spawn(async move {
+        let conn = incoming.next().await.unwrap().unwrap();
+        let mut conn = SecureConnection::accept(conn, Arc::new(server_config))
+            .await
+            .unwrap();
+        let mut buf = [0u8; 5];
+        conn.read_exact(&mut buf).await.unwrap();
+        conn.write_all(&buf).await.unwrap();
+        conn.flush().await.unwrap();
+    });
+
+    let conn = Endpoint::connect(path).await.unwrap();
+    let mut conn = SecureConnection::connect(
+        conn,
+        Arc::new(client_config),
+        ServerName::try_from("localhost").unwrap(),
+    )
+    .await
+    .unwrap();
+    conn.write_all(b"hello").await.unwrap();
+    conn.flush().await.unwrap();
+    let mut buf = [0u8; 5];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn token_auth() {
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_token_auth(TokenAuth::new("secret"));
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    tokio::spawn(async move {
+        while let Some(Ok(mut conn)) = incoming.next().await {
+            conn.write_all(b"hello").await.unwrap();
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let res = Endpoint::connect_with_options(
+        path.clone(),
+        ConnectOptions::new().token_auth(TokenAuth::new("wrong")),
+    )
+    .await;
+    assert!(matches!(res, Err(e) if e.kind() == io::ErrorKind::PermissionDenied));
+
+    let mut conn = Endpoint::connect_with_options(
+        path,
+        ConnectOptions::new().token_auth(TokenAuth::new("secret")),
+    )
+    .await
+    .unwrap();
+    let mut buf = [0; 5];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[tokio::test]
+async fn peer_filter() {
+    use std::os::unix::fs::MetadataExt;
+
+    use tipsy::PeerFilter;
+
+    async fn accepts(filter: PeerFilter) -> bool {
+        let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+        endpoint.set_peer_filter(filter);
+        let path = endpoint.path().to_path_buf();
+        let mut incoming = endpoint.incoming().unwrap();
+        tokio::spawn(async move {
+            while let Some(Ok(mut conn)) = incoming.next().await {
+                conn.write_all(b"hello").await.unwrap();
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut conn = Endpoint::connect(path).await.unwrap();
+        let mut buf = Vec::new();
+        conn.read_to_end(&mut buf).await.is_ok_and(|n| n > 0)
+    }
+
+    // Files we create are owned by the current user
+    let file = std::env::temp_dir().join(dummy_endpoint("uid").0);
+    std::fs::write(&file, b"").unwrap();
+    let uid = std::fs::metadata(&file).unwrap().uid();
+    std::fs::remove_file(&file).unwrap();
+
+    assert!(accepts(PeerFilter::new().allow_uid(uid)).await);
+    assert!(!accepts(PeerFilter::new().allow_uid(uid + 1)).await);
+    assert!(!accepts(PeerFilter::new()).await);
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn session_filter() {
+    use tipsy::PeerFilter;
+
+    let path = dummy_endpoint("test");
+    let mut endpoint = Endpoint::new(path.clone(), OnConflict::Overwrite).unwrap();
+    endpoint.set_peer_filter(PeerFilter::new().allow_current_session().unwrap());
+    let mut incoming = endpoint.incoming().unwrap();
+    let client = Endpoint::connect(path.clone()).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let session_id = server.peer_session_id().unwrap();
+    assert_eq!(client.peer_session_id().unwrap(), session_id);
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_peer_filter(PeerFilter::new().allow_session(session_id + 1));
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    tokio::spawn(async move {
+        while let Some(Ok(mut conn)) = incoming.next().await {
+            conn.write_all(b"hello").await.unwrap();
+        }
+    });
+    let mut conn = Endpoint::connect(path).await.unwrap();
+    let mut buf = Vec::new();
+    assert!(!conn.read_to_end(&mut buf).await.is_ok_and(|n| n > 0));
+}
+
+#[tokio::test]
+async fn handle_passing() {
+    use std::fs::File;
+    use std::io::{Read, Seek, Write};
+    #[cfg(unix)]
+    use std::os::fd::AsFd;
+    #[cfg(windows)]
+    use std::os::windows::io::AsHandle;
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let server = tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().unwrap();
+        let mut file = File::from(conn.recv_handle().await.unwrap());
+        file.rewind().unwrap();
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).unwrap();
+        contents
+    });
+
+    let file_path = std::env::temp_dir().join(dummy_endpoint("handle").0);
+    let mut file = File::options()
+        .create(true)
+        .truncate(true)
+        .read(true)
+        .write(true)
+        .open(&file_path)
+        .unwrap();
+    file.write_all(b"shared file").unwrap();
+
+    let mut conn = Endpoint::connect(path).await.unwrap();
+    #[cfg(unix)]
+    conn.send_handle(file.as_fd()).await.unwrap();
+    #[cfg(windows)]
+    conn.send_handle(file.as_handle()).await.unwrap();
+
+    assert_eq!(server.await.unwrap(), "shared file");
+    drop(file);
+    std::fs::remove_file(file_path).unwrap();
+}
+
+#[tokio::test]
+async fn current_user_only() {
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_security_attributes(SecurityAttributes::current_user_only().unwrap());
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn socket_ownership() {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    // A file created next to the socket gets the same owner and group as the socket, which we're
+    // always allowed to change ownership to
+    let file = endpoint.path().with_extension("owner");
+    std::fs::write(&file, b"").unwrap();
+    let metadata = std::fs::metadata(&file).unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    endpoint.set_security_attributes(
+        SecurityAttributes::empty()
+            .set_owner(metadata.uid())
+            .unwrap()
+            .set_group(metadata.gid())
+            .unwrap(),
+    );
+    let path = endpoint.path().to_path_buf();
+    let _incoming = endpoint.incoming().unwrap();
+    let socket = std::fs::metadata(path).unwrap();
+    assert_eq!(socket.uid(), metadata.uid());
+    assert_eq!(socket.gid(), metadata.gid());
+
+    assert!(SecurityAttributes::empty()
+        .set_group_name("tipsy-nonexistent-group")
+        .is_err());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn overwrite_stale_socket() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    // Dropping the listener leaves the socket file behind without anything listening on it
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let endpoint = Endpoint::new(path.clone(), OnConflict::OverwriteIfStale).unwrap();
+    let _incoming = endpoint.incoming().unwrap();
+
+    let endpoint = Endpoint::new(path.clone(), OnConflict::OverwriteIfStale).unwrap();
+    let err = endpoint.incoming().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    assert!(Endpoint::connect(path).await.is_ok());
+}
+
+#[tokio::test]
+async fn incoming_with_info() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming_with_info().unwrap();
+
+    let before = std::time::SystemTime::now();
+    let _client = Endpoint::connect(path).await.unwrap();
+    let (_conn, info) = incoming.next().await.unwrap().unwrap();
+    assert!(info.accepted_at() >= before);
+    assert!(info.peer_addr().is_none());
+    #[cfg(any(windows, target_os = "linux", target_os = "android"))]
+    assert_eq!(info.pid(), Some(std::process::id()));
+}
+
+#[tokio::test]
+async fn connection_id() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let first = Endpoint::connect(path.clone()).await.unwrap();
+    let second = Endpoint::connect(path).await.unwrap();
+    let first_server = incoming.next().await.unwrap().unwrap();
+    let second_server = incoming.next().await.unwrap().unwrap();
+    assert!(first.id() < second.id());
+    assert!(first_server.id() < second_server.id());
+    assert_ne!(first.id(), first_server.id());
+}
+
+#[tokio::test]
+async fn metrics() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use tipsy::IpcMetrics;
+
+    #[derive(Default)]
+    struct Counters {
+        accepted: AtomicUsize,
+        established: AtomicUsize,
+        read: AtomicUsize,
+        written: AtomicUsize,
+        active: AtomicUsize,
+    }
+
+    impl IpcMetrics for Counters {
+        fn connection_accepted(&self, _id: u64) {
+            self.accepted.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn connection_established(&self, _id: u64) {
+            self.established.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn bytes_read(&self, _id: u64, bytes: usize) {
+            self.read.fetch_add(bytes, Ordering::SeqCst);
+        }
+
+        fn bytes_written(&self, _id: u64, bytes: usize) {
+            self.written.fetch_add(bytes, Ordering::SeqCst);
+        }
+
+        fn active_connections(&self, count: usize) {
+            self.active.store(count, Ordering::SeqCst);
+        }
+    }
+
+    let server_metrics = Arc::new(Counters::default());
+    let client_metrics = Arc::new(Counters::default());
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_metrics(server_metrics.clone());
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client =
+        Endpoint::connect_with_options(path, ConnectOptions::new().metrics(client_metrics.clone()))
+            .await
+            .unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    assert_eq!(server_metrics.accepted.load(Ordering::SeqCst), 1);
+    assert_eq!(server_metrics.active.load(Ordering::SeqCst), 1);
+    assert_eq!(client_metrics.established.load(Ordering::SeqCst), 1);
+
+    let (mut read, write) = server.into_split();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    read.read_exact(&mut buf).await.unwrap();
+    assert_eq!(client_metrics.written.load(Ordering::SeqCst), 5);
+    assert_eq!(server_metrics.read.load(Ordering::SeqCst), 5);
+
+    drop(read);
+    assert_eq!(server_metrics.active.load(Ordering::SeqCst), 1);
+    drop(write);
+    assert_eq!(server_metrics.active.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn reconnecting_connection() {
+    use tipsy::{ConnectionState, ReconnectPolicy, ReconnectingConnection};
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let policy = ReconnectPolicy::new()
+        .initial_interval(Duration::from_millis(10))
+        .backoff(Backoff::Fixed);
+    let mut client =
+        ReconnectingConnection::connect(path.clone(), ConnectOptions::default(), policy)
+            .await
+            .unwrap();
+    let mut state = client.subscribe();
+    let ConnectionState::Connected(first_id) = *state.borrow_and_update() else {
+        panic!("expected to be connected");
+    };
+
+    let server = tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().unwrap();
+        conn.write_all(b"one").await.unwrap();
+        drop(conn);
+        drop(incoming);
+
+        // Restart the server at the same path
+        let endpoint = Endpoint::new(path, OnConflict::Overwrite).unwrap();
+        let mut incoming = endpoint.incoming().unwrap();
+        let mut conn = incoming.next().await.unwrap().unwrap();
+        conn.write_all(b"two").await.unwrap();
+    });
+
+    let mut buf = [0; 3];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"one");
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"two");
+    server.await.unwrap();
+
+    let ConnectionState::Connected(second_id) = *state.borrow_and_update() else {
+        panic!("expected to be reconnected");
+    };
+    assert_ne!(first_id, second_id);
+}
+
+#[tokio::test]
+async fn reconnecting_connection_gives_up() {
+    use tipsy::{ConnectionState, ReconnectPolicy, ReconnectingConnection};
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let policy = ReconnectPolicy::new()
+        .initial_interval(Duration::from_millis(10))
+        .backoff(Backoff::Fixed)
+        .max_attempts(Some(2));
+    let mut client =
+        ReconnectingConnection::connect(path.clone(), ConnectOptions::default(), policy)
+            .await
+            .unwrap();
+    let state = client.subscribe();
+
+    // The server stops for good
+    drop(incoming.next().await.unwrap().unwrap());
+    drop(incoming);
+    let mut buf = [0; 3];
+    assert!(client.read(&mut buf).await.is_err());
+    assert_eq!(*state.borrow(), ConnectionState::Failed);
+
+    // Later calls fail without trying again
+    let err = client.read(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(*state.borrow(), ConnectionState::Failed);
+
+    // Starting over connects to the restarted server
+    let endpoint = Endpoint::new(path, OnConflict::Overwrite).unwrap();
+    let mut incoming = endpoint.incoming().unwrap();
+    client.reconnect();
+    let server = tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().unwrap();
+        conn.write_all(b"two").await.unwrap();
+    });
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"two");
+    server.await.unwrap();
+    assert!(matches!(*state.borrow(), ConnectionState::Connected(_)));
+}
+
+#[tokio::test]
+async fn connection_pool() {
+    use tipsy::IpcPool;
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    tokio::spawn(async move {
+        while let Some(Ok(conn)) = incoming.next().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = split(conn);
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    let pool = IpcPool::new(path, ConnectOptions::default(), 2)
+        .await
+        .unwrap();
+    assert_eq!(pool.idle(), 2);
+
+    let mut conn = pool.get().await.unwrap();
+    let id = conn.id();
+    conn.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+    assert_eq!(pool.idle(), 1);
+    drop(conn);
+    assert_eq!(pool.idle(), 2);
+
+    // Healthy connections are reused
+    let mut conn = pool.get().await.unwrap();
+    assert_eq!(conn.id(), id);
+
+    // Discarded connections are replaced
+    conn.discard();
+    drop(conn);
+    let first = pool.get().await.unwrap();
+    let second = pool.get().await.unwrap();
+    assert_ne!(first.id(), id);
+    assert_ne!(second.id(), id);
+}
+
+#[tokio::test]
+async fn connection_pool_replaces_closed() {
+    use tipsy::IpcPool;
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let pool = IpcPool::new(path, ConnectOptions::default(), 1)
+        .await
+        .unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let id = pool.get().await.unwrap().id();
+
+    // The server goes away while the connection is idle
+    drop(server);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut conn = pool.get().await.unwrap();
+    assert_ne!(conn.id(), id);
+
+    let mut server = incoming.next().await.unwrap().unwrap();
+    server.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[cfg(feature = "tokio-util")]
+#[tokio::test]
+async fn heartbeat() {
+    use bytes::Bytes;
+    use futures::SinkExt;
+    use tipsy::{Heartbeat, HeartbeatConnection};
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let heartbeat = Heartbeat::new()
+        .interval(Duration::from_millis(20))
+        .max_missed(2);
+
+    // Both ends respond to pings, so the connection stays healthy
+    let mut client =
+        HeartbeatConnection::new(Endpoint::connect(path.clone()).await.unwrap(), heartbeat);
+    let server = incoming.next().await.unwrap().unwrap();
+    tokio::spawn(async move {
+        let mut server = server.into_heartbeat(heartbeat);
+        while let Some(Ok(message)) = server.next().await {
+            server.send(message).await.unwrap();
+        }
+    });
+    client.send(Bytes::from_static(b"hello")).await.unwrap();
+    assert_eq!(
+        client.next().await.unwrap().unwrap(),
+        Bytes::from_static(b"hello")
+    );
+    assert!(
+        tokio::time::timeout(Duration::from_millis(200), client.next())
+            .await
+            .is_err()
+    );
+
+    // The server never answers pings
+    let mut client = HeartbeatConnection::new(Endpoint::connect(path).await.unwrap(), heartbeat);
+    let _server = incoming.next().await.unwrap().unwrap();
+    let err = tokio::time::timeout(Duration::from_secs(1), client.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
+#[cfg(feature = "tokio-util")]
+#[tokio::test]
+async fn heartbeat_zero_interval() {
+    use bytes::Bytes;
+    use futures::SinkExt;
+    use tipsy::{Heartbeat, HeartbeatConnection};
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let heartbeat = Heartbeat::new().interval(Duration::ZERO);
+
+    let mut client = HeartbeatConnection::new(Endpoint::connect(path).await.unwrap(), heartbeat);
+    let mut server = incoming
+        .next()
+        .await
+        .unwrap()
+        .unwrap()
+        .into_heartbeat(heartbeat);
+    client.send(Bytes::from_static(b"hello")).await.unwrap();
+    assert_eq!(
+        server.next().await.unwrap().unwrap(),
+        Bytes::from_static(b"hello")
+    );
+}
+
+#[tokio::test]
+async fn idle_timeout() {
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_idle_timeout(Duration::from_millis(200));
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let (mut reader, _writer) = server.into_split();
+
+    // Regular activity keeps the connection open
+    let mut buf = [0; 1];
+    for _ in 0..4 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        client.write_all(b"x").await.unwrap();
+        reader.read_exact(&mut buf).await.unwrap();
+    }
+
+    let err = reader.read_exact(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
+#[tokio::test]
+async fn accept_rate_limit() {
+    use tipsy::RateLimit;
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_accept_rate_limit(RateLimit::new(5, Duration::from_secs(1)).burst(2));
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    for _ in 0..3 {
+        let path = path.clone();
+        tokio::spawn(async move {
+            let _client = Endpoint::connect(path).await.unwrap();
+            std::future::pending::<()>().await;
+        });
+    }
+
+    let start = tokio::time::Instant::now();
+    incoming.next().await.unwrap().unwrap();
+    incoming.next().await.unwrap().unwrap();
+    assert!(start.elapsed() < Duration::from_millis(150));
+    // The burst is used up, so the next accept waits for a new token
+    incoming.next().await.unwrap().unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[tokio::test]
+async fn buffer_sizes() {
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_in_buffer_size(4096);
+    endpoint.set_out_buffer_size(4096);
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let options = ConnectOptions::new()
+        .in_buffer_size(4096)
+        .out_buffer_size(4096);
+    let mut client = Endpoint::connect_with_options(path, options).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    let data = vec![7u8; 100_000];
+    let expected = data.clone();
+    let write = tokio::spawn(async move { client.write_all(&data).await.unwrap() });
+    let mut buf = vec![0u8; expected.len()];
+    server.read_exact(&mut buf).await.unwrap();
+    write.await.unwrap();
+    assert_eq!(buf, expected);
+}
+
+#[test]
+fn blocking() {
+    use std::io::{Read, Write};
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming_blocking().unwrap();
+
+    let server = std::thread::spawn(move || {
+        for _ in 0..2 {
+            let mut conn = incoming.next().unwrap().unwrap();
+            let mut buf = [0u8; 4];
+            conn.read_exact(&mut buf).unwrap();
+            conn.write_all(&buf).unwrap();
+        }
+    });
+
+    for _ in 0..2 {
+        let mut client = Endpoint::connect_blocking(path.clone()).unwrap();
+        client.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+    server.join().unwrap();
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_token_auth(TokenAuth::new("secret"));
+    assert!(matches!(
+        endpoint.incoming_blocking(),
+        Err(e) if e.kind() == io::ErrorKind::Unsupported
+    ));
+}
+
+#[tokio::test]
+async fn splice_to() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut source = Endpoint::connect(path.clone()).await.unwrap();
+    let mut from = incoming.next().await.unwrap().unwrap();
+    let mut sink = Endpoint::connect(path).await.unwrap();
+    let mut to = incoming.next().await.unwrap().unwrap();
+
+    let data: Vec<u8> = (0..300_000u32).map(|i| i as u8).collect();
+    let expected = data.clone();
+    let writer = tokio::spawn(async move {
+        source.write_all(&data).await.unwrap();
+        // Closing the source ends the splice early
+    });
+    let reader = tokio::spawn(async move {
+        let mut received = Vec::new();
+        sink.read_to_end(&mut received).await.unwrap();
+        received
+    });
+
+    let forwarded = from.splice_to(&mut to, 1_000_000).await.unwrap();
+    writer.await.unwrap();
+    assert_eq!(forwarded, expected.len());
+    assert_eq!(from.stats().bytes_read(), expected.len() as u64);
+    assert_eq!(to.stats().bytes_written(), expected.len() as u64);
+    drop(to);
+    assert_eq!(reader.await.unwrap(), expected);
+}
+
+#[cfg(feature = "bytes")]
+#[tokio::test]
+async fn read_buf() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    let mut buf = bytes::BytesMut::from(&b"existing "[..]);
+    client.write_all(b"data").await.unwrap();
+    let mut read = 0;
+    while read < 4 {
+        read += server.read_buf(&mut buf).await.unwrap();
+    }
+    assert_eq!(&buf[..], b"existing data");
+
+    let mut full = [0u8; 0];
+    assert_eq!(server.read_buf(&mut &mut full[..]).await.unwrap(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn as_fd() {
+    use std::io::Write;
+    use std::os::fd::{AsFd, AsRawFd};
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    assert_eq!(client.as_fd().as_raw_fd(), client.as_raw_fd());
+
+    // Write through a duplicate of the underlying socket
+    let fd = client.as_fd().try_clone_to_owned().unwrap();
+    let mut raw = std::os::unix::net::UnixStream::from(fd);
+    raw.write_all(b"hello").unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn into_inner() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    #[cfg(unix)]
+    let (mut client, mut server) = (client.into_inner(), server.into_inner());
+    #[cfg(windows)]
+    let (mut client, mut server) = match (client.into_inner(), server.into_inner()) {
+        (tipsy::NamedPipe::Client(client), tipsy::NamedPipe::Server(server)) => (client, server),
+        _ => panic!("unexpected pipe types"),
+    };
+
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn impersonation() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    // The server needs to read from the pipe before it can impersonate
+    client.write_all(b"x").await.unwrap();
+    let mut buf = [0u8; 1];
+    server.read_exact(&mut buf).await.unwrap();
+
+    let guard = server.impersonate().unwrap();
+    drop(guard);
+    assert!(matches!(
+        client.impersonate(),
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput
+    ));
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn max_instances() {
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_max_instances(2);
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client1 = Endpoint::connect(path.clone()).await.unwrap();
+    let server1 = incoming.next().await.unwrap().unwrap();
+    let _client2 = Endpoint::connect(path.clone()).await.unwrap();
+    let _server2 = incoming.next().await.unwrap().unwrap();
+
+    let options = ConnectOptions::new().busy_timeout(Duration::from_millis(100));
+    let err = Endpoint::connect_with_options(path.clone(), options)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(err.raw_os_error(), Some(231));
+
+    // Closing a connection frees up an instance for the next client
+    drop(client1);
+    drop(server1);
+    let accept = tokio::spawn(async move { incoming.next().await.unwrap().unwrap() });
+    let _client3 = Endpoint::connect(path).await.unwrap();
+    accept.await.unwrap();
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn pipe_info() {
+    use tipsy::PipeMode;
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_pipe_mode(PipeMode::Message);
+    endpoint.set_max_instances(4);
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    let info = server.pipe_info().unwrap();
+    assert!(info.is_server());
+    assert_eq!(info.pipe_mode(), PipeMode::Message);
+    assert_eq!(info.read_mode(), PipeMode::Message);
+    assert_eq!(info.max_instances(), Some(4));
+    assert!(info.current_instances() >= 1);
+
+    // The client didn't ask for message mode, so it reads a stream of bytes
+    let info = client.pipe_info().unwrap();
+    assert!(!info.is_server());
+    assert_eq!(info.pipe_mode(), PipeMode::Message);
+    assert_eq!(info.read_mode(), PipeMode::Byte);
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn reuse_instances() {
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_max_instances(1);
+    endpoint.set_reuse_instances(true);
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    for _ in 0..3 {
+        let accept = tokio::spawn(async move {
+            let mut server = incoming.next().await.unwrap().unwrap();
+            let mut buf = [0; 5];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(server.pipe_info().unwrap().current_instances(), 1);
+            (incoming, buf)
+        });
+        // Only one instance can exist, so each client gets the instance used by the last one
+        let mut client = Endpoint::connect(path.clone()).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        let buf;
+        (incoming, buf) = accept.await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn standby_instances() {
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_standby_instances(4);
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    // All of the clients can connect before the server accepts any of them
+    let options = ConnectOptions::new().busy_timeout(Duration::ZERO);
+    let mut clients = Vec::new();
+    for _ in 0..4 {
+        clients.push(
+            Endpoint::connect_with_options(path.clone(), options.clone())
+                .await
+                .unwrap(),
+        );
+    }
+    for _ in 0..4 {
+        incoming.next().await.unwrap().unwrap();
+    }
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn join_existing_pipe() {
+    let path = dummy_endpoint("test");
+    let first = Endpoint::new(path.clone(), OnConflict::Error)
+        .unwrap()
+        .incoming()
+        .unwrap();
+
+    let err = Endpoint::new(path.clone(), OnConflict::Error)
+        .unwrap()
+        .incoming()
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    assert!(err.to_string().contains(&std::process::id().to_string()));
+
+    let mut endpoint = Endpoint::new(path.clone(), OnConflict::Error).unwrap();
+    endpoint.set_join_existing(true);
+    let mut second = endpoint.incoming().unwrap();
+
+    // Once the first server goes away, clients are handled by the second one
+    drop(first);
+    let accept = tokio::spawn(async move { second.next().await.unwrap().unwrap() });
+    let _client = Endpoint::connect(path.into_ipc_path().unwrap())
+        .await
+        .unwrap();
+    accept.await.unwrap();
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn accept_remote_clients() {
+    let path = dummy_endpoint("test");
+    let mut endpoint = Endpoint::new(path.clone(), OnConflict::Error).unwrap();
+    endpoint.set_reject_remote_clients(false);
+    let mut incoming = endpoint.incoming().unwrap();
+    // Local clients are still accepted
+    let _client = Endpoint::connect(path).await.unwrap();
+    incoming.next().await.unwrap().unwrap();
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn write_through() {
+    let path = dummy_endpoint("test");
+    let mut endpoint = Endpoint::new(path.clone(), OnConflict::Error).unwrap();
+    endpoint.set_write_through(true);
+    let mut incoming = endpoint.incoming().unwrap();
+    let server = tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().unwrap();
+        let mut buf = [0; 5];
+        conn.read_exact(&mut buf).await.unwrap();
+        conn.write_all(&buf).await.unwrap();
+    });
+
+    let mut client = Endpoint::connect_with_options(
+        path.into_ipc_path().unwrap(),
+        ConnectOptions::new().write_through(true),
+    )
+    .await
+    .unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn probe_endpoint() {
+    use tipsy::EndpointStatus;
+
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    assert_eq!(
+        Endpoint::probe(path.clone()).unwrap(),
+        EndpointStatus::NotFound
+    );
+    assert!(!Endpoint::is_listening(path.clone()));
+
+    let endpoint = Endpoint::new(path.clone(), OnConflict::Overwrite).unwrap();
+    let incoming = endpoint.incoming().unwrap();
+    assert_eq!(
+        Endpoint::probe(path.clone()).unwrap(),
+        EndpointStatus::Listening
+    );
+    assert!(Endpoint::is_listening(path.clone()));
+    drop(incoming);
+
+    assert_eq!(Endpoint::probe(path).unwrap(), EndpointStatus::NotFound);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn probe_stale_socket() {
+    use tipsy::EndpointStatus;
+
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert_eq!(
+        Endpoint::probe(path.clone()).unwrap(),
+        EndpointStatus::Stale
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn service_id() {
+    use tipsy::ServiceId;
+
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let id = ServiceId::new(format!("app-{num}"), "control").instance("1");
+    assert_eq!(id.name(), format!("app-{num}.control.1"));
+    // Separators inside components can't cause collisions
+    assert_ne!(
+        ServiceId::new("a.b", "c").into_ipc_path().unwrap(),
+        ServiceId::new("a", "b.c").into_ipc_path().unwrap()
+    );
+    assert_ne!(
+        ServiceId::new("a", "b").instance("c").name(),
+        ServiceId::new("a", "b.c").name()
+    );
+
+    let mut incoming = Endpoint::new(id.clone(), OnConflict::Overwrite)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    let _client = Endpoint::connect(id).await.unwrap();
+    incoming.next().await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn scoped_service_ids() {
+    use tipsy::ServiceId;
+
+    let global = ServiceId::new("app", "agent");
+    let user = global.clone().per_user().unwrap();
+    assert!(user.name().starts_with("app.agent.user-"));
+    assert_ne!(user.name(), global.name());
+    assert_eq!(
+        ServiceId::new("app", "agent").for_user("1000").name(),
+        "app.agent.user-1000"
+    );
+
+    #[cfg(windows)]
+    {
+        let session = global.per_session().unwrap();
+        assert!(session.name().starts_with("app.agent.session-"));
+        assert_eq!(
+            ServiceId::new("app", "agent").for_session(1).name(),
+            "app.agent.session-1"
+        );
+    }
+}
+
+#[tokio::test]
+async fn path_resolvers() {
+    use std::path::PathBuf;
+
+    use tipsy::DirResolver;
+
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let dir = std::env::temp_dir().join(format!("tipsy-{num}"));
+    let id = ServerId("test").resolve_with(DirResolver::new(&dir));
+    let path = id.clone().into_ipc_path().unwrap();
+    #[cfg(unix)]
+    assert_eq!(path, dir.join("test.sock"));
+
+    let custom = ServerId("test").resolve_with(|name: &str| Ok(PathBuf::from(name)));
+    assert_eq!(custom.into_ipc_path().unwrap(), PathBuf::from("test"));
+
+    let mut incoming = Endpoint::new(id, OnConflict::Overwrite)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    let _client = Endpoint::connect(path.to_str().unwrap()).await.unwrap();
+    incoming.next().await.unwrap().unwrap();
+    let _client = Endpoint::connect(path.clone().into_os_string())
+        .await
+        .unwrap();
+    incoming.next().await.unwrap().unwrap();
+    drop(incoming);
+    #[cfg(unix)]
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn tcp_fallback() {
+    use tipsy::DualConnection;
+
+    let path = dummy_endpoint("test");
+    let err = Endpoint::new(path.clone(), OnConflict::Overwrite)
+        .unwrap()
+        .incoming_with_tcp("0.0.0.0:0".parse().unwrap())
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let mut incoming = Endpoint::new(path.clone(), OnConflict::Overwrite)
+        .unwrap()
+        .incoming_with_tcp("127.0.0.1:0".parse().unwrap())
+        .unwrap();
+    let addr = incoming.local_tcp_addr().unwrap();
+
+    let mut tcp_client = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut conn = incoming.next().await.unwrap().unwrap();
+    assert!(matches!(conn, DualConnection::Tcp(_)));
+    tcp_client.write_all(b"tcp").await.unwrap();
+    let mut buf = [0; 3];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"tcp");
+
+    let mut ipc_client = Endpoint::connect(path).await.unwrap();
+    let mut conn = incoming.next().await.unwrap().unwrap();
+    assert!(matches!(conn, DualConnection::Ipc(_)));
+    ipc_client.write_all(b"ipc").await.unwrap();
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ipc");
+
+    incoming.shutdown_handle().shutdown();
+    assert!(incoming.next().await.is_none());
+}
+
+#[tokio::test]
+async fn cygwin_socket() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let path = std::env::temp_dir().join(format!(
+        "cygwin-{}",
+        rand::Rng::gen::<u64>(&mut rand::thread_rng())
+    ));
+    std::fs::write(
+        &path,
+        format!("!<socket >{port} s 01234567-89ABCDEF-00000001-DEADBEEF\0"),
+    )
+    .unwrap();
+
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut key = [0; 16];
+        stream.read_exact(&mut key).await.unwrap();
+        assert_eq!(&key[..4], &0x01234567u32.to_le_bytes());
+        assert_eq!(&key[12..], &0xDEADBEEFu32.to_le_bytes());
+        stream.write_all(&key).await.unwrap();
+        let mut credentials = [0; 12];
+        stream.read_exact(&mut credentials).await.unwrap();
+        assert_eq!(&credentials[4..8], &1000u32.to_le_bytes());
+        stream.write_all(&credentials).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+    });
+
+    let mut client = Endpoint::connect_cygwin(path.clone(), 1000, 1000)
+        .await
+        .unwrap();
+    let mut buf = [0; 5];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+    server.await.unwrap();
+
+    std::fs::write(&path, "not a socket").unwrap();
+    let err = Endpoint::connect_cygwin(path.clone(), 1000, 1000)
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn mock_transport() {
+    use tipsy::mock;
+
+    let path = dummy_endpoint("mock").into_ipc_path().unwrap();
+    let err = mock::Endpoint::connect(path.clone()).await.err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    let mut incoming = mock::Endpoint::new(path.clone(), OnConflict::Error)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    assert!(!path.exists());
+    let err = mock::Endpoint::new(path.clone(), OnConflict::Error)
+        .unwrap()
+        .incoming()
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+    // Connections are accepted in the order they were made
+    let mut clients = Vec::new();
+    for i in 0..3u8 {
+        let mut client = mock::Endpoint::connect(path.clone()).await.unwrap();
+        client.write_all(&[i]).await.unwrap();
+        clients.push(client);
+    }
+    for i in 0..3u8 {
+        let mut server = incoming.next().await.unwrap().unwrap();
+        assert_eq!(server.read_u8().await.unwrap(), i);
+    }
+
+    drop(incoming);
+    let err = mock::Endpoint::connect(path).await.err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+}
+
+#[tokio::test]
+async fn drain_connections() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let mut incoming = Endpoint::new(path.clone(), OnConflict::Overwrite)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    let handle = incoming.shutdown_handle();
+    let _client = Endpoint::connect(path.clone()).await.unwrap();
+    let conn = incoming.next().await.unwrap().unwrap();
+    let (read, write) = conn.into_split();
+
+    let err = handle.drain(Duration::from_millis(50)).await.err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(incoming.next().await.is_none());
+
+    let drain = tokio::spawn(async move { handle.drain(Duration::from_secs(5)).await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!drain.is_finished());
+    drop(read);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!drain.is_finished());
+    drop(write);
+    drain.await.unwrap().unwrap();
+    #[cfg(unix)]
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn keep_socket_file() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let mut endpoint = Endpoint::new(path.clone(), OnConflict::Overwrite).unwrap();
+    endpoint.set_remove_on_drop(false);
+    let incoming = endpoint.incoming().unwrap();
+    incoming.shutdown_handle().shutdown();
+    drop(incoming);
+    assert!(path.exists());
+
+    let mut endpoint = Endpoint::new(path.clone(), OnConflict::Overwrite).unwrap();
+    endpoint.set_remove_on_drop(false);
+    let incoming = endpoint.incoming().unwrap();
+    incoming.cleanup();
+    assert!(!path.exists());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn peer_security_label() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let mut incoming = Endpoint::new(path.clone(), OnConflict::Overwrite)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    let _client = Endpoint::connect(path).await.unwrap();
+    let conn = incoming.next().await.unwrap().unwrap();
+    // Whether a label is available depends on the security modules enabled on the host
+    match conn.peer_security_label() {
+        Ok(label) => assert!(!label.contains('\0')),
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::Unsupported),
+    }
+}
+
+#[tokio::test]
+async fn one_way_endpoint() {
+    use tipsy::Direction;
+
+    let path = dummy_endpoint("test");
+    let mut endpoint = Endpoint::new(path.clone(), OnConflict::Overwrite).unwrap();
+    endpoint.set_direction(Direction::Inbound);
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect_with_options(
+        path.into_ipc_path().unwrap(),
+        ConnectOptions::new().direction(Direction::Inbound),
+    )
+    .await
+    .unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    client.write_all(b"log").await.unwrap();
+    let mut buf = [0; 3];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"log");
+    assert!(server.write_all(b"reply").await.is_err());
+}
+
+#[test]
+fn invalid_path() {
+    #[cfg(unix)]
+    let path = std::env::temp_dir().join("a".repeat(200));
+    #[cfg(windows)]
+    let path = std::path::PathBuf::from(r"C:\test.sock");
+
+    let err = Endpoint::new(path, OnConflict::Error).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let err = Endpoint::new(std::path::PathBuf::new(), OnConflict::Error)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn connect_retry() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+
+    let err = Endpoint::connect(path.clone()).await.err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    let server_path = path.clone();
+    let server = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let endpoint = Endpoint::new(server_path, OnConflict::Overwrite).unwrap();
+        let mut incoming = endpoint.incoming().unwrap();
+        incoming.next().await.unwrap().unwrap()
+    });
+    let options = ConnectOptions::new()
+        .retry_attempts(20)
+        .poll_interval(Duration::from_millis(10))
+        .backoff(Backoff::Exponential {
+            max: Duration::from_millis(100),
+        });
+    Endpoint::connect_with_options(path, options).await.unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn try_connect() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let err = Endpoint::try_connect(path.clone()).await.err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    let endpoint = Endpoint::new(path.clone(), OnConflict::Overwrite).unwrap();
+    let mut incoming = endpoint.incoming().unwrap();
+    let mut client = Endpoint::try_connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+}
+
+#[tokio::test]
+async fn endpoint_builder() {
+    #[cfg(unix)]
+    use tipsy::os::unix::EndpointBuilderExt;
+    #[cfg(windows)]
+    use tipsy::os::windows::EndpointBuilderExt;
+
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let builder = Endpoint::builder(path.clone(), OnConflict::Overwrite)
+        .in_buffer_size(16 * 1024)
+        .out_buffer_size(16 * 1024);
+    #[cfg(unix)]
+    let builder = builder.backlog(8).remove_on_drop(false);
+    #[cfg(windows)]
+    let builder = builder.max_instances(4);
+    let mut incoming = builder.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path.clone()).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    #[cfg(unix)]
+    {
+        drop(incoming);
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    let err = Endpoint::builder("", OnConflict::Overwrite)
+        .in_buffer_size(1024)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn structured_errors() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let err = Endpoint::connect(path.clone()).await.err().unwrap();
+    assert!(matches!(err, tipsy::Error::Connect { .. }));
+    assert_eq!(err.path(), path);
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(err.to_string().contains(&format!("{path:?}")));
+
+    let io_err = io::Error::from(err);
+    assert_eq!(io_err.kind(), io::ErrorKind::NotFound);
+    assert_eq!(tipsy::Error::from_io(&io_err).unwrap().path(), path);
+
+    let _incoming = Endpoint::new(path.clone(), OnConflict::Overwrite)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    let err = Endpoint::new(path.clone(), OnConflict::Ignore)
+        .unwrap()
+        .incoming()
+        .err()
+        .unwrap();
+    assert!(matches!(err, tipsy::Error::Bind { .. }));
+    assert_eq!(err.path(), path);
+    assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+    let err = Endpoint::builder(path.clone(), OnConflict::Ignore)
+        .incoming()
+        .err()
+        .unwrap();
+    assert!(matches!(err, tipsy::Error::Bind { .. }));
+    assert_eq!(err.path(), path);
+    assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+    // Pipes don't exist as files on Windows, so only Unix checks for them up front
+    #[cfg(unix)]
+    {
+        let err = Endpoint::builder(path.clone(), OnConflict::Error)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, tipsy::Error::Bind { .. }));
+        assert_eq!(err.path(), path);
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+}
+
+#[tokio::test]
+async fn shorten_long_paths() {
+    use tipsy::{DefaultResolver, ShortenResolver};
+
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let name = path.file_stem().unwrap().to_str().unwrap().to_owned();
+    let short = ServerId(name.as_str())
+        .resolve_with(ShortenResolver::new(DefaultResolver))
+        .into_ipc_path()
+        .unwrap();
+    assert_eq!(short, path);
+
+    let long_name = format!("{name}-{}", "x".repeat(300));
+    let id = ServerId(long_name.as_str()).resolve_with(ShortenResolver::new(DefaultResolver));
+    let path = id.clone().into_ipc_path().unwrap();
+    assert_eq!(path, id.clone().into_ipc_path().unwrap());
+    assert!(path.as_os_str().len() < 100);
+    assert!(Endpoint::new(ServerId(long_name.as_str()), OnConflict::Overwrite).is_err());
+
+    let mut incoming = Endpoint::new(id.clone(), OnConflict::Overwrite)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    let _client = Endpoint::connect(id).await.unwrap();
+    incoming.next().await.unwrap().unwrap();
+}
+
+#[cfg(feature = "tokio-serde")]
+#[tokio::test]
+async fn serde_framed() {
+    use futures::SinkExt;
+    use tipsy::tokio_serde::formats::Json;
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Ping {
+        seq: u32,
+    }
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    let mut client = client.serde_framed(Json::<Ping, Ping>::default());
+    let mut server = server.serde_framed(Json::<Ping, Ping>::default());
+    client.send(Ping { seq: 1 }).await.unwrap();
+    let ping = server.next().await.unwrap().unwrap();
+    assert_eq!(ping, Ping { seq: 1 });
+    server.send(Ping { seq: ping.seq + 1 }).await.unwrap();
+    assert_eq!(client.next().await.unwrap().unwrap(), Ping { seq: 2 });
+}
+
+#[cfg(feature = "tokio-util")]
+#[tokio::test]
+async fn framed_codec() {
+    use futures::SinkExt;
+    use tipsy::codec::LinesCodec;
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    let mut client = client.framed(LinesCodec::new());
+    let mut server = server.framed(LinesCodec::new());
+    client.send("hello").await.unwrap();
+    client.send("world").await.unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), "hello");
+    assert_eq!(server.next().await.unwrap().unwrap(), "world");
+
+    let server = server.into_inner();
+    drop(server);
+    assert!(client.next().await.is_none());
+}
+
+#[cfg(feature = "tokio-util")]
+#[tokio::test]
+async fn max_frame_length() {
+    use bytes::Bytes;
+    use futures::SinkExt;
+    use tipsy::{FrameTooLarge, MessageConnection};
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let mut server = MessageConnection::with_max_frame_length(server, 16);
+
+    // A forged length prefix is rejected before the payload arrives
+    client.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+    let err = server.next().await.unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let too_large = err.get_ref().unwrap().downcast_ref::<FrameTooLarge>();
+    assert_eq!(too_large.unwrap().max_frame_length(), 16);
+
+    let err = server.send(Bytes::from_static(&[0; 17])).await.unwrap_err();
+    assert!(err.get_ref().unwrap().is::<FrameTooLarge>());
+    server.send(Bytes::from_static(&[0; 16])).await.unwrap();
+}
+
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+#[tokio::test]
+async fn compressed_connection() {
+    use bytes::Bytes;
+    use futures::SinkExt;
+    use tipsy::{CompressedConnection, Compression};
+
+    async fn connect(
+        client_algorithms: &[Compression],
+        server_algorithms: Vec<Compression>,
+    ) -> (CompressedConnection, CompressedConnection) {
+        let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+        let path = endpoint.path().to_path_buf();
+        let mut incoming = endpoint.incoming().unwrap();
+        let client = Endpoint::connect(path).await.unwrap();
+        let server = incoming.next().await.unwrap().unwrap();
+        let server = tokio::spawn(async move {
+            CompressedConnection::negotiate(server, &server_algorithms)
+                .await
+                .unwrap()
+        });
+        let client = CompressedConnection::negotiate(client, client_algorithms)
+            .await
+            .unwrap();
+        (client, server.await.unwrap())
+    }
+
+    #[cfg(feature = "zstd")]
+    let algorithm = Compression::Zstd;
+    #[cfg(not(feature = "zstd"))]
+    let algorithm = Compression::Lz4;
+    let (mut client, mut server) = connect(&[algorithm], vec![algorithm]).await;
+    assert_eq!(client.compression(), algorithm);
+    assert_eq!(server.compression(), algorithm);
+
+    let large = Bytes::from("hello ".repeat(10_000));
+    client.send(large.clone()).await.unwrap();
+    client.send(Bytes::from_static(b"small")).await.unwrap();
+    assert_eq!(server.next().await.unwrap().unwrap(), large);
+    assert_eq!(server.next().await.unwrap().unwrap(), "small");
+
+    // Falls back to sending uncompressed messages if there's nothing in common
+    let (mut client, mut server) = connect(&[algorithm], Vec::new()).await;
+    assert_eq!(client.compression(), Compression::None);
+    assert_eq!(server.compression(), Compression::None);
+    server.send(large.clone()).await.unwrap();
+    assert_eq!(client.next().await.unwrap().unwrap(), large);
+}
+
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+#[tokio::test]
+async fn compressed_connection_max_frame_length() {
+    use bytes::Bytes;
+    use futures::SinkExt;
+    use tipsy::{CompressedConnection, Compression};
+
+    #[cfg(feature = "zstd")]
+    let algorithm = Compression::Zstd;
+    #[cfg(not(feature = "zstd"))]
+    let algorithm = Compression::Lz4;
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let server = tokio::spawn(async move {
+        CompressedConnection::negotiate_with_max_frame_length(server, &[algorithm], 1024)
+            .await
+            .unwrap()
+    });
+    let mut client = CompressedConnection::negotiate(client, &[algorithm])
+        .await
+        .unwrap();
+    let mut server = server.await.unwrap();
+
+    // Compresses to well under the limit, but expands to more than it
+    client.send(Bytes::from("a".repeat(4096))).await.unwrap();
+    let err = server.next().await.unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn serve_connections() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let active = Arc::new(AtomicUsize::new(0));
+    let max_active = Arc::new(AtomicUsize::new(0));
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+    let serve = endpoint
+        .serve({
+            let active = active.clone();
+            let max_active = max_active.clone();
+            move |mut conn| {
+                let active = active.clone();
+                let max_active = max_active.clone();
+                async move {
+                    let count = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_active.fetch_max(count, Ordering::SeqCst);
+                    let mut buf = [0; 1];
+                    conn.read_exact(&mut buf).await.unwrap();
+                    conn.write_all(&buf).await.unwrap();
+                    // Wait for the client to disconnect
+                    let _ = conn.read(&mut buf).await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+        })
+        .max_connections(1)
+        .with_graceful_shutdown(async move {
+            shutdown_rx.await.ok();
+        });
+    let server = tokio::spawn(async move { serve.await });
+
+    let clients = (0..3u8).map(|i| {
+        let path = path.clone();
+        tokio::spawn(async move {
+            let options = ConnectOptions::new().retry_attempts(10);
+            let mut client = Endpoint::connect_with_options(path, options).await.unwrap();
+            client.write_all(&[i]).await.unwrap();
+            let mut buf = [0; 1];
+            client.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, [i]);
+        })
+    });
+    for client in futures::future::join_all(clients).await {
+        client.unwrap();
+    }
+
+    shutdown_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+    assert_eq!(max_active.load(Ordering::SeqCst), 1);
+    assert_eq!(active.load(Ordering::SeqCst), 0);
+    assert!(!Endpoint::is_listening(path));
+}
+
+#[tokio::test]
+async fn connection_stats() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let stats = client.stats();
+    assert_eq!(stats.bytes_read(), 0);
+    assert_eq!(stats.bytes_written(), 0);
+    assert_eq!(stats.last_activity(), stats.created_at());
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    client.write_all(b"hello").await.unwrap();
+    let (mut read, mut write) = server.into_split();
+    let mut buf = [0; 5];
+    read.read_exact(&mut buf).await.unwrap();
+    write.write_all(b"hi").await.unwrap();
+
+    let stats = read.stats();
+    assert_eq!(stats.bytes_read(), 5);
+    assert_eq!(stats.bytes_written(), 2);
+    assert!(stats.last_activity() > stats.created_at());
+
+    let stats = client.stats();
+    assert_eq!(stats.bytes_written(), 5);
+    assert!(stats.idle_time() < stats.created_at().elapsed().unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn autobind() {
+    let mut incoming = IpcStream::autobind().unwrap();
+    let name = incoming.abstract_name().unwrap();
+    assert!(!name.is_empty());
+    assert_ne!(
+        IpcStream::autobind().unwrap().abstract_name().unwrap(),
+        name
+    );
+
+    let mut client = Endpoint::connect_abstract(&name).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"reply").await.unwrap();
+    let mut buf = [0; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"reply");
+
+    incoming.shutdown_handle().shutdown();
+    assert!(incoming.next().await.is_none());
+    assert!(incoming.abstract_name().is_none());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn lock_file_single_server() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let mut lock_path = path.clone().into_os_string();
+    lock_path.push(".lock");
+
+    let mut endpoint = Endpoint::new(path.clone(), OnConflict::Overwrite).unwrap();
+    endpoint.set_lock_file(true);
+    let incoming = endpoint.incoming().unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&lock_path).unwrap(),
+        std::process::id().to_string()
+    );
+
+    let mut endpoint = Endpoint::new(path.clone(), OnConflict::Overwrite).unwrap();
+    endpoint.set_lock_file(true);
+    let err = endpoint.incoming().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    assert!(err
+        .to_string()
+        .contains(&format!("pid {}", std::process::id())));
+
+    // The first server's socket wasn't replaced
+    tokio::spawn(run_stream(incoming));
+    let mut conn = Endpoint::connect(path.clone()).await.unwrap();
+    conn.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+    drop(conn);
+
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let mut endpoint = Endpoint::new(path.clone(), OnConflict::Overwrite).unwrap();
+    endpoint.set_lock_file(true);
+    drop(endpoint.incoming().unwrap());
+    // The lock is released once the server is dropped
+    let mut endpoint = Endpoint::new(path, OnConflict::Overwrite).unwrap();
+    endpoint.set_lock_file(true);
+    assert!(endpoint.incoming().is_ok());
+}
+
+#[tokio::test]
+async fn pid_file() {
+    let dir = std::env::temp_dir().join(format!("tipsy-pid-{}", std::process::id()));
+    let pid_file = dir.join("server.pid");
+    let expected = format!("{}\n", std::process::id());
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_pid_file(&pid_file);
+    let incoming = endpoint.incoming().unwrap();
+    assert_eq!(std::fs::read_to_string(&pid_file).unwrap(), expected);
+    incoming.shutdown_handle().shutdown();
+    assert!(!pid_file.exists());
+
+    let incoming = Endpoint::builder(dummy_endpoint("test"), OnConflict::Overwrite)
+        .pid_file(&pid_file)
+        .incoming()
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&pid_file).unwrap(), expected);
+    drop(incoming);
+    assert!(!pid_file.exists());
+
+    let _ = std::fs::remove_dir(dir);
+}
+
+#[tokio::test]
+async fn wait_ready() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let err = Endpoint::wait_ready(path.clone(), Duration::from_millis(100))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    let server_path = path.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let mut incoming = Endpoint::new(server_path, OnConflict::Overwrite)
+            .unwrap()
+            .incoming()
+            .unwrap();
+        while let Some(Ok(mut conn)) = incoming.next().await {
+            // The connection used to check if the server is ready closes without sending anything
+            let mut buf = [0; 5];
+            if conn.read_exact(&mut buf).await.is_ok() {
+                conn.write_all(&buf).await.unwrap();
+            }
+        }
+    });
+    Endpoint::wait_ready(path.clone(), Duration::from_secs(5))
+        .await
+        .unwrap();
+    let mut conn = Endpoint::connect(path).await.unwrap();
+    conn.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn ready_notification() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let endpoint = Endpoint::builder(path.clone(), OnConflict::Overwrite)
+        .on_ready(move || {
+            let _ = ready_tx.send(());
+        })
+        .build()
+        .unwrap();
+    tokio::spawn(run_server(endpoint));
+
+    ready_rx.await.unwrap();
+    let mut conn = Endpoint::connect(path).await.unwrap();
+    conn.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn peek() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let mut incoming = Endpoint::new(path.clone(), OnConflict::Overwrite)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    client.write_all(b"GET /").await.unwrap();
+    let mut buf = [0; 3];
+    assert_eq!(server.peek(&mut buf).await.unwrap(), 3);
+    assert_eq!(&buf, b"GET");
+    // Peeking doesn't consume the data
+    let mut buf = [0; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"GET /");
+
+    drop(client);
+    assert_eq!(server.peek(&mut buf).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn readiness() {
+    use tokio::io::Interest;
+
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let mut incoming = Endpoint::new(path.clone(), OnConflict::Overwrite)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    client.writable().await.unwrap();
+    let ready = client.ready(Interest::WRITABLE).await.unwrap();
+    assert!(ready.is_writable());
+
+    client.write_all(b"hello").await.unwrap();
+    server.readable().await.unwrap();
+    let ready = server.ready(Interest::READABLE).await.unwrap();
+    assert!(ready.is_readable());
+    let mut buf = [0; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn try_read_write() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let mut incoming = Endpoint::new(path.clone(), OnConflict::Overwrite)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    let mut client = Endpoint::connect(path).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    let mut buf = [0; 5];
+    assert_eq!(
+        server.try_read(&mut buf).unwrap_err().kind(),
+        io::ErrorKind::WouldBlock
+    );
+
+    let mut written = 0;
+    while written < 5 {
+        client.writable().await.unwrap();
+        match client.try_write(&b"hello"[written..]) {
+            Ok(len) => written += len,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+    let mut read = 0;
+    while read < 5 {
+        server.readable().await.unwrap();
+        match server.try_read(&mut buf[read..]) {
+            Ok(len) => read += len,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => panic!("{e:?}"),
+        }
+    }
+    assert_eq!(&buf, b"hello");
+    assert_eq!(server.stats().bytes_read(), 5);
+    assert_eq!(client.stats().bytes_written(), 5);
+}
+
+#[tokio::test]
+async fn buffered_connection() {
+    use tokio::io::AsyncBufReadExt;
+
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let mut incoming = Endpoint::new(path.clone(), OnConflict::Overwrite)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    let mut client = Endpoint::connect(path).await.unwrap().buffered(1024, 1024);
+    let mut server = incoming.next().await.unwrap().unwrap().buffered(1024, 1024);
+
+    for word in ["hello", " ", "world", "\n"] {
+        client.write_all(word.as_bytes()).await.unwrap();
+    }
+    client.flush().await.unwrap();
+    let mut line = String::new();
+    server.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "hello world\n");
+    assert_eq!(server.get_ref().stats().bytes_read(), 12);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn allow_user() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let file = endpoint.path().with_extension("owner");
+    std::fs::write(&file, b"").unwrap();
+    let metadata = std::fs::metadata(&file).unwrap();
+    std::fs::remove_file(&file).unwrap();
+
+    endpoint.set_security_attributes(
+        SecurityAttributes::empty()
+            .allow_user(metadata.uid(), Access::ReadWrite)
+            .unwrap()
+            .allow_group(metadata.gid(), Access::Write)
+            .unwrap(),
+    );
+    let path = endpoint.path().to_path_buf();
+    let _incoming = endpoint.incoming().unwrap();
+    let socket = std::fs::metadata(&path).unwrap();
+    assert_eq!(socket.uid(), metadata.uid());
+    assert_eq!(socket.gid(), metadata.gid());
+    assert_eq!(socket.permissions().mode() & 0o777, 0o620);
+    assert!(Endpoint::connect(path).await.is_ok());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn permissions_applied_before_bind() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_security_attributes(SecurityAttributes::empty().set_mode(0o640).unwrap());
+    let path = endpoint.path().to_path_buf();
+    let _incoming = endpoint.incoming().unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+    assert!(Endpoint::connect(path.clone()).await.is_ok());
+
+    // The private directory used while binding is cleaned up
+    let pid = std::process::id();
+    let leftover = std::fs::read_dir(path.parent().unwrap())
+        .unwrap()
+        .filter_map(Result::ok)
+        .any(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(&format!(".tipsy-{pid}-"))
+        });
+    assert!(!leftover);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn permissions_with_long_path() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    // Leaves no room for the private directory that's normally used to change the owner
+    let temp = std::env::temp_dir();
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let name_len = 100 - temp.as_os_str().len() - 1;
+    let dir = temp.join(format!("{num:d<name_len$}"));
+    std::fs::create_dir(&dir).unwrap();
+    let path = dir.join("t.sock");
+    let uid = std::fs::metadata(&dir).unwrap().uid();
+
+    let mut endpoint = Endpoint::new(path.clone(), OnConflict::Overwrite).unwrap();
+    endpoint.set_security_attributes(
+        SecurityAttributes::empty()
+            .set_owner(uid)
+            .unwrap()
+            .set_mode(0o640)
+            .unwrap(),
+    );
+    let incoming = endpoint.incoming().unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+    assert!(Endpoint::connect(path.clone()).await.is_ok());
+
+    drop(incoming);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn stale_private_dirs_removed() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Replace).unwrap();
+    let path = endpoint.path().to_path_buf();
+    // Left behind by a process that no longer exists
+    let stale = path
+        .parent()
+        .unwrap()
+        .join(format!(".tipsy-{}-0", i32::MAX));
+    std::fs::create_dir_all(&stale).unwrap();
+
+    let _incoming = endpoint.incoming().unwrap();
+    assert!(!stale.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn replace_socket() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let old = endpoint.incoming().unwrap();
+
+    let endpoint = Endpoint::new(path.clone(), OnConflict::Replace).unwrap();
+    let mut new = endpoint.incoming().unwrap();
+    // The old server doesn't remove the socket that replaced it
+    drop(old);
+    assert!(path.exists());
+
+    let mut client = Endpoint::connect(path.clone()).await.unwrap();
+    let mut server = new.next().await.unwrap().unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    drop(new);
+    assert!(!path.exists());
+}
+
+// Runs in the child process started by `hand_off_listener` and does nothing otherwise
+#[cfg(unix)]
+#[test]
+fn hand_off_listener_child() {
+    // Has to happen before the runtime starts any threads
+    let Some(endpoint) = Endpoint::from_inherited().unwrap() else {
+        return;
+    };
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut incoming = endpoint.incoming().unwrap();
+        let mut conn = incoming.next().await.unwrap().unwrap();
+        conn.write_all(b"child").await.unwrap();
+    });
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn hand_off_listener() {
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_lock_file(true);
+    let path = endpoint.path().to_path_buf();
+    let incoming = endpoint.incoming().unwrap();
+
+    // A failed spawn leaves the listener in charge of the socket file
+    let mut missing = std::process::Command::new(path.with_extension("missing"));
+    assert!(incoming.hand_off(&mut missing).is_err());
+
+    let mut command = std::process::Command::new(std::env::current_exe().unwrap());
+    command
+        .args(["hand_off_listener_child", "--exact"])
+        .stdout(std::process::Stdio::null());
+    let mut child = incoming.hand_off(&mut command).unwrap();
+    // The child owns the socket file and the lock now
+    drop(incoming);
+    assert!(path.exists());
+    let mut other = Endpoint::new(path.clone(), OnConflict::Overwrite).unwrap();
+    other.set_lock_file(true);
+    let err = other.incoming().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+    let mut client = Endpoint::connect(path.clone()).await.unwrap();
+    let mut buf = [0u8; 5];
+    tokio::time::timeout(Duration::from_secs(10), client.read_exact(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf, b"child");
+    assert!(child.wait().unwrap().success());
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn shared_listener() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let listener = std::sync::Arc::new(endpoint.listen().unwrap());
+
+    let workers: Vec<_> = (0..2)
+        .map(|_| {
+            let listener = listener.clone();
+            tokio::spawn(async move {
+                let mut accepted = 0;
+                while let Some(conn) = listener.accept().await {
+                    let mut conn = conn.unwrap();
+                    conn.write_all(b"hello").await.unwrap();
+                    accepted += 1;
+                }
+                accepted
+            })
+        })
+        .collect();
+
+    for _ in 0..4 {
+        let mut client = Endpoint::connect(path.clone()).await.unwrap();
+        let mut buf = [0u8; 5];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    listener.shutdown_handle().shutdown();
+    let mut total = 0;
+    for worker in workers {
+        total += worker.await.unwrap();
+    }
+    assert_eq!(total, 4);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn into_listener() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_security_attributes(SecurityAttributes::empty().set_mode(0o640).unwrap());
+    let path = endpoint.path().to_path_buf();
+    let listener = endpoint.into_listener().unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+
+    let mut client = Endpoint::connect(path.clone()).await.unwrap();
+    let (mut server, _) = listener.accept().await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    // The socket file is left for the caller to clean up
+    drop(listener);
+    assert!(path.exists());
+    std::fs::remove_file(&path).unwrap();
+
+    let mut endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    endpoint.set_token_auth(TokenAuth::new("secret"));
+    let err = endpoint.into_listener().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn into_listener() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), OnConflict::Overwrite).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut listener = endpoint.into_listener().unwrap();
+    let mut server = listener.create_instance().unwrap();
+
+    let mut client = Endpoint::connect(path).await.unwrap();
+    server.connect().await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+    assert!(listener.create_instance().is_ok());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn from_tokio_listener() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let listener = tokio::net::UnixListener::bind(&path).unwrap();
+    let mut incoming = IpcStream::from_tokio_listener(listener, Some(path.clone()));
+
+    let mut client = Endpoint::connect(path.clone()).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    // The registered path is cleaned up like an endpoint's socket
+    drop(incoming);
+    assert!(!path.exists());
+}
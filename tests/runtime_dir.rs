@@ -0,0 +1,28 @@
+// Kept separate from the other integration tests because changing the environment would affect
+// the paths of tests running in parallel in the same process.
+#![cfg(unix)]
+
+use futures::StreamExt;
+use tipsy::{Endpoint, IntoIpcPath, OnConflict, ServerId, RUNTIME_DIR_ENV};
+
+#[tokio::test]
+async fn runtime_dir_override() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let dir = std::env::temp_dir().join(format!("tipsy-{num}"));
+    std::env::set_var(RUNTIME_DIR_ENV, &dir);
+
+    let path = ServerId("test").into_ipc_path().unwrap();
+    assert_eq!(path, dir.join("test.sock"));
+
+    let mut incoming = Endpoint::new(ServerId("test"), OnConflict::Overwrite)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    let _client = Endpoint::connect(ServerId("test")).await.unwrap();
+    incoming.next().await.unwrap().unwrap();
+    drop(incoming);
+
+    std::env::set_var(RUNTIME_DIR_ENV, "");
+    assert_ne!(ServerId("test").into_ipc_path().unwrap(), path);
+    std::fs::remove_dir_all(dir).unwrap();
+}